@@ -0,0 +1,141 @@
+use std::{hint::black_box, sync::Arc};
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use raytracer::{
+    bvh::{
+        BVH, SplitMethod,
+        linear::LinearBVH,
+        wide::{BVH4, BVH8},
+    },
+    hit::Hittable,
+    material::Lambertian,
+    shapes::{quad::build_box, sphere::Sphere},
+    texture::SolidColor,
+    utils::{
+        color::Color,
+        interval::Interval,
+        random::Random,
+        ray::Ray,
+        vec3::{Point3, Vec3},
+    },
+};
+
+// final_scene 中的地面方块与球堆，固定种子以保证每次构建的场景相同
+struct Scene {
+    boxes: Vec<(Point3, Point3)>,
+    spheres: Vec<(Point3, f64)>,
+}
+
+impl Scene {
+    fn new() -> Scene {
+        Random::seed(0);
+
+        let mut boxes = Vec::new();
+        for i in 0..20 {
+            for j in 0..20 {
+                let w = 100.0;
+                let x0 = -1000.0 + i as f64 * w;
+                let z0 = -1000.0 + j as f64 * w;
+                let y1 = Random::random_range(1.0..101.0);
+                boxes.push((Point3::new(x0, 0.0, z0), Point3::new(x0 + w, y1, z0 + w)));
+            }
+        }
+
+        let offset = Vec3::new(-100.0, 270.0, 395.0);
+        let mut spheres: Vec<(Point3, f64)> = (0..1000)
+            .map(|_| (Point3::random_range(0.0..165.0) + offset, 10.0))
+            .collect();
+        spheres.extend([
+            (Point3::new(400.0, 200.0, 400.0), 100.0),
+            (Point3::new(260.0, 150.0, 45.0), 50.0),
+            (Point3::new(0.0, 150.0, 145.0), 50.0),
+            (Point3::new(360.0, 150.0, 145.0), 70.0),
+            (Point3::new(220.0, 280.0, 300.0), 80.0),
+        ]);
+
+        Scene { boxes, spheres }
+    }
+
+    fn primitives(&self) -> Vec<Box<dyn Hittable>> {
+        let mat = Arc::new(Lambertian::new(Arc::new(SolidColor::new(Color::WHITE))));
+        let mut primitives: Vec<Box<dyn Hittable>> = Vec::new();
+        for &(a, b) in &self.boxes {
+            primitives.extend(build_box(a, b, mat.clone()).objects);
+        }
+        for &(center, radius) in &self.spheres {
+            primitives.push(Box::new(Sphere::new(center, radius, mat.clone())));
+        }
+        primitives
+    }
+
+    // 从 final_scene 的相机位置射向画面内随机方向的光线
+    fn camera_rays(count: usize) -> Vec<Ray> {
+        let look_from = Point3::new(478.0, 278.0, -600.0);
+        (0..count)
+            .map(|_| {
+                let target = Point3::new(
+                    Random::random_range(-300.0..850.0),
+                    Random::random_range(-300.0..850.0),
+                    0.0,
+                );
+                Ray::new(look_from, target - look_from)
+            })
+            .collect()
+    }
+}
+
+fn trace(world: &dyn Hittable, rays: &[Ray]) -> usize {
+    let interval = Interval::from_range(0.001..f64::INFINITY);
+    rays.iter()
+        .filter(|r| world.hit(r, &interval).is_some())
+        .count()
+}
+
+fn bench_build(c: &mut Criterion) {
+    let scene = Scene::new();
+    let mut group = c.benchmark_group("build");
+    group.sample_size(20);
+    group.bench_function("bvh_median", |b| {
+        b.iter(|| BVH::with_method(scene.primitives(), SplitMethod::Median))
+    });
+    group.bench_function("bvh_sah", |b| b.iter(|| BVH::from_vec(scene.primitives())));
+    group.bench_function("linear_sah", |b| {
+        b.iter(|| LinearBVH::from_vec(scene.primitives()))
+    });
+    group.bench_function("bvh4_sah", |b| {
+        b.iter(|| BVH4::from_vec(scene.primitives()))
+    });
+    group.bench_function("bvh8_sah", |b| {
+        b.iter(|| BVH8::from_vec(scene.primitives()))
+    });
+    group.finish();
+}
+
+fn bench_traverse(c: &mut Criterion) {
+    let scene = Scene::new();
+    let rays = Scene::camera_rays(10_000);
+    let median = BVH::with_method(scene.primitives(), SplitMethod::Median);
+    let sah = BVH::from_vec(scene.primitives());
+    let linear = LinearBVH::from_vec(scene.primitives());
+    let bvh4 = BVH4::from_vec(scene.primitives());
+    let bvh8 = BVH8::from_vec(scene.primitives());
+    let hits = trace(&sah, &rays);
+    assert_eq!(trace(&linear, &rays), hits);
+    assert_eq!(trace(&bvh4, &rays), hits);
+    assert_eq!(trace(&bvh8, &rays), hits);
+
+    let mut group = c.benchmark_group("traverse_final_scene");
+    group.bench_function("bvh_median", |b| {
+        b.iter(|| trace(black_box(&median), &rays))
+    });
+    group.bench_function("bvh_sah", |b| b.iter(|| trace(black_box(&sah), &rays)));
+    group.bench_function("linear_sah", |b| {
+        b.iter(|| trace(black_box(&linear), &rays))
+    });
+    group.bench_function("bvh4_sah", |b| b.iter(|| trace(black_box(&bvh4), &rays)));
+    group.bench_function("bvh8_sah", |b| b.iter(|| trace(black_box(&bvh8), &rays)));
+    group.finish();
+}
+
+criterion_group!(benches, bench_build, bench_traverse);
+criterion_main!(benches);
@@ -6,6 +6,11 @@ use crate::utils::{
     vec3::{Point3, Vec3},
 };
 
+// 包围盒测试中远端距离的相对误差界 2γ(3)，按 t + |t|·γ 放宽以抵消计算进出距离时的舍入误差
+// 使经过三角形边与顶点的光线不会错过包围盒（Woop 等人的保守遍历），t 为负时同样向外放宽
+pub const CONSERVATIVE_GAMMA: f64 =
+    2.0 * (3.0 * f64::EPSILON * 0.5) / (1.0 - 3.0 * f64::EPSILON * 0.5);
+
 #[derive(Default, Clone, Copy)]
 pub struct AABB {
     x: Interval,
@@ -70,8 +75,9 @@ impl AABB {
 
                 let t0 = (ax.min() - ray_orig[axis]) * adinv;
                 let t1 = (ax.max() - ray_orig[axis]) * adinv;
+                let (t0, t1) = if t0 <= t1 { (t0, t1) } else { (t1, t0) };
 
-                Interval::new(t0, t1)
+                Interval::new(t0, t1 + t1.abs() * CONSERVATIVE_GAMMA)
             })
             .try_fold(ray_t, |x, y| Interval::intersect(&x, &y))
             .is_some()
@@ -91,6 +97,19 @@ impl AABB {
         }
     }
 
+    pub fn surface_area(&self) -> f64 {
+        let (x, y, z) = (self.x.size(), self.y.size(), self.z.size());
+        2.0 * (x * y + y * z + z * x)
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        Point3::new(
+            (self.x.min() + self.x.max()) * 0.5,
+            (self.y.min() + self.y.max()) * 0.5,
+            (self.z.min() + self.z.max()) * 0.5,
+        )
+    }
+
     pub fn union(self, rhs: AABB) -> AABB {
         AABB {
             x: Interval::union(self.x, rhs.x),
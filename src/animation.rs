@@ -0,0 +1,299 @@
+use std::{
+    fs,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use image::RgbImage;
+
+use crate::{
+    bvh::linear::LinearBVH,
+    camera::Camera,
+    hit::Hittable,
+    hits::Hittables,
+    shapes::Keyframe,
+    utils::{lerp, quaternion::Quaternion, vec3::Vec3},
+};
+
+pub trait Interpolate: Copy {
+    fn interpolate(&self, rhs: &Self, t: f64) -> Self;
+}
+
+impl Interpolate for f64 {
+    fn interpolate(&self, rhs: &Self, t: f64) -> Self {
+        lerp(*self, *rhs, t)
+    }
+}
+
+impl Interpolate for Vec3 {
+    fn interpolate(&self, rhs: &Self, t: f64) -> Self {
+        lerp(*self, *rhs, t)
+    }
+}
+
+impl Interpolate for Quaternion {
+    fn interpolate(&self, rhs: &Self, t: f64) -> Self {
+        self.slerp(*rhs, t)
+    }
+}
+
+impl Interpolate for Keyframe {
+    fn interpolate(&self, rhs: &Self, t: f64) -> Self {
+        Keyframe {
+            time: lerp(self.time, rhs.time, t),
+            offset: lerp(self.offset, rhs.offset, t),
+            quaternion: self.quaternion.slerp(rhs.quaternion, t),
+            scale: lerp(self.scale, rhs.scale, t),
+        }
+    }
+}
+
+// 按帧号排列的关键帧序列，帧号可以是小数
+#[derive(Debug, Clone)]
+pub struct Track<T: Interpolate> {
+    keys: Vec<(f64, T)>,
+}
+
+impl<T: Interpolate> Default for Track<T> {
+    fn default() -> Self {
+        Self { keys: Vec::new() }
+    }
+}
+
+impl<T: Interpolate> Track<T> {
+    pub fn new() -> Track<T> {
+        Track::default()
+    }
+
+    pub fn key(mut self, frame: f64, value: T) -> Track<T> {
+        let index = self.keys.partition_point(|(f, _)| *f <= frame);
+        self.keys.insert(index, (frame, value));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn value_at(&self, frame: f64) -> Option<T> {
+        let first = self.keys.first()?;
+        let last = self.keys.last()?;
+
+        if frame <= first.0 {
+            return Some(first.1);
+        }
+        if frame >= last.0 {
+            return Some(last.1);
+        }
+
+        let next = self.keys.partition_point(|(f, _)| *f <= frame);
+        let (f0, v0) = &self.keys[next - 1];
+        let (f1, v1) = &self.keys[next];
+        Some(v0.interpolate(v1, (frame - f0) / (f1 - f0)))
+    }
+}
+
+pub type TransformTrack = Track<Keyframe>;
+
+impl TransformTrack {
+    // 生成某一帧快门区间内的两个关键帧，可直接交给 Transform::new_with_keyframes 产生运动模糊
+    pub fn keyframes_for_frame(&self, frame: f64, shutter: f64) -> Vec<Keyframe> {
+        let Some(open) = self.value_at(frame) else {
+            return vec![Keyframe::new(0.0, None, None, None)];
+        };
+        if shutter <= 0.0 {
+            return vec![Keyframe { time: 0.0, ..open }];
+        }
+
+        let close = self.value_at(frame + shutter).unwrap_or(open);
+        vec![
+            Keyframe { time: 0.0, ..open },
+            Keyframe { time: 1.0, ..close },
+        ]
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CameraTrack {
+    pub look_from: Track<Vec3>,
+    pub look_at: Track<Vec3>,
+    pub vertical_fov_in_degrees: Track<f64>,
+    pub focus_distance: Track<f64>,
+    pub defocus_angle_in_degrees: Track<f64>,
+}
+
+impl CameraTrack {
+    // 只覆盖设置了关键帧的参数
+    pub fn apply(&self, camera: &mut Camera, frame: f64) {
+        if let Some(v) = self.look_from.value_at(frame) {
+            camera.look_from = v;
+        }
+        if let Some(v) = self.look_at.value_at(frame) {
+            camera.look_at = v;
+        }
+        if let Some(v) = self.vertical_fov_in_degrees.value_at(frame) {
+            camera.vertical_fov_in_degrees = v;
+        }
+        if let Some(v) = self.focus_distance.value_at(frame) {
+            camera.focus_distance = v;
+        }
+        if let Some(v) = self.defocus_angle_in_degrees.value_at(frame) {
+            camera.defocus_angle_in_degrees = v;
+        }
+    }
+}
+
+// 根据帧号构建场景，返回世界与光源
+pub type SceneFn = Box<dyn Fn(f64) -> (Hittables, Option<Hittables>)>;
+
+// 物体在 SceneFn 返回的世界或光源列表中的下标
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SceneObject {
+    World(usize),
+    Light(usize),
+}
+
+// 根据帧号返回代替场景中某个物体的新物体，参数为帧号与物体的位置，返回 None 时物体保持不变
+// 动画物体的具体类型（如 Transform）由场景一侧持有，场景本身只保存 dyn Hittable
+// 光源列表中的物体也会被更新，移动的光源需要在两处做同样的修改
+pub type UpdateFn = Box<dyn Fn(f64, SceneObject) -> Option<Box<dyn Hittable>>>;
+
+pub struct Animation {
+    pub camera: Camera,
+    pub camera_track: CameraTrack,
+    pub scene: SceneFn,
+    pub update: Option<UpdateFn>,
+    // 设置了 update 时保留上一帧的 BVH 与光源
+    world: Option<(LinearBVH, Option<Hittables>)>,
+}
+
+impl Animation {
+    pub fn new(camera: Camera, camera_track: CameraTrack, scene: SceneFn) -> Animation {
+        Animation {
+            camera,
+            camera_track,
+            scene,
+            update: None,
+            world: None,
+        }
+    }
+
+    // 只在首帧调用 scene 构建 BVH，之后的帧用 update 修改物体并重新拟合 BVH
+    pub fn with_update(mut self, update: UpdateFn) -> Animation {
+        self.update = Some(update);
+        self
+    }
+
+    pub fn frame_path(output_dir: &Path, frame: u32) -> PathBuf {
+        output_dir.join(format!("frame_{frame:04}.png"))
+    }
+
+    pub fn render_frame(&mut self, frame: u32) -> RgbImage {
+        let time = frame as f64;
+        self.camera_track.apply(&mut self.camera, time);
+
+        let Some(update) = &self.update else {
+            let (world, lights) = (self.scene)(time);
+            return self
+                .camera
+                .render(&world, lights.as_ref().map(|l| l as &dyn Hittable));
+        };
+
+        let start = Instant::now();
+        let action = match &mut self.world {
+            Some((world, lights)) => {
+                let replace = |position, object: &mut Box<dyn Hittable>| {
+                    if let Some(new) = update(time, position) {
+                        *object = new;
+                    }
+                };
+                if let Some(lights) = lights {
+                    lights.update(|index, object| replace(SceneObject::Light(index), object));
+                }
+                if world.update(|index, object| replace(SceneObject::World(index), object)) {
+                    "rebuilt"
+                } else {
+                    "refitted"
+                }
+            }
+            None => {
+                let (world, lights) = (self.scene)(time);
+                self.world = Some((LinearBVH::new(world), lights));
+                "built"
+            }
+        };
+        println!("BVH {action} in {:.3}s", start.elapsed().as_secs_f64());
+
+        let (world, lights) = self.world.as_ref().unwrap();
+        self.camera
+            .render(world, lights.as_ref().map(|l| l as &dyn Hittable))
+    }
+
+    // 依次渲染并保存帧序列，resume 为 true 时跳过已经存在的帧以便中途继续
+    pub fn render_sequence(
+        &mut self,
+        frames: RangeInclusive<u32>,
+        output_dir: &Path,
+        resume: bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        fs::create_dir_all(output_dir)?;
+
+        for frame in frames {
+            let path = Animation::frame_path(output_dir, frame);
+            if resume && path.exists() {
+                println!("Skip frame {frame}, already rendered");
+                continue;
+            }
+
+            println!("Rendering frame {frame}");
+            let img = self.render_frame(frame);
+
+            // 先写入临时文件再重命名，避免中断时留下不完整的帧被当作已完成
+            let tmp_path = path.with_extension("tmp.png");
+            img.save(&tmp_path)?;
+            fs::rename(&tmp_path, &path)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_track_value_at() {
+        let track = Track::new().key(10.0, 1.0).key(0.0, 0.0).key(20.0, 3.0);
+
+        assert_eq!(track.value_at(-5.0), Some(0.0));
+        assert_eq!(track.value_at(5.0), Some(0.5));
+        assert_eq!(track.value_at(15.0), Some(2.0));
+        assert_eq!(track.value_at(25.0), Some(3.0));
+        assert_eq!(Track::<f64>::new().value_at(1.0), None);
+    }
+
+    #[test]
+    fn test_frame_path() {
+        let path = Animation::frame_path(Path::new("out"), 7);
+        assert_eq!(path, Path::new("out").join("frame_0007.png"));
+    }
+
+    #[test]
+    fn test_transform_track_shutter() {
+        let track = TransformTrack::new()
+            .key(0.0, Keyframe::new(0.0, None, None, None))
+            .key(
+                10.0,
+                Keyframe::new(0.0, Some(Vec3::new(10.0, 0.0, 0.0)), None, None),
+            );
+
+        let keys = track.keyframes_for_frame(2.0, 0.5);
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0].time, 0.0);
+        assert_eq!(keys[1].time, 1.0);
+        assert!((keys[0].offset.x() - 2.0).abs() < 1e-10);
+        assert!((keys[1].offset.x() - 2.5).abs() < 1e-10);
+    }
+}
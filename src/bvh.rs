@@ -1,6 +1,245 @@
-use std::cmp::Ordering;
+pub mod linear;
+pub mod wide;
 
-use crate::{aabb::AABB, hit::Hittable, hits::Hittables, utils::interval::Interval};
+use rayon::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    aabb::AABB,
+    hit::{HitRecord, Hittable},
+    hits::Hittables,
+    stats::{self, Counter},
+    utils::{
+        interval::Interval,
+        random::Random,
+        ray::Ray,
+        vec3::{Point3, UnitVec3, Vec3},
+    },
+};
+
+// 物体数不少于该值时并行构建子树与分桶
+const PARALLEL_THRESHOLD: usize = 1024;
+
+// 构建 BVH 时选择划分位置的方法
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SplitMethod {
+    // 沿最长轴按包围盒最小值排序，从中间划分
+    Median,
+    // 分桶的表面积启发式，在三个轴上选择估计开销最小的划分，
+    // 物体数不超过 max_leaf_size 且不划分更划算时作为叶节点
+    Sah {
+        #[serde(default = "default_bins")]
+        bins: usize,
+        #[serde(default = "default_max_leaf_size")]
+        max_leaf_size: usize,
+        #[serde(default = "default_cost")]
+        traversal_cost: f64,
+        #[serde(default = "default_cost")]
+        intersection_cost: f64,
+    },
+}
+
+fn default_bins() -> usize {
+    12
+}
+
+fn default_max_leaf_size() -> usize {
+    4
+}
+
+fn default_cost() -> f64 {
+    1.0
+}
+
+impl Default for SplitMethod {
+    fn default() -> Self {
+        SplitMethod::Sah {
+            bins: default_bins(),
+            max_leaf_size: default_max_leaf_size(),
+            traversal_cost: default_cost(),
+            intersection_cost: default_cost(),
+        }
+    }
+}
+
+// 场景顶层使用的加速结构，都实现 Hittable，可以互相替换
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Accelerator {
+    #[default]
+    Bvh,
+    Linear,
+    Bvh4,
+    Bvh8,
+}
+
+impl Accelerator {
+    pub fn build(self, objects: Vec<Box<dyn Hittable>>) -> Box<dyn Hittable> {
+        match self {
+            Accelerator::Bvh => Box::new(BVH::from_vec(objects)),
+            Accelerator::Linear => Box::new(linear::LinearBVH::from_vec(objects)),
+            Accelerator::Bvh4 => Box::new(wide::BVH4::from_vec(objects)),
+            Accelerator::Bvh8 => Box::new(wide::BVH8::from_vec(objects)),
+        }
+    }
+}
+
+impl SplitMethod {
+    // 划分一个节点中的物体，将右子节点的物体移出并返回，返回 None 表示应作为叶节点
+    pub(crate) fn split<T: Sync>(
+        &self,
+        objects: &mut Vec<T>,
+        bbox: &AABB,
+        bounding_box: impl Fn(&T) -> &AABB + Sync,
+    ) -> Option<Vec<T>> {
+        match *self {
+            SplitMethod::Median => Some(SplitMethod::split_median(objects, bbox, bounding_box)),
+            SplitMethod::Sah {
+                bins,
+                max_leaf_size,
+                traversal_cost,
+                intersection_cost,
+            } => SplitMethod::split_sah(
+                objects,
+                bbox,
+                bounding_box,
+                bins,
+                max_leaf_size,
+                traversal_cost,
+                intersection_cost,
+            ),
+        }
+    }
+
+    // 将 objects 的后一半移出并返回
+    fn split_median<T>(
+        objects: &mut Vec<T>,
+        bbox: &AABB,
+        bounding_box: impl Fn(&T) -> &AABB + Sync,
+    ) -> Vec<T> {
+        let axis = bbox.longest_axis();
+        objects.sort_by(|a, b| {
+            f64::total_cmp(
+                bounding_box(a).axis_interval(axis).min(),
+                bounding_box(b).axis_interval(axis).min(),
+            )
+        });
+
+        let mid = objects.len() / 2;
+        objects.split_off(mid)
+    }
+
+    // 按包围盒中心将物体分入等宽的桶，在桶的边界中选择开销最小的划分
+    // 返回 None 表示应作为叶节点
+    fn split_sah<T: Sync>(
+        objects: &mut Vec<T>,
+        bbox: &AABB,
+        bounding_box: impl Fn(&T) -> &AABB + Sync,
+        bins: usize,
+        max_leaf_size: usize,
+        traversal_cost: f64,
+        intersection_cost: f64,
+    ) -> Option<Vec<T>> {
+        let bins = bins.max(2);
+        let len = objects.len();
+
+        let centroids: Vec<Point3> = objects
+            .par_iter()
+            .with_min_len(PARALLEL_THRESHOLD)
+            .map(|object| bounding_box(object).centroid())
+            .collect();
+        let (min, max) = centroids.iter().fold(
+            ([f64::INFINITY; 3], [f64::NEG_INFINITY; 3]),
+            |(mut min, mut max), c| {
+                for axis in 0..3 {
+                    min[axis] = min[axis].min(c[axis]);
+                    max[axis] = max[axis].max(c[axis]);
+                }
+                (min, max)
+            },
+        );
+        let bin_of = |c: &Point3, axis: usize| {
+            let t = (c[axis] - min[axis]) / (max[axis] - min[axis]);
+            ((t * bins as f64) as usize).min(bins - 1)
+        };
+
+        // (开销, 轴, 左侧最后一个桶)
+        let mut best: Option<(f64, usize, usize)> = None;
+        for axis in 0..3 {
+            if max[axis] - min[axis] <= 0.0 {
+                continue;
+            }
+
+            let empty = || (vec![0usize; bins], vec![AABB::EMPTY; bins]);
+            let add = |(mut counts, mut boxes): (Vec<usize>, Vec<AABB>), (object, c)| {
+                let b = bin_of(c, axis);
+                counts[b] += 1;
+                boxes[b] = boxes[b].union(*bounding_box(object));
+                (counts, boxes)
+            };
+            let (counts, boxes) = if len >= PARALLEL_THRESHOLD {
+                objects
+                    .par_iter()
+                    .zip(centroids.par_iter())
+                    .fold(empty, add)
+                    .reduce(empty, |(mut counts, mut boxes), (c, b)| {
+                        for i in 0..bins {
+                            counts[i] += c[i];
+                            boxes[i] = boxes[i].union(b[i]);
+                        }
+                        (counts, boxes)
+                    })
+            } else {
+                objects.iter().zip(&centroids).fold(empty(), add)
+            };
+
+            // 自右向左累积右侧的面积与数量
+            let mut right_area = vec![0.0; bins];
+            let mut right_count = vec![0usize; bins];
+            let (mut area, mut count) = (AABB::EMPTY, 0);
+            for b in (1..bins).rev() {
+                area = area.union(boxes[b]);
+                count += counts[b];
+                right_area[b] = area.surface_area();
+                right_count[b] = count;
+            }
+
+            let (mut area, mut count) = (AABB::EMPTY, 0);
+            for b in 0..bins - 1 {
+                area = area.union(boxes[b]);
+                count += counts[b];
+                if count == 0 || right_count[b + 1] == 0 {
+                    continue;
+                }
+                let cost = traversal_cost
+                    + intersection_cost
+                        * (area.surface_area() * count as f64
+                            + right_area[b + 1] * right_count[b + 1] as f64)
+                        / bbox.surface_area();
+                if best.is_none_or(|(best_cost, _, _)| cost < best_cost) {
+                    best = Some((cost, axis, b));
+                }
+            }
+        }
+
+        let leaf_cost = intersection_cost * len as f64;
+        match best {
+            Some((cost, _, _)) if len <= max_leaf_size && leaf_cost <= cost => None,
+            Some((_, axis, split)) => {
+                let (left, right): (Vec<_>, Vec<_>) = std::mem::take(objects)
+                    .into_iter()
+                    .zip(&centroids)
+                    .partition(|(_, c)| bin_of(c, axis) <= split);
+                *objects = left.into_iter().map(|(object, _)| object).collect();
+                Some(right.into_iter().map(|(object, _)| object).collect())
+            }
+            // 所有物体的中心重合，无法按中心划分
+            None if len <= max_leaf_size => None,
+            None => Some(SplitMethod::split_median(objects, bbox, bounding_box)),
+        }
+    }
+}
 
 pub struct BVH {
     left: Option<Box<dyn Hittable>>,
@@ -13,13 +252,15 @@ impl BVH {
         BVH::from_vec(world.objects)
     }
 
-    pub fn from_vec(mut objects: Vec<Box<dyn Hittable>>) -> BVH {
+    pub fn from_vec(objects: Vec<Box<dyn Hittable>>) -> BVH {
+        BVH::with_method(objects, SplitMethod::default())
+    }
+
+    pub fn with_method(mut objects: Vec<Box<dyn Hittable>>, method: SplitMethod) -> BVH {
         let bbox = objects
             .iter()
             .fold(AABB::EMPTY, |x, y| AABB::union(x, *y.bounding_box()));
 
-        let axis = bbox.longest_axis();
-
         let len = objects.len();
 
         let (left, right) = match len {
@@ -29,27 +270,159 @@ impl BVH {
                 let mut iter = objects.into_iter();
                 (Some(iter.next().unwrap()), Some(iter.next().unwrap()))
             }
-            _ => {
-                objects.sort_by(|a, b| BVH::box_compare(a.as_ref(), b.as_ref(), axis));
-
-                let mid = len / 2;
-                let right_vec = objects.split_off(mid);
-                let left_vec = objects;
-                let left: Option<Box<dyn Hittable>> = Some(Box::new(BVH::from_vec(left_vec)));
-                let right: Option<Box<dyn Hittable>> = Some(Box::new(BVH::from_vec(right_vec)));
+            _ => match method.split(&mut objects, &bbox, |object| object.bounding_box()) {
+                Some(right_vec) => {
+                    let left_vec = objects;
+                    let build = |objects| BVH::with_method(objects, method);
+                    let (left, right) = if len >= PARALLEL_THRESHOLD {
+                        rayon::join(|| build(left_vec), || build(right_vec))
+                    } else {
+                        (build(left_vec), build(right_vec))
+                    };
+                    let left: Option<Box<dyn Hittable>> = Some(Box::new(left));
+                    let right: Option<Box<dyn Hittable>> = Some(Box::new(right));
 
-                (left, right)
-            }
+                    (left, right)
+                }
+                None => {
+                    let mut leaf = Hittables::default();
+                    objects.into_iter().for_each(|object| leaf.add(object));
+                    (Some(Box::new(leaf) as Box<dyn Hittable>), None)
+                }
+            },
         };
 
         BVH { left, right, bbox }
     }
+}
+
+// 超过该深度后改为中位数划分，使展平后的 BVH 可以使用定长的遍历栈
+const MAX_SAH_DEPTH: usize = 32;
 
-    fn box_compare(a: &dyn Hittable, b: &dyn Hittable, axis_index: usize) -> Ordering {
-        let a_axis_interval = a.bounding_box().axis_interval(axis_index);
-        let b_axis_interval = b.bounding_box().axis_interval(axis_index);
+// 重新拟合后的 SAH 代价超过构建时的该倍数时，重新构建整棵树
+const REBUILD_RATIO: f64 = 1.5;
 
-        f64::total_cmp(a_axis_interval.min(), b_axis_interval.min())
+// 展平的 BVH 引用的图元集合，叶节点只保存图元在集合中的下标
+// 网格的三角形据此直接以下标引用共享的顶点数据，不必各自装箱
+pub trait Primitives: Send + Sync {
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn bounding_box(&self, index: u32) -> AABB;
+
+    fn hit(&self, index: u32, r: &Ray, interval: &Interval) -> Option<HitRecord<'_>>;
+
+    fn pdf_value(&self, index: u32, origin: &Point3, direction: &Vec3, time: f64) -> f64;
+
+    fn random(&self, index: u32, origin: &Point3, time: f64) -> UnitVec3;
+}
+
+// 与 Hittables 相同，在所有图元中均匀选择一个作为光源采样
+pub(crate) fn pdf_value(
+    primitives: &impl Primitives,
+    origin: &Point3,
+    direction: &Vec3,
+    time: f64,
+) -> f64 {
+    let sum: f64 = (0..primitives.len() as u32)
+        .map(|index| primitives.pdf_value(index, origin, direction, time))
+        .sum();
+    sum / primitives.len() as f64
+}
+
+pub(crate) fn random(primitives: &impl Primitives, origin: &Point3, time: f64) -> UnitVec3 {
+    let index = Random::usize(0..=primitives.len() - 1);
+    primitives.random(index as u32, origin, time)
+}
+
+impl Primitives for Vec<Box<dyn Hittable>> {
+    fn len(&self) -> usize {
+        self.as_slice().len()
+    }
+
+    fn bounding_box(&self, index: u32) -> AABB {
+        *self[index as usize].bounding_box()
+    }
+
+    fn hit(&self, index: u32, r: &Ray, interval: &Interval) -> Option<HitRecord<'_>> {
+        self[index as usize].hit(r, interval)
+    }
+
+    fn pdf_value(&self, index: u32, origin: &Point3, direction: &Vec3, time: f64) -> f64 {
+        self[index as usize].pdf_value(origin, direction, time)
+    }
+
+    fn random(&self, index: u32, origin: &Point3, time: f64) -> UnitVec3 {
+        self[index as usize].random(origin, time)
+    }
+}
+
+// 构建时使用的图元下标及其包围盒
+type Primitive = (u32, AABB);
+
+fn indexed(primitives: &impl Primitives) -> Vec<Primitive> {
+    (0..primitives.len() as u32)
+        .into_par_iter()
+        .with_min_len(PARALLEL_THRESHOLD)
+        .map(|index| (index, primitives.bounding_box(index)))
+        .collect()
+}
+
+// 展平的 BVH 构建时使用的二叉树
+enum BuildNode {
+    Leaf {
+        bbox: AABB,
+        objects: Vec<Primitive>,
+    },
+    Interior {
+        bbox: AABB,
+        children: Box<[BuildNode; 2]>,
+    },
+}
+
+impl BuildNode {
+    fn new(mut objects: Vec<Primitive>, method: SplitMethod, depth: usize) -> BuildNode {
+        let bbox = objects
+            .iter()
+            .fold(AABB::EMPTY, |x, (_, y)| AABB::union(x, *y));
+        let len = objects.len();
+
+        // 叶节点的图元数量以 u16 存储
+        let method = if depth >= MAX_SAH_DEPTH || len > u16::MAX as usize {
+            SplitMethod::Median
+        } else {
+            method
+        };
+        let right = if len == 1 {
+            None
+        } else {
+            method.split(&mut objects, &bbox, |(_, bbox)| bbox)
+        };
+
+        match right {
+            None => BuildNode::Leaf { bbox, objects },
+            Some(right) => {
+                let build = |objects| BuildNode::new(objects, method, depth + 1);
+                let (left, right) = if len >= PARALLEL_THRESHOLD {
+                    rayon::join(|| build(objects), || build(right))
+                } else {
+                    (build(objects), build(right))
+                };
+                BuildNode::Interior {
+                    bbox,
+                    children: Box::new([left, right]),
+                }
+            }
+        }
+    }
+
+    fn bbox(&self) -> &AABB {
+        match self {
+            BuildNode::Leaf { bbox, .. } | BuildNode::Interior { bbox, .. } => bbox,
+        }
     }
 }
 
@@ -59,6 +432,7 @@ impl Hittable for BVH {
         r: &crate::utils::ray::Ray,
         interval: &Interval,
     ) -> Option<crate::hit::HitRecord> {
+        stats::count(Counter::BvhNodes);
         if !self.bbox.hit(r, *interval) {
             return None;
         }
@@ -88,3 +462,130 @@ impl Hittable for BVH {
         &self.bbox
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+
+    use super::*;
+    use crate::{
+        material::Lambertian,
+        shapes::sphere::Sphere,
+        texture::SolidColor,
+        utils::{color::Color, ray::Ray},
+    };
+
+    // 大部分球聚集在一角，另有少量分散的大球
+    // 使用独立的固定种子，共享的随机数状态会被并发的其他测试重新播种
+    fn uneven_spheres() -> Vec<(Point3, f64)> {
+        let mut rng = StdRng::seed_from_u64(42);
+        (0..200)
+            .map(|i| {
+                let (scale, radius) = if i % 20 == 0 { (50.0, 3.0) } else { (2.0, 0.1) };
+                let center = Point3::new(
+                    rng.random_range(0.0..scale),
+                    rng.random_range(0.0..scale),
+                    rng.random_range(0.0..scale),
+                );
+                (center, radius)
+            })
+            .collect()
+    }
+
+    fn build(spheres: &[(Point3, f64)]) -> Vec<Box<dyn Hittable>> {
+        let mat = Arc::new(Lambertian::new(Arc::new(SolidColor::new(Color::WHITE))));
+        spheres
+            .iter()
+            .map(|&(center, radius)| {
+                Box::new(Sphere::new(center, radius, mat.clone())) as Box<dyn Hittable>
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_split_methods_agree() {
+        let spheres = uneven_spheres();
+        let sah = BVH::from_vec(build(&spheres));
+        let median = BVH::with_method(build(&spheres), SplitMethod::Median);
+        let mut list = Hittables::default();
+        build(&spheres).into_iter().for_each(|o| list.add(o));
+
+        // 与逐个求交的结果一致
+        let interval = Interval::from_range(0.001..f64::INFINITY);
+        let origin = Point3::new(-10.0, 25.0, -10.0);
+        let targets = spheres
+            .iter()
+            .map(|&(center, _)| center)
+            .chain((0..216).map(|i| {
+                // 覆盖整个场景的 6x6x6 网格
+                let [x, y, z] = [i % 6, i / 6 % 6, i / 36].map(|k| 5.0 + 8.0 * k as f64);
+                Point3::new(x, y, z)
+            }));
+        let (mut sah_cost, mut median_cost) = (0, 0);
+        let _collecting = stats::collect();
+        for target in targets {
+            let r = Ray::new(origin, target - origin);
+            let expected = list.hit(&r, &interval).map(|rec| rec.t);
+            stats::take_local();
+            assert_eq!(sah.hit(&r, &interval).map(|rec| rec.t), expected);
+            sah_cost += stats::take_local().cost();
+            assert_eq!(median.hit(&r, &interval).map(|rec| rec.t), expected);
+            median_cost += stats::take_local().cost();
+        }
+        // 不均匀分布时 SAH 的遍历开销低于中点划分
+        assert!(sah_cost < median_cost, "{sah_cost} >= {median_cost}");
+    }
+
+    #[test]
+    fn test_parallel_build() {
+        // 超过 PARALLEL_THRESHOLD 时并行构建子树与分桶
+        let spheres: Vec<(Point3, f64)> = (0..5000)
+            .map(|_| (Point3::random_range(-100.0..100.0), 0.5))
+            .collect();
+        let accelerators: Vec<_> = ["bvh", "linear", "bvh4", "bvh8"]
+            .into_iter()
+            .map(|name| {
+                let accelerator: Accelerator = serde_json::from_value(name.into()).unwrap();
+                accelerator.build(build(&spheres))
+            })
+            .collect();
+        let mut list = Hittables::default();
+        build(&spheres).into_iter().for_each(|o| list.add(o));
+
+        let interval = Interval::from_range(0.001..f64::INFINITY);
+        for &(center, _) in spheres.iter().take(200) {
+            let origin = Point3::new(0.0, 0.0, -200.0);
+            let r = Ray::new(origin, center - origin);
+            let expected = list.hit(&r, &interval).map(|rec| rec.t);
+            assert!(expected.is_some());
+            for accelerator in &accelerators {
+                assert_eq!(accelerator.hit(&r, &interval).map(|rec| rec.t), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let parse = |value| serde_json::from_value::<SplitMethod>(value);
+        assert_eq!(
+            parse(serde_json::json!({"type": "median"})).unwrap(),
+            SplitMethod::Median
+        );
+        assert_eq!(
+            parse(serde_json::json!({"type": "sah"})).unwrap(),
+            SplitMethod::default()
+        );
+        assert_eq!(
+            parse(serde_json::json!({"type": "sah", "max_leaf_size": 1, "bins": 32})).unwrap(),
+            SplitMethod::Sah {
+                bins: 32,
+                max_leaf_size: 1,
+                traversal_cost: 1.0,
+                intersection_cost: 1.0,
+            }
+        );
+        assert!(parse(serde_json::json!({"type": "middle"})).is_err());
+    }
+}
@@ -0,0 +1,503 @@
+use std::{
+    io::{self, Read, Write},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    aabb::{AABB, CONSERVATIVE_GAMMA},
+    bvh::{self, BuildNode, Primitives, REBUILD_RATIO, SplitMethod, indexed},
+    hit::{HitRecord, Hittable},
+    hits::Hittables,
+    stats::{self, Counter},
+    utils::{
+        interval::Interval,
+        ray::Ray,
+        vec3::{Point3, UnitVec3, Vec3},
+    },
+};
+
+// 二叉树的最大深度，见 BuildNode::new
+const STACK_SIZE: usize = 64;
+
+// 32 字节的节点，包围盒以 f32 存储并向外取整
+#[derive(Debug, Clone, Copy)]
+#[repr(C, align(32))]
+struct LinearNode {
+    min: [f32; 3],
+    max: [f32; 3],
+    // 叶节点为第一个图元的下标，内部节点为第二个子节点的下标，第一个子节点紧跟在其后
+    offset: u32,
+    // 叶节点中图元的数量，内部节点为 0
+    count: u16,
+    // 划分子节点的轴，遍历时据此先访问较近的子节点
+    // 最高位为 1 表示第二个子节点在该轴上更靠前
+    axis: u8,
+}
+
+impl LinearNode {
+    fn new(bbox: &AABB, offset: u32, count: u16, axis: u8) -> LinearNode {
+        let mut node = LinearNode {
+            min: [0.0; 3],
+            max: [0.0; 3],
+            offset,
+            count,
+            axis,
+        };
+        node.set_bbox(bbox);
+        node
+    }
+
+    fn set_bbox(&mut self, bbox: &AABB) {
+        for axis in 0..3 {
+            let interval = bbox.axis_interval(axis);
+            self.min[axis] = round_down(*interval.min());
+            self.max[axis] = round_up(*interval.max());
+        }
+    }
+
+    fn bbox(&self) -> AABB {
+        let [min, max] = [self.min, self.max].map(|v| v.map(|x| x as f64));
+        AABB::from_points(
+            Point3::new(min[0], min[1], min[2]),
+            Point3::new(max[0], max[1], max[2]),
+        )
+    }
+
+    fn surface_area(&self) -> f64 {
+        let d = [0, 1, 2].map(|axis| (self.max[axis] - self.min[axis]).max(0.0) as f64);
+        2.0 * (d[0] * d[1] + d[1] * d[2] + d[2] * d[0])
+    }
+
+    fn hit(&self, origin: &[f64; 3], inv_dir: &[f64; 3], t_min: f64, t_max: f64) -> bool {
+        let (mut t_min, mut t_max) = (t_min, t_max);
+        for axis in 0..3 {
+            let t0 = (self.min[axis] as f64 - origin[axis]) * inv_dir[axis];
+            let t1 = (self.max[axis] as f64 - origin[axis]) * inv_dir[axis];
+            let (t0, t1) = if t0 <= t1 { (t0, t1) } else { (t1, t0) };
+            t_min = t_min.max(t0);
+            t_max = t_max.min(t1 + t1.abs() * CONSERVATIVE_GAMMA);
+            if t_min > t_max {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+pub(super) fn round_down(x: f64) -> f32 {
+    let y = x as f32;
+    if y as f64 > x { y.next_down() } else { y }
+}
+
+pub(super) fn round_up(x: f64) -> f32 {
+    let y = x as f32;
+    if (y as f64) < x { y.next_up() } else { y }
+}
+
+// 展平到数组中的 BVH，用栈代替递归遍历
+// 图元保持原来的顺序，叶节点按顺序引用 indices 中的一段图元下标
+pub struct LinearBVH<P: Primitives = Vec<Box<dyn Hittable>>> {
+    nodes: Vec<LinearNode>,
+    primitives: P,
+    // 按叶节点顺序排列的图元下标
+    indices: Vec<u32>,
+    bbox: AABB,
+    method: SplitMethod,
+    // 构建完成时的 SAH 代价，用于判断重新拟合后是否需要重新构建
+    build_cost: f64,
+    build_time: Duration,
+}
+
+impl LinearBVH {
+    pub fn new(world: Hittables) -> LinearBVH {
+        LinearBVH::from_vec(world.objects)
+    }
+
+    pub fn from_vec(objects: Vec<Box<dyn Hittable>>) -> LinearBVH {
+        LinearBVH::with_method(objects, SplitMethod::default())
+    }
+
+    // 对每个图元调用 update（参数为构建时的下标），然后重新拟合包围盒
+    // 树的质量下降过多时重新构建，返回是否重新构建
+    pub fn update(&mut self, mut update: impl FnMut(usize, &mut Box<dyn Hittable>)) -> bool {
+        for (index, primitive) in self.primitives.iter_mut().enumerate() {
+            update(index, primitive);
+        }
+        self.refit();
+
+        if self.needs_rebuild() {
+            self.rebuild();
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl<P: Primitives> LinearBVH<P> {
+    pub fn with_method(primitives: P, method: SplitMethod) -> LinearBVH<P> {
+        assert!(
+            !primitives.is_empty(),
+            "BVH node must contain at least one object"
+        );
+
+        let len = primitives.len();
+        let mut bvh = LinearBVH {
+            nodes: Vec::with_capacity(len * 2),
+            primitives,
+            indices: Vec::with_capacity(len),
+            bbox: AABB::EMPTY,
+            method,
+            build_cost: 0.0,
+            build_time: Duration::ZERO,
+        };
+        bvh.build();
+        bvh
+    }
+
+    fn build(&mut self) {
+        let start = Instant::now();
+        let root = BuildNode::new(indexed(&self.primitives), self.method, 0);
+        self.nodes.clear();
+        self.indices.clear();
+        self.bbox = *root.bbox();
+        self.flatten(root);
+        self.build_cost = self.cost();
+        self.build_time = start.elapsed();
+    }
+
+    // 图元移动后自底向上更新节点的包围盒，树的结构保持不变
+    pub fn refit(&mut self) {
+        // 子节点的下标总是大于父节点，逆序遍历即为自底向上
+        for index in (0..self.nodes.len()).rev() {
+            let node = self.nodes[index];
+            let bbox = if node.count > 0 {
+                let start = node.offset as usize;
+                self.indices[start..start + node.count as usize]
+                    .iter()
+                    .fold(AABB::EMPTY, |x, &y| {
+                        AABB::union(x, self.primitives.bounding_box(y))
+                    })
+            } else {
+                AABB::union(
+                    self.nodes[index + 1].bbox(),
+                    self.nodes[node.offset as usize].bbox(),
+                )
+            };
+            self.nodes[index].set_bbox(&bbox);
+            if index == 0 {
+                self.bbox = bbox;
+            }
+        }
+    }
+
+    pub fn needs_rebuild(&self) -> bool {
+        self.cost() > self.build_cost * REBUILD_RATIO
+    }
+
+    // 以构建时的划分方法重新构建
+    pub fn rebuild(&mut self) {
+        self.build();
+    }
+
+    // 以根节点表面积归一化的 SAH 代价，遍历与求交的代价都取 1
+    fn cost(&self) -> f64 {
+        let root = self.nodes[0].surface_area();
+        if root <= 0.0 {
+            return 0.0;
+        }
+        self.nodes
+            .iter()
+            .map(|node| node.surface_area() * node.count.max(1) as f64)
+            .sum::<f64>()
+            / root
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn build_time(&self) -> Duration {
+        self.build_time
+    }
+
+    pub fn primitives(&self) -> &P {
+        &self.primitives
+    }
+
+    // 按叶节点顺序排列的图元下标
+    pub fn primitive_indices(&self) -> &[u32] {
+        &self.indices
+    }
+
+    // 以小端序写入节点数组，图元由调用者按 primitive_indices 的顺序另行保存
+    pub fn write_nodes(&self, w: &mut impl Write) -> io::Result<()> {
+        w.write_all(&(self.nodes.len() as u32).to_le_bytes())?;
+        for node in &self.nodes {
+            for x in node.min.iter().chain(&node.max) {
+                w.write_all(&x.to_le_bytes())?;
+            }
+            w.write_all(&node.offset.to_le_bytes())?;
+            w.write_all(&node.count.to_le_bytes())?;
+            w.write_all(&[node.axis])?;
+        }
+        Ok(())
+    }
+
+    // 由 write_nodes 写入的节点与按叶节点顺序排列的图元恢复 BVH，不再重新构建
+    pub fn read_nodes(r: &mut impl Read, primitives: P) -> io::Result<LinearBVH<P>> {
+        let start = Instant::now();
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_owned());
+        let mut buf = [0u8; 4];
+        r.read_exact(&mut buf)?;
+        let len = u32::from_le_bytes(buf) as usize;
+        if len == 0 || len > 2 * primitives.len() {
+            return Err(invalid("Invalid BVH node count"));
+        }
+
+        let mut nodes = Vec::with_capacity(len);
+        let mut node = [0u8; 31];
+        for index in 0..len {
+            r.read_exact(&mut node)?;
+            let f = |i: usize| f32::from_le_bytes(node[i * 4..i * 4 + 4].try_into().unwrap());
+            let node = LinearNode {
+                min: [f(0), f(1), f(2)],
+                max: [f(3), f(4), f(5)],
+                offset: u32::from_le_bytes(node[24..28].try_into().unwrap()),
+                count: u16::from_le_bytes(node[28..30].try_into().unwrap()),
+                axis: node[30],
+            };
+
+            // 拒绝越界的下标，避免损坏的数据在遍历时引起越界访问
+            let valid = if node.count > 0 {
+                node.offset as usize + node.count as usize <= primitives.len()
+            } else {
+                index + 1 < len
+                    && (node.offset as usize) > index + 1
+                    && (node.offset as usize) < len
+            };
+            if !valid || (node.axis & 0x7f) > 2 {
+                return Err(invalid("Invalid BVH node"));
+            }
+            nodes.push(node);
+        }
+
+        let mut bvh = LinearBVH {
+            bbox: nodes[0].bbox(),
+            nodes,
+            indices: (0..primitives.len() as u32).collect(),
+            primitives,
+            method: SplitMethod::default(),
+            build_cost: 0.0,
+            build_time: Duration::ZERO,
+        };
+        bvh.build_cost = bvh.cost();
+        bvh.build_time = start.elapsed();
+        Ok(bvh)
+    }
+
+    fn node_centroid(&self, index: usize) -> [f64; 3] {
+        let node = &self.nodes[index];
+        [0, 1, 2].map(|axis| (node.min[axis] as f64 + node.max[axis] as f64) * 0.5)
+    }
+
+    // 按深度优先顺序写入节点，返回节点的下标
+    fn flatten(&mut self, node: BuildNode) -> u32 {
+        let index = self.nodes.len();
+        self.nodes.push(LinearNode::new(node.bbox(), 0, 0, 0));
+
+        match node {
+            BuildNode::Leaf { objects, .. } => {
+                self.nodes[index].offset = self.indices.len() as u32;
+                self.nodes[index].count = objects.len() as u16;
+                self.indices.extend(objects.into_iter().map(|(i, _)| i));
+            }
+            BuildNode::Interior { children, .. } => {
+                let [left, right] = *children;
+                self.flatten(left);
+                let second = self.flatten(right);
+
+                // 取两个子节点中心相距最远的轴
+                let a = self.node_centroid(index + 1);
+                let b = self.node_centroid(second as usize);
+                let axis = (0..3)
+                    .max_by(|&i, &j| f64::total_cmp(&(b[i] - a[i]).abs(), &(b[j] - a[j]).abs()))
+                    .unwrap();
+                self.nodes[index].offset = second;
+                self.nodes[index].axis = axis as u8;
+                if b[axis] < a[axis] {
+                    self.nodes[index].axis |= 0x80;
+                }
+            }
+        }
+
+        index as u32
+    }
+}
+
+impl<P: Primitives> Hittable for LinearBVH<P> {
+    fn hit(&self, r: &Ray, interval: &Interval) -> Option<HitRecord> {
+        let origin = r.origin().e();
+        let dir = r.direction().e();
+        let inv_dir = dir.map(|d| 1.0 / d);
+
+        let t_min = *interval.min();
+        let mut closest_so_far = *interval.max();
+        let mut result = None;
+
+        let mut stack = [0u32; STACK_SIZE];
+        let mut stack_len = 0;
+        let mut index = 0;
+        loop {
+            stats::count(Counter::BvhNodes);
+            let node = &self.nodes[index];
+            if node.hit(&origin, &inv_dir, t_min, closest_so_far) {
+                if node.count > 0 {
+                    let start = node.offset as usize;
+                    for &i in &self.indices[start..start + node.count as usize] {
+                        let interval = Interval::new(t_min, closest_so_far);
+                        if let Some(rec) = self.primitives.hit(i, r, &interval) {
+                            closest_so_far = rec.t;
+                            result = Some(rec);
+                        }
+                    }
+                } else {
+                    let axis = (node.axis & 0x7f) as usize;
+                    let swapped = node.axis & 0x80 != 0;
+                    let (near, far) = if (dir[axis] < 0.0) != swapped {
+                        (node.offset as usize, index + 1)
+                    } else {
+                        (index + 1, node.offset as usize)
+                    };
+                    stack[stack_len] = far as u32;
+                    stack_len += 1;
+                    index = near;
+                    continue;
+                }
+            }
+
+            if stack_len == 0 {
+                break;
+            }
+            stack_len -= 1;
+            index = stack[stack_len] as usize;
+        }
+
+        result
+    }
+
+    fn bounding_box(&self) -> &AABB {
+        &self.bbox
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3, time: f64) -> f64 {
+        bvh::pdf_value(&self.primitives, origin, direction, time)
+    }
+
+    fn random(&self, origin: &Point3, time: f64) -> UnitVec3 {
+        bvh::random(&self.primitives, origin, time)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        bvh::BVH,
+        material::Lambertian,
+        shapes::sphere::Sphere,
+        texture::SolidColor,
+        utils::{
+            color::Color,
+            random::Random,
+            vec3::{Point3, Vec3},
+        },
+    };
+
+    #[test]
+    fn test_node_size() {
+        assert_eq!(std::mem::size_of::<LinearNode>(), 32);
+        assert!(round_down(0.1) as f64 <= 0.1);
+        assert!(round_up(0.1) as f64 >= 0.1);
+    }
+
+    #[test]
+    fn test_matches_bvh() {
+        let mat = Arc::new(Lambertian::new(Arc::new(SolidColor::new(Color::WHITE))));
+        let spheres: Vec<(Point3, f64)> = (0..300)
+            .map(|_| {
+                (
+                    Point3::random_range(-50.0..50.0),
+                    Random::random_range(0.5..3.0),
+                )
+            })
+            .collect();
+        let build = || {
+            spheres
+                .iter()
+                .map(|&(center, radius)| {
+                    Box::new(Sphere::new(center, radius, mat.clone())) as Box<dyn Hittable>
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let bvh = BVH::from_vec(build());
+        let linear = LinearBVH::from_vec(build());
+        let median = LinearBVH::with_method(build(), SplitMethod::Median);
+        assert_eq!(median.node_count(), 2 * spheres.len() - 1);
+
+        let interval = Interval::from_range(0.001..f64::INFINITY);
+        for _ in 0..500 {
+            let r = Ray::new(
+                Point3::random_range(-80.0..80.0),
+                Vec3::random_range(-1.0..1.0),
+            );
+            let expected = bvh.hit(&r, &interval).map(|rec| rec.t);
+            assert_eq!(linear.hit(&r, &interval).map(|rec| rec.t), expected);
+            assert_eq!(median.hit(&r, &interval).map(|rec| rec.t), expected);
+        }
+    }
+
+    #[test]
+    fn test_refit() {
+        let mat = Arc::new(Lambertian::new(Arc::new(SolidColor::new(Color::WHITE))));
+        let centers: Vec<Point3> = (0..200)
+            .map(|_| Point3::random_range(-50.0..50.0))
+            .collect();
+        let sphere =
+            |center: Point3| Box::new(Sphere::new(center, 1.0, mat.clone())) as Box<dyn Hittable>;
+        let check = |linear: &LinearBVH, centers: &[Point3]| {
+            let bvh = BVH::from_vec(centers.iter().map(|&c| sphere(c)).collect());
+            let interval = Interval::from_range(0.001..f64::INFINITY);
+            for _ in 0..300 {
+                let r = Ray::new(
+                    Point3::random_range(-80.0..80.0),
+                    Vec3::random_range(-1.0..1.0),
+                );
+                let expected = bvh.hit(&r, &interval).map(|rec| rec.t);
+                assert_eq!(linear.hit(&r, &interval).map(|rec| rec.t), expected);
+            }
+        };
+        let mut linear = LinearBVH::from_vec(centers.iter().map(|&c| sphere(c)).collect());
+
+        // 整体平移不改变树的质量，只需重新拟合
+        let offset = Vec3::new(5.0, -3.0, 2.0);
+        let moved: Vec<Point3> = centers.iter().map(|&c| c + offset).collect();
+        assert!(!linear.update(|i, object| *object = sphere(moved[i])));
+        check(&linear, &moved);
+
+        // 打乱位置后包围盒大量重叠，触发重新构建
+        let shuffled: Vec<Point3> = (0..centers.len())
+            .map(|i| moved[i * 37 % centers.len()])
+            .collect();
+        assert!(linear.update(|i, object| *object = sphere(shuffled[i])));
+        check(&linear, &shuffled);
+
+        // 重新构建后仍以原来的下标访问图元
+        let moved: Vec<Point3> = shuffled.iter().map(|&c| c - offset).collect();
+        assert!(!linear.update(|i, object| *object = sphere(moved[i])));
+        check(&linear, &moved);
+    }
+}
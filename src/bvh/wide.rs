@@ -0,0 +1,439 @@
+use std::time::{Duration, Instant};
+
+use crate::{
+    aabb::AABB,
+    bvh::{
+        self, BuildNode, Primitives, REBUILD_RATIO, SplitMethod, indexed,
+        linear::{round_down, round_up},
+    },
+    hit::{HitRecord, Hittable},
+    hits::Hittables,
+    stats::{self, Counter},
+    utils::{
+        interval::Interval,
+        ray::Ray,
+        vec3::{Point3, UnitVec3, Vec3},
+    },
+};
+
+// 二叉树的最大深度，决定遍历栈的大小
+const MAX_DEPTH: usize = 64;
+// 空位的子节点下标
+const EMPTY: u32 = u32::MAX;
+
+pub type BVH4 = WideBVH<4>;
+pub type BVH8 = WideBVH<8>;
+
+// 一个节点保存 N 个子节点的包围盒，按分量分别存放，以便一次对所有子节点做 slab 测试
+#[derive(Debug, Clone)]
+struct WideNode<const N: usize> {
+    min: [[f32; N]; 3],
+    max: [[f32; N]; 3],
+    // 内部子节点为节点的下标，叶子为第一个图元的下标，空位为 EMPTY
+    child: [u32; N],
+    // 叶子中图元的数量，内部子节点为 0
+    count: [u32; N],
+}
+
+impl<const N: usize> WideNode<N> {
+    fn empty() -> WideNode<N> {
+        WideNode {
+            min: [[f32::INFINITY; N]; 3],
+            max: [[f32::NEG_INFINITY; N]; 3],
+            child: [EMPTY; N],
+            count: [0; N],
+        }
+    }
+
+    // 对 N 个子节点同时求光线进入的距离，未命中的为无穷大
+    // 各分量以定长数组逐条计算，由编译器自动向量化
+    fn hit(&self, origin: &[f32; 3], inv_dir: &[f32; 3], t_min: f32, t_max: f32) -> [f32; N] {
+        let mut near = [t_min; N];
+        let mut far = [t_max; N];
+        for axis in 0..3 {
+            for lane in 0..N {
+                let t0 = (self.min[axis][lane] - origin[axis]) * inv_dir[axis];
+                let t1 = (self.max[axis][lane] - origin[axis]) * inv_dir[axis];
+                near[lane] = near[lane].max(t0.min(t1));
+                far[lane] = far[lane].min(t0.max(t1));
+            }
+        }
+
+        // 放宽远端以抵消光线转换为 f32 的误差
+        let mut t = [f32::INFINITY; N];
+        for lane in 0..N {
+            if near[lane] <= far[lane] * (1.0 + 4.0 * f32::EPSILON) && self.child[lane] != EMPTY {
+                t[lane] = near[lane];
+            }
+        }
+        t
+    }
+
+    fn set_bbox(&mut self, lane: usize, bbox: &AABB) {
+        for axis in 0..3 {
+            let interval = bbox.axis_interval(axis);
+            self.min[axis][lane] = pad(round_down(*interval.min()), -1.0);
+            self.max[axis][lane] = pad(round_up(*interval.max()), 1.0);
+        }
+    }
+
+    fn surface_area(&self, lane: usize) -> f64 {
+        let d = [0, 1, 2].map(|axis| (self.max[axis][lane] - self.min[axis][lane]).max(0.0) as f64);
+        2.0 * (d[0] * d[1] + d[1] * d[2] + d[2] * d[0])
+    }
+}
+
+// 将二叉 BVH 合并为 N 叉树，每个节点的 N 个子包围盒一次测试完成
+// 与 LinearBVH 相同，叶子引用 indices 中的一段图元下标
+pub struct WideBVH<const N: usize, P: Primitives = Vec<Box<dyn Hittable>>> {
+    nodes: Vec<WideNode<N>>,
+    primitives: P,
+    // 按叶子顺序排列的图元下标
+    indices: Vec<u32>,
+    bbox: AABB,
+    method: SplitMethod,
+    // 构建完成时的 SAH 代价，用于判断重新拟合后是否需要重新构建
+    build_cost: f64,
+    build_time: Duration,
+}
+
+impl<const N: usize> WideBVH<N> {
+    pub fn new(world: Hittables) -> WideBVH<N> {
+        WideBVH::from_vec(world.objects)
+    }
+
+    pub fn from_vec(objects: Vec<Box<dyn Hittable>>) -> WideBVH<N> {
+        WideBVH::with_method(objects, SplitMethod::default())
+    }
+
+    // 见 LinearBVH::update
+    pub fn update(&mut self, mut update: impl FnMut(usize, &mut Box<dyn Hittable>)) -> bool {
+        for (index, primitive) in self.primitives.iter_mut().enumerate() {
+            update(index, primitive);
+        }
+        self.refit();
+
+        if self.needs_rebuild() {
+            self.rebuild();
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl<const N: usize, P: Primitives> WideBVH<N, P> {
+    pub fn with_method(primitives: P, method: SplitMethod) -> WideBVH<N, P> {
+        assert!(N >= 2, "A wide BVH needs at least 2 children per node");
+        assert!(
+            !primitives.is_empty(),
+            "BVH node must contain at least one object"
+        );
+
+        let mut bvh = WideBVH {
+            nodes: Vec::new(),
+            primitives,
+            indices: Vec::new(),
+            bbox: AABB::EMPTY,
+            method,
+            build_cost: 0.0,
+            build_time: Duration::ZERO,
+        };
+        bvh.build();
+        bvh
+    }
+
+    fn build(&mut self) {
+        let start = Instant::now();
+        let root = BuildNode::new(indexed(&self.primitives), self.method, 0);
+        self.nodes.clear();
+        self.indices.clear();
+        self.bbox = *root.bbox();
+        self.collapse(vec![root]);
+        self.build_cost = self.cost();
+        self.build_time = start.elapsed();
+    }
+
+    // 图元移动后自底向上更新各子节点的包围盒，树的结构保持不变
+    pub fn refit(&mut self) {
+        self.bbox = self.refit_node(0);
+    }
+
+    // 返回节点所有子节点包围盒的并
+    fn refit_node(&mut self, index: usize) -> AABB {
+        let mut bbox = AABB::EMPTY;
+        for lane in 0..N {
+            let (child, count) = (self.nodes[index].child[lane], self.nodes[index].count[lane]);
+            if child == EMPTY {
+                continue;
+            }
+            let child_bbox = if count > 0 {
+                let start = child as usize;
+                self.indices[start..start + count as usize]
+                    .iter()
+                    .fold(AABB::EMPTY, |x, &y| {
+                        AABB::union(x, self.primitives.bounding_box(y))
+                    })
+            } else {
+                self.refit_node(child as usize)
+            };
+            self.nodes[index].set_bbox(lane, &child_bbox);
+            bbox = AABB::union(bbox, child_bbox);
+        }
+        bbox
+    }
+
+    pub fn needs_rebuild(&self) -> bool {
+        self.cost() > self.build_cost * REBUILD_RATIO
+    }
+
+    // 以构建时的划分方法重新构建
+    pub fn rebuild(&mut self) {
+        self.build();
+    }
+
+    // 以根包围盒表面积归一化的 SAH 代价，遍历与求交的代价都取 1
+    fn cost(&self) -> f64 {
+        let root = self.bbox.surface_area();
+        if root <= 0.0 {
+            return 0.0;
+        }
+        self.nodes
+            .iter()
+            .flat_map(|node| {
+                (0..N)
+                    .filter(|&lane| node.child[lane] != EMPTY)
+                    .map(move |lane| node.surface_area(lane) * node.count[lane].max(1) as f64)
+            })
+            .sum::<f64>()
+            / root
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn build_time(&self) -> Duration {
+        self.build_time
+    }
+
+    // 反复展开表面积最大的内部节点，直到凑满 N 个子节点，返回新节点的下标
+    fn collapse(&mut self, mut children: Vec<BuildNode>) -> u32 {
+        while children.len() < N {
+            let Some(index) = children
+                .iter()
+                .enumerate()
+                .filter(|(_, child)| matches!(child, BuildNode::Interior { .. }))
+                .max_by(|(_, a), (_, b)| {
+                    f64::total_cmp(&a.bbox().surface_area(), &b.bbox().surface_area())
+                })
+                .map(|(index, _)| index)
+            else {
+                break;
+            };
+            let BuildNode::Interior { children: pair, .. } = children.swap_remove(index) else {
+                unreachable!()
+            };
+            children.extend(*pair);
+        }
+
+        let index = self.nodes.len();
+        self.nodes.push(WideNode::empty());
+
+        for (lane, child) in children.into_iter().enumerate() {
+            let bbox = *child.bbox();
+            let (child, count) = match child {
+                BuildNode::Leaf { objects, .. } => {
+                    let offset = self.indices.len() as u32;
+                    let count = objects.len() as u32;
+                    self.indices.extend(objects.into_iter().map(|(i, _)| i));
+                    (offset, count)
+                }
+                BuildNode::Interior { children, .. } => (self.collapse(Vec::from(*children)), 0),
+            };
+
+            let node = &mut self.nodes[index];
+            node.set_bbox(lane, &bbox);
+            node.child[lane] = child;
+            node.count[lane] = count;
+        }
+
+        index as u32
+    }
+}
+
+// 按坐标大小向外扩展，抵消光线原点转换为 f32 的误差
+fn pad(x: f32, sign: f32) -> f32 {
+    x + sign * x.abs() * 4.0 * f32::EPSILON
+}
+
+impl<const N: usize, P: Primitives> Hittable for WideBVH<N, P> {
+    fn hit(&self, r: &Ray, interval: &Interval) -> Option<HitRecord> {
+        let origin = r.origin().e().map(|v| v as f32);
+        let dir = r.direction().e();
+        let inv_dir = dir.map(|d| (1.0 / d) as f32);
+
+        let t_min = *interval.min();
+        let mut closest_so_far = *interval.max();
+        let mut result = None;
+
+        // (子节点, 图元数量, 进入距离)
+        let mut stack = [(0u32, 0u32, 0.0f32); MAX_DEPTH * 8];
+        let mut stack_len = 1;
+        stack[0] = (0, 0, t_min as f32);
+        while stack_len > 0 {
+            stack_len -= 1;
+            let (child, count, t) = stack[stack_len];
+            if t as f64 > closest_so_far * (1.0 + 4.0 * f32::EPSILON as f64) {
+                continue;
+            }
+
+            if count > 0 {
+                let start = child as usize;
+                for &i in &self.indices[start..start + count as usize] {
+                    let interval = Interval::new(t_min, closest_so_far);
+                    if let Some(rec) = self.primitives.hit(i, r, &interval) {
+                        closest_so_far = rec.t;
+                        result = Some(rec);
+                    }
+                }
+                continue;
+            }
+
+            stats::count(Counter::BvhNodes);
+            let node = &self.nodes[child as usize];
+            let hits = node.hit(
+                &origin,
+                &inv_dir,
+                t_min as f32,
+                closest_so_far.min(f32::MAX as f64) as f32,
+            );
+
+            // 按进入距离从远到近入栈，使最近的子节点最先被访问
+            let start = stack_len;
+            for (lane, &t) in hits.iter().enumerate() {
+                if t.is_finite() {
+                    let entry = (node.child[lane], node.count[lane], t);
+                    let mut i = stack_len;
+                    while i > start && stack[i - 1].2 < entry.2 {
+                        stack[i] = stack[i - 1];
+                        i -= 1;
+                    }
+                    stack[i] = entry;
+                    stack_len += 1;
+                }
+            }
+        }
+
+        result
+    }
+
+    fn bounding_box(&self) -> &AABB {
+        &self.bbox
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3, time: f64) -> f64 {
+        bvh::pdf_value(&self.primitives, origin, direction, time)
+    }
+
+    fn random(&self, origin: &Point3, time: f64) -> UnitVec3 {
+        bvh::random(&self.primitives, origin, time)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        bvh::BVH,
+        material::Lambertian,
+        shapes::{quad::build_box, sphere::Sphere},
+        texture::SolidColor,
+        utils::{
+            color::Color,
+            vec3::{Point3, Vec3},
+        },
+    };
+
+    fn scene() -> Vec<Box<dyn Hittable>> {
+        let mat = Arc::new(Lambertian::new(Arc::new(SolidColor::new(Color::WHITE))));
+        let mut objects: Vec<Box<dyn Hittable>> = Vec::new();
+        for i in 0..300 {
+            let center = Point3::new((i % 17) as f64 * 7.0, (i / 17) as f64 * 5.0, i as f64);
+            objects.push(Box::new(Sphere::new(center, 1.5, mat.clone())));
+        }
+        objects.extend(
+            build_box(
+                Point3::new(-200.0, -20.0, -200.0),
+                Point3::new(200.0, -10.0, 400.0),
+                mat,
+            )
+            .objects,
+        );
+        objects
+    }
+
+    fn check<const N: usize>() {
+        let bvh = BVH::from_vec(scene());
+        let wide = WideBVH::<N>::from_vec(scene());
+        let median = WideBVH::<N>::with_method(scene(), SplitMethod::Median);
+        assert!(wide.node_count() < scene().len());
+
+        let interval = Interval::from_range(0.001..f64::INFINITY);
+        for _ in 0..1000 {
+            let r = Ray::new(
+                Point3::random_range(-100.0..300.0),
+                Vec3::random_range(-1.0..1.0),
+            );
+            let expected = bvh.hit(&r, &interval).map(|rec| rec.t);
+            assert_eq!(wide.hit(&r, &interval).map(|rec| rec.t), expected);
+            assert_eq!(median.hit(&r, &interval).map(|rec| rec.t), expected);
+        }
+
+        // 原点在图元上且沿坐标轴方向的光线
+        let r = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0));
+        let interval = Interval::from_range(1e-8..f64::INFINITY);
+        assert_eq!(
+            wide.hit(&r, &interval).map(|rec| rec.t),
+            bvh.hit(&r, &interval).map(|rec| rec.t)
+        );
+    }
+
+    #[test]
+    fn test_bvh4_matches_bvh() {
+        check::<4>();
+    }
+
+    #[test]
+    fn test_bvh8_matches_bvh() {
+        check::<8>();
+    }
+
+    #[test]
+    fn test_refit() {
+        let mat = Arc::new(Lambertian::new(Arc::new(SolidColor::new(Color::WHITE))));
+        let sphere = |i: usize, offset: f64| {
+            let center = Point3::new((i % 10) as f64 * 4.0 + offset, (i / 10) as f64 * 4.0, 0.0);
+            Box::new(Sphere::new(center, 1.0, mat.clone())) as Box<dyn Hittable>
+        };
+        let mut wide = BVH4::from_vec((0..100).map(|i| sphere(i, 0.0)).collect());
+        assert!(!wide.update(|i, object| *object = sphere(i, 2.0)));
+
+        let bvh = BVH::from_vec((0..100).map(|i| sphere(i, 2.0)).collect());
+        assert_eq!(
+            wide.bounding_box().centroid().e(),
+            bvh.bounding_box().centroid().e()
+        );
+        let interval = Interval::from_range(0.001..f64::INFINITY);
+        for _ in 0..300 {
+            let r = Ray::new(
+                Point3::random_range(-10.0..50.0),
+                Vec3::random_range(-1.0..1.0),
+            );
+            let expected = bvh.hit(&r, &interval).map(|rec| rec.t);
+            assert_eq!(wide.hit(&r, &interval).map(|rec| rec.t), expected);
+        }
+    }
+}
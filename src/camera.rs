@@ -1,12 +1,14 @@
 use std::sync::{
     Arc,
-    atomic::{AtomicUsize, Ordering},
+    atomic::{AtomicU64, AtomicUsize, Ordering},
 };
 use std::{
     env::{self, current_dir},
+    f64::consts::PI,
     fs::File,
     io::BufReader,
     path::PathBuf,
+    time::{Duration, Instant},
 };
 
 use image::{ImageBuffer, RgbImage};
@@ -14,21 +16,54 @@ use indicatif::{ProgressBar, ProgressStyle};
 use rayon::prelude::*;
 use serde::Deserialize;
 
+pub mod crop;
+pub mod diagnostics;
+pub mod film;
+pub mod lens;
+
 use crate::{
+    camera::{
+        crop::CropWindow,
+        diagnostics::{Diagnostics, IssueKind, SampleId, SampleIssue},
+        film::{Film, Filter},
+        lens::{Aperture, PhysicalLens},
+    },
+    distributed::{PartialResult, RenderJob, Split},
     hit::Hittable,
     material::ScatterRecord,
     pdf::{HittablePDF, MixturePDF, PDF},
+    post::{
+        PostEffect, Region,
+        outline::{GBuffer, GSample, Outline},
+    },
     shapes::environment::Environment,
+    stats::{self, Counter, Heatmap, HeatmapKind, PixelCost, RenderStats},
     texture::SolidColor,
     utils::{
-        color::{Color, ToonMap},
+        color::{Color, Exposure, ToonMap},
         interval::Interval,
+        quaternion::Quaternion,
         random::Random,
         ray::Ray,
         vec3::{Point3, UnitVec3, Vec3},
     },
 };
 
+// 相机的投影模型
+// 鱼眼的视场由 vertical_fov_in_degrees 决定（对应图像高度），
+// 等距柱状投影总是覆盖 360° x 180°，柱面全景水平覆盖 360°、竖直方向使用 vertical_fov_in_degrees
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Projection {
+    #[default]
+    Perspective,
+    Orthographic,
+    FisheyeEquidistant,
+    FisheyeEquisolid,
+    Equirectangular,
+    CylindricalPanorama,
+}
+
 // 用于从 JSON 反序列化相机参数的结构体
 #[derive(Debug, Deserialize)]
 struct CameraParams {
@@ -40,6 +75,30 @@ struct CameraParams {
     vec_up: Vec3,
     defocus_angle_in_degrees: f64,
     focus_distance: f64,
+    #[serde(default)]
+    projection: Projection,
+    #[serde(default)]
+    lens: Option<PhysicalLens>,
+    #[serde(default)]
+    aperture: Aperture,
+    #[serde(default)]
+    cat_eye: f64,
+    #[serde(default)]
+    tilt_in_degrees: (f64, f64),
+    #[serde(default)]
+    shift: (f64, f64),
+    #[serde(default)]
+    post_effects: Vec<PostEffect>,
+    #[serde(default)]
+    outline: Option<Outline>,
+    #[serde(default)]
+    filter: Filter,
+    #[serde(default)]
+    crop: Option<CropWindow>,
+    #[serde(default)]
+    samples_per_pixel: Option<usize>,
+    #[serde(default)]
+    max_depth: Option<u32>,
 }
 
 #[derive(Debug)]
@@ -48,6 +107,20 @@ pub struct Camera {
     pub image_width: u32,
     pub samples_per_pixel: usize,
     pub max_depth: u32,
+    // 像素重建滤波器，样本按权重累积到半径内的所有像素
+    pub filter: Filter,
+    // 设置后限制间接光照样本的亮度，以少量偏差换取更少的萤火虫噪点
+    pub max_indirect_luminance: Option<f64>,
+    // 设置后只渲染图像中的这一区域，相机的其余设置与完整渲染相同
+    pub crop: Option<CropWindow>,
+    // 设置后 render 作为分布式渲染的工作进程或合并结果
+    pub job: Option<RenderJob>,
+    // 设置后在每次渲染结束时将异常样本的诊断信息保存为 JSON
+    pub diagnostics_report: Option<PathBuf>,
+    // 设置后在每次渲染结束时保存统计信息的 JSON，以及每个像素渲染时间与求交开销的热力图
+    pub stats_report: Option<PathBuf>,
+    pub time_heatmap: Option<PathBuf>,
+    pub cost_heatmap: Option<PathBuf>,
     pub background: Environment,
 
     pub vertical_fov_in_degrees: f64,
@@ -58,19 +131,41 @@ pub struct Camera {
     pub defocus_angle_in_degrees: f64,
     pub focus_distance: f64,
 
+    pub projection: Projection,
+
+    // 光圈形状、猫眼暗角强度（0 为关闭）、焦平面绕相机 u/v 轴的倾斜角与画面平移（以画面宽高为单位）
+    pub aperture: Aperture,
+    pub cat_eye: f64,
+    pub tilt_in_degrees: (f64, f64),
+    pub shift: (f64, f64),
+    // 设置后以焦距和光圈数代替 vertical_fov_in_degrees 和 defocus_angle_in_degrees
+    pub lens: Option<PhysicalLens>,
+
+    pub exposure: Exposure,
+    pub white_balance_in_kelvin: Option<f64>,
     pub toon_map: ToonMap,
+    // 曝光之后、色调映射之前按顺序作用的后期效果
+    pub post_effects: Vec<PostEffect>,
+    // 在最终图像上叠加的描边
+    pub outline: Option<Outline>,
 
     image_height: u32,
+    // 实际使用的竖直视场（弧度），设置 lens 时由焦距求出
+    vertical_fov: f64,
     sqrt_spp: u32,
     recip_sqrt_spp: f64,
     center: Point3,
     pixel00_loc: Point3,
     pixel_delta_u: Vec3,
     pixel_delta_v: Vec3,
-    pixel_sample_scale: f64,
     camera_axis: (UnitVec3, UnitVec3, UnitVec3),
     defocus_disk_u: Vec3,
     defocus_disk_v: Vec3,
+    defocus_radius: f64,
+    focus_plane_normal: UnitVec3,
+    diagnostics: Diagnostics,
+    stats: RenderStats,
+    heatmap: Heatmap,
 }
 
 impl Default for Camera {
@@ -80,6 +175,14 @@ impl Default for Camera {
             image_width: 100,
             samples_per_pixel: 10,
             max_depth: 10,
+            filter: Filter::default(),
+            max_indirect_luminance: None,
+            crop: None,
+            job: None,
+            diagnostics_report: None,
+            stats_report: None,
+            time_heatmap: None,
+            cost_heatmap: None,
             background: Environment {
                 texture: Arc::new(SolidColor::new(Color::BLACK)),
             },
@@ -89,18 +192,33 @@ impl Default for Camera {
             vec_up: Vec3::new(0.0, 1.0, 0.0),
             defocus_angle_in_degrees: 0.0,
             focus_distance: 10.0,
+            projection: Projection::Perspective,
+            aperture: Aperture::Circle,
+            cat_eye: 0.0,
+            tilt_in_degrees: (0.0, 0.0),
+            shift: (0.0, 0.0),
+            lens: None,
+            exposure: Exposure::default(),
+            white_balance_in_kelvin: None,
             toon_map: ToonMap::None,
+            post_effects: Vec::new(),
+            outline: None,
             image_height: Default::default(),
+            vertical_fov: Default::default(),
             sqrt_spp: Default::default(),
             recip_sqrt_spp: Default::default(),
             center: Default::default(),
             pixel00_loc: Default::default(),
             pixel_delta_u: Default::default(),
             pixel_delta_v: Default::default(),
-            pixel_sample_scale: Default::default(),
             camera_axis: Default::default(),
             defocus_disk_u: Default::default(),
             defocus_disk_v: Default::default(),
+            defocus_radius: Default::default(),
+            focus_plane_normal: Default::default(),
+            diagnostics: Default::default(),
+            stats: Default::default(),
+            heatmap: Default::default(),
         }
     }
 }
@@ -145,7 +263,19 @@ impl Camera {
         let reader = BufReader::new(file);
         let params: CameraParams = serde_json::from_reader(reader)?;
 
-        Ok(Camera {
+        Ok(Camera::from_params(params))
+    }
+
+    // 从 JSON 值构建相机，格式与 from_json 读取的文件相同
+    pub fn from_json_value(value: serde_json::Value) -> Result<Camera, Box<dyn std::error::Error>> {
+        let params: CameraParams = serde_json::from_value(value)?;
+        Ok(Camera::from_params(params))
+    }
+
+    fn from_params(params: CameraParams) -> Camera {
+        let defaults = Camera::default();
+
+        Camera {
             aspect_ratio: params.aspect_ratio,
             image_width: params.image_width,
             vertical_fov_in_degrees: params.vertical_fov_in_degrees,
@@ -154,19 +284,249 @@ impl Camera {
             vec_up: params.vec_up,
             defocus_angle_in_degrees: params.defocus_angle_in_degrees,
             focus_distance: params.focus_distance,
-            ..Default::default()
-        })
+            projection: params.projection,
+            lens: params.lens,
+            aperture: params.aperture,
+            cat_eye: params.cat_eye,
+            tilt_in_degrees: params.tilt_in_degrees,
+            shift: params.shift,
+            post_effects: params.post_effects,
+            outline: params.outline,
+            filter: params.filter,
+            crop: params.crop,
+            samples_per_pixel: params
+                .samples_per_pixel
+                .unwrap_or(defaults.samples_per_pixel),
+            max_depth: params.max_depth.unwrap_or(defaults.max_depth),
+            ..defaults
+        }
+    }
+
+    // 最近一次渲染中被丢弃的异常样本
+    pub fn diagnostics(&self) -> &Diagnostics {
+        &self.diagnostics
+    }
+
+    // 最近一次渲染的统计信息
+    pub fn stats(&self) -> &RenderStats {
+        &self.stats
+    }
+
+    // 最近一次渲染中每个像素的时间与开销，范围与返回的帧缓冲相同
+    pub fn heatmap(&self) -> &Heatmap {
+        &self.heatmap
+    }
+
+    // 完整图像的宽高，与裁剪无关
+    pub fn image_size(&self) -> (u32, u32) {
+        let height = (self.image_width as f64 / self.aspect_ratio) as u32;
+        (self.image_width, height.max(1))
     }
 
     pub fn render(&mut self, world: &dyn Hittable, lights: Option<&dyn Hittable>) -> RgbImage {
+        if let Err(e) = self.check_crop() {
+            panic!("Cannot render the crop window: {e}");
+        }
+        let buffer = match self.job.clone() {
+            Some(job) => self
+                .run_job(&job, world, lights)
+                .expect("Cannot run the render job"),
+            None => self.render_buffer(world, lights),
+        };
+        self.finish(&buffer, world)
+    }
+
+    // 显影并绘制描边，buffer 覆盖裁剪区域，未裁剪时为整幅图像
+    pub fn finish(&mut self, buffer: &[Color], world: &dyn Hittable) -> RgbImage {
+        let mut img = self.develop(buffer);
+
+        if let Some(outline) = self.outline.clone() {
+            let gbuffer = self.render_gbuffer(world, outline.supersampling);
+            outline.draw(&mut img, &gbuffer);
+        }
+
+        img
+    }
+
+    // 执行分布式渲染任务，工作进程返回本进程渲染的部分，合并时返回完整的帧缓冲
+    pub fn run_job(
+        &mut self,
+        job: &RenderJob,
+        world: &dyn Hittable,
+        lights: Option<&dyn Hittable>,
+    ) -> Result<Vec<Color>, Box<dyn std::error::Error>> {
+        self.initilize();
+
+        match job {
+            RenderJob::Worker {
+                split,
+                index,
+                output,
+            } => {
+                if *index >= split.count() {
+                    return Err(format!("Job index {index} out of range").into());
+                }
+                // 块数多于行数时部分工作进程没有可渲染的行
+                if let Split::Tiles { count } = *split {
+                    if count > self.image_height {
+                        return Err(format!(
+                            "Cannot split {} rows into {count} tiles",
+                            self.image_height
+                        )
+                        .into());
+                    }
+                }
+
+                // 场景已用 SCENE_SEED 构建，渲染时每个工作进程换用各自的种子
+                let seed = *index as u64 + 1;
+                match *split {
+                    Split::Tiles { count } => {
+                        let (y0, y1) = Split::tile_rows(count, *index, self.image_height);
+                        self.crop = Some(CropWindow::Pixels {
+                            x: 0,
+                            y: y0,
+                            width: self.image_width,
+                            height: y1 - y0,
+                        });
+                    }
+                    Split::Samples { count } => {
+                        self.samples_per_pixel = self.samples_per_pixel.div_ceil(count as usize);
+                    }
+                }
+                Random::seed(seed);
+
+                let pixels = self.render_buffer(world, lights);
+                let (x0, y0, x1, y1) = self.pixel_bounds();
+                let part = PartialResult {
+                    image_width: self.image_width,
+                    image_height: self.image_height,
+                    x0,
+                    y0,
+                    width: x1 - x0,
+                    height: y1 - y0,
+                    samples_per_pixel: (self.sqrt_spp * self.sqrt_spp) as u64,
+                    seed,
+                    pixels,
+                };
+                part.save(output)?;
+
+                Ok(part.pixels)
+            }
+            RenderJob::Merge { inputs } => {
+                let parts = inputs
+                    .iter()
+                    .map(|path| PartialResult::load(path))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let (width, height, pixels) = PartialResult::merge(&parts)?;
+                if (width, height) != (self.image_width, self.image_height) {
+                    return Err("Partial results do not match the camera resolution".into());
+                }
+
+                self.crop = None;
+                Ok(pixels)
+            }
+        }
+    }
+
+    // 渲染超采样的几何缓冲，每个像素每个方向 supersampling 个采样，忽略景深与运动模糊
+    pub fn render_gbuffer(&mut self, world: &dyn Hittable, supersampling: u32) -> GBuffer {
         self.initilize();
 
-        let mut img: RgbImage = ImageBuffer::new(self.image_width, self.image_height);
+        let s = supersampling.max(1);
+        let (x0, y0, x1, y1) = self.pixel_bounds();
+        let (width, height) = ((x1 - x0) * s, (y1 - y0) * s);
+        let samples = (0..width * height)
+            .into_par_iter()
+            .map(|index| {
+                let (x, y) = (x0 * s + index % width, y0 * s + index / width);
+                let offset = Vec3::new(
+                    ((x % s) as f64 + 0.5) / s as f64 - 0.5,
+                    ((y % s) as f64 + 0.5) / s as f64 - 0.5,
+                    0.0,
+                );
+                let ray = self.ray_at(x / s, y / s, offset, 0.0, false)?;
+                self.first_surface(&ray, world)
+            })
+            .collect();
+
+        GBuffer {
+            width: width as usize,
+            height: height as usize,
+            samples,
+        }
+    }
+
+    // 沿光线找到第一个表面，穿过雾等参与介质
+    fn first_surface(&self, r: &Ray, world: &dyn Hittable) -> Option<GSample> {
+        const MAX_MEDIUM_HITS: usize = 16;
+
+        let length = r.direction().length();
+        let mut ray = *r;
+        let mut distance = 0.0;
+        for _ in 0..MAX_MEDIUM_HITS {
+            let rec = world.hit(&ray, &Interval::from_range(1e-8..f64::INFINITY))?;
+            distance += rec.t * length;
+            if !rec.mat.is_medium() {
+                return Some(GSample {
+                    position: rec.p,
+                    normal: *rec.normal.as_inner(),
+                    distance,
+                    object_id: rec.object_id,
+                    material_id: rec.mat.id(),
+                });
+            }
+            ray = Ray::new_with_time(rec.p, *ray.direction(), *ray.time());
+        }
+
+        None
+    }
+
+    // 渲染得到线性颜色的浮点帧缓冲，按行优先存储
+    pub fn render_buffer(
+        &mut self,
+        world: &dyn Hittable,
+        lights: Option<&dyn Hittable>,
+    ) -> Vec<Color> {
+        let pixels = self.render_region(world, lights);
+        self.save_stats();
+
+        if self.diagnostics.total() > 0 {
+            eprintln!("{}", self.diagnostics);
+        }
+        if let Some(path) = &self.diagnostics_report {
+            if let Err(e) = self.diagnostics.save_json(path) {
+                eprintln!("Cannot save the diagnostics report: {e}");
+            }
+        }
+
+        pixels
+    }
+
+    // 渲染裁剪区域或整幅图像的浮点帧缓冲，不输出统计与诊断报告，用于逐块渲染
+    pub fn render_region(
+        &mut self,
+        world: &dyn Hittable,
+        lights: Option<&dyn Hittable>,
+    ) -> Vec<Color> {
+        self.initilize();
+
+        // 裁剪区域向外扩展滤波器半径，使边缘像素与完整渲染时得到相同的样本
+        let (x0, y0, x1, y1) = self.pixel_bounds();
+        let pad = self.filter.radius().ceil() as u32;
+        let (fx0, fy0) = (x0.saturating_sub(pad), y0.saturating_sub(pad));
+        let (fx1, fy1) = (
+            (x1 + pad).min(self.image_width),
+            (y1 + pad).min(self.image_height),
+        );
+        let (film_width, film_height) = (fx1 - fx0, fy1 - fy0);
+
+        let film = Film::new(film_width, film_height, self.filter);
+        self.diagnostics.clear();
 
         let progress = if option_env!("CI").unwrap_or_default() == "true" {
             ProgressBar::hidden()
         } else {
-            let pb = ProgressBar::new((self.image_height * self.image_width) as u64);
+            let pb = ProgressBar::new((film_width * film_height) as u64);
             pb.set_style(
                 ProgressStyle::default_bar()
                     .template("[{elapsed_precise}] [{wide_bar}] {pos}/{len} ({eta_precise})")
@@ -175,28 +535,165 @@ impl Camera {
             pb
         };
 
+        let _collecting = self.collects_stats().then(stats::collect);
+        let start = Instant::now();
+        let heat: Vec<(AtomicU64, AtomicU64)> = (0..film_width * film_height)
+            .map(|_| Default::default())
+            .collect();
         let counter = Arc::new(AtomicUsize::new(0));
-        img.enumerate_pixels_mut()
-            .par_bridge()
-            .for_each(|(i, j, pixel)| {
-                let mut pixel_color = Color::BLACK;
+        let mut stats = (0..film_width * film_height)
+            .into_par_iter()
+            .fold(RenderStats::default, |mut stats, index| {
+                let i = fx0 + index % film_width;
+                let j = fy0 + index / film_width;
+                let pixel_start = Instant::now();
+                stats::take_local();
+
                 for s_i in 0..self.sqrt_spp {
                     for s_j in 0..self.sqrt_spp {
-                        pixel_color += self.ray_color(
-                            &self.get_ray(i, j, s_i, s_j),
-                            self.max_depth,
-                            world,
-                            lights,
+                        let offset = self.sample_square_stratified(s_i, s_j);
+                        let sample = SampleId {
+                            pixel: (i, j),
+                            index: s_j * self.sqrt_spp + s_i,
+                        };
+                        let sample_color = match self.ray_at(i, j, offset, Random::f64(), true) {
+                            Some(ray) => {
+                                stats::count(Counter::CameraRays);
+                                self.ray_color(&ray, self.max_depth, world, lights, sample)
+                            }
+                            None => Color::BLACK,
+                        };
+                        film.add_sample(
+                            (i - fx0) as f64 + 0.5 + offset.x(),
+                            (j - fy0) as f64 + 0.5 + offset.y(),
+                            sample_color,
                         );
                     }
                 }
-                let pixel_color = pixel_color * self.pixel_sample_scale;
-                *pixel = image::Rgb(pixel_color.to_rgb(&self.toon_map));
+                let local = stats::take_local();
+                heat[index as usize]
+                    .0
+                    .store(pixel_start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+                heat[index as usize]
+                    .1
+                    .store(local.cost(), Ordering::Relaxed);
+                stats.add(local);
+
                 let prev = counter.fetch_add(1, Ordering::SeqCst);
                 progress.set_position((prev + 1) as u64);
-            });
+                stats
+            })
+            .reduce(RenderStats::default, RenderStats::merge);
 
         progress.finish();
+        stats.seconds = start.elapsed().as_secs_f64();
+        self.stats = stats;
+        let region = |j: u32| (x0..x1).map(move |i| ((j - fy0) * film_width + (i - fx0)) as usize);
+        self.heatmap = Heatmap {
+            width: x1 - x0,
+            height: y1 - y0,
+            pixels: (y0..y1)
+                .flat_map(region)
+                .map(|index| PixelCost {
+                    time: Duration::from_nanos(heat[index].0.load(Ordering::Relaxed)),
+                    cost: heat[index].1.load(Ordering::Relaxed),
+                })
+                .collect(),
+        };
+
+        let pixels = film.resolve();
+        (y0..y1)
+            .flat_map(region)
+            .map(|index| pixels[index])
+            .collect()
+    }
+
+    // 只有请求了统计报告或开销热力图时才计数
+    fn collects_stats(&self) -> bool {
+        self.stats_report.is_some() || self.cost_heatmap.is_some()
+    }
+
+    fn save_stats(&self) {
+        if let Some(path) = &self.stats_report {
+            println!("{}", self.stats);
+            if let Err(e) = self.stats.save_json(path) {
+                eprintln!("Cannot save the render statistics: {e}");
+            }
+        }
+        for (path, kind) in [
+            (&self.time_heatmap, HeatmapKind::Time),
+            (&self.cost_heatmap, HeatmapKind::Cost),
+        ] {
+            if let Some(path) = path {
+                if let Err(e) = self.heatmap.save(path, kind) {
+                    eprintln!("Cannot save the heatmap: {e}");
+                }
+            }
+        }
+    }
+
+    // 实际渲染的像素范围 [x0, x1) x [y0, y1)，未设置裁剪时为整幅图像
+    fn pixel_bounds(&self) -> (u32, u32, u32, u32) {
+        match &self.crop {
+            Some(crop) => crop.pixel_bounds(self.image_width, self.image_height),
+            None => (0, 0, self.image_width, self.image_height),
+        }
+    }
+
+    // 裁剪区域的结果要与完整渲染的对应部分一致，才能贴回完整图像
+    // 自动曝光、描边与读取周围像素的后期效果依赖裁剪区域之外的像素，不能与裁剪同时使用
+    pub fn check_crop(&self) -> Result<(), String> {
+        if self.crop.is_none() {
+            return Ok(());
+        }
+        self.check_local_develop()
+    }
+
+    // 显影结果是否只取决于各像素本身（及其在画面中的位置），满足时可以逐块显影
+    pub fn check_local_develop(&self) -> Result<(), String> {
+        if matches!(self.exposure, Exposure::Auto { .. }) {
+            return Err("auto exposure depends on the whole frame".to_owned());
+        }
+        if self.outline.is_some() {
+            return Err("the outline depends on neighbouring pixels".to_owned());
+        }
+        if let Some(effect) = self.post_effects.iter().find(|e| e.reads_neighbours()) {
+            return Err(format!("{effect:?} depends on neighbouring pixels"));
+        }
+        Ok(())
+    }
+
+    // 对浮点帧缓冲依次进行曝光、白平衡、后期效果与色调映射，得到最终图像
+    // 设置裁剪时图像只包含裁剪区域，位置相关的效果仍以完整画面计算
+    pub fn develop(&self, buffer: &[Color]) -> RgbImage {
+        let (x0, y0, x1, y1) = self.pixel_bounds();
+        let (width, height) = (x1 - x0, y1 - y0);
+        let region = Region {
+            x0,
+            y0,
+            width,
+            height,
+            frame_width: self.image_width,
+            frame_height: self.image_height,
+        };
+
+        let exposure = self.exposure.scale(buffer);
+        let gains = self
+            .white_balance_in_kelvin
+            .map_or(Color::WHITE, Color::white_balance_gains);
+        let scale = exposure * gains;
+
+        let mut exposed: Vec<Color> = buffer.par_iter().map(|color| color * scale).collect();
+        for effect in &self.post_effects {
+            effect.apply(&mut exposed, region);
+        }
+
+        let mut img: RgbImage = ImageBuffer::new(width, height);
+        img.par_chunks_mut(3)
+            .zip(exposed.par_iter())
+            .for_each(|(pixel, color)| {
+                pixel.copy_from_slice(&color.to_rgb(&self.toon_map));
+            });
 
         img
     }
@@ -210,12 +707,17 @@ impl Camera {
         };
 
         self.sqrt_spp = f64::sqrt(self.samples_per_pixel as f64) as u32;
-        self.pixel_sample_scale = 1.0 / (self.sqrt_spp * self.sqrt_spp) as f64;
         self.recip_sqrt_spp = 1.0 / self.sqrt_spp as f64;
 
         self.center = self.look_from;
 
-        let theta = self.vertical_fov_in_degrees.to_radians();
+        self.vertical_fov = match &self.lens {
+            Some(lens) => lens.vertical_fov_in_degrees(),
+            None => self.vertical_fov_in_degrees,
+        }
+        .to_radians();
+
+        let theta = self.vertical_fov;
         let h = f64::tan(theta / 2.0);
         let viewport_height: f64 = 2.0 * h * self.focus_distance;
         let viewport_width = viewport_height * (self.image_width as f64 / self.image_height as f64);
@@ -235,29 +737,135 @@ impl Camera {
         let viewport_upper_left = self.center
             - self.focus_distance * self.camera_axis.2.as_inner()
             - viewport_u / 2.0
-            - viewport_v / 2.0;
+            - viewport_v / 2.0
+            + self.shift.0 * viewport_u
+            - self.shift.1 * viewport_v;
         self.pixel00_loc = viewport_upper_left + 0.5 * (self.pixel_delta_u + self.pixel_delta_v);
 
-        let defocus_radius =
-            self.focus_distance * f64::tan((self.defocus_angle_in_degrees / 2.0).to_radians());
-        self.defocus_disk_u = self.camera_axis.0.as_inner() * defocus_radius;
-        self.defocus_disk_v = self.camera_axis.1.as_inner() * defocus_radius;
-    }
-
-    fn get_ray(&self, i: u32, j: u32, s_i: u32, s_j: u32) -> Ray {
-        let offset = self.sample_square_stratified(s_i, s_j);
-        let pixel_sample = self.pixel00_loc
-            + ((i as f64 + offset.x()) * self.pixel_delta_u)
-            + ((j as f64 + offset.y()) * self.pixel_delta_v);
-        let ray_origin = if self.defocus_angle_in_degrees <= 0.0 {
-            self.center
+        self.defocus_radius = if let Some(lens) = &self.lens {
+            lens.aperture_radius()
         } else {
-            self.defocus_disk_sample()
+            self.focus_distance * f64::tan((self.defocus_angle_in_degrees / 2.0).to_radians())
         };
-        let ray_direction = pixel_sample - ray_origin;
-        let ray_time = Random::f64();
+        self.defocus_disk_u = self.camera_axis.0.as_inner() * self.defocus_radius;
+        self.defocus_disk_v = self.camera_axis.1.as_inner() * self.defocus_radius;
+
+        let tilt =
+            Quaternion::from_axis_angle(*self.camera_axis.0.as_inner(), self.tilt_in_degrees.0)
+                * Quaternion::from_axis_angle(
+                    *self.camera_axis.1.as_inner(),
+                    self.tilt_in_degrees.1,
+                );
+        self.focus_plane_normal =
+            UnitVec3::from_vec3_raw(tilt.rotate_vector(*self.camera_axis.2.as_inner()));
+    }
+
+    // offset 为相对像素中心的偏移，defocus 为 false 时按针孔相机处理
+    // 对于鱼眼等投影，视场之外的像素没有对应的光线
+    fn ray_at(&self, i: u32, j: u32, offset: Vec3, ray_time: f64, defocus: bool) -> Option<Ray> {
+        match self.projection {
+            Projection::Perspective => {
+                let pixel_sample = self.pixel00_loc
+                    + ((i as f64 + offset.x()) * self.pixel_delta_u)
+                    + ((j as f64 + offset.y()) * self.pixel_delta_v);
+                if !defocus || self.defocus_radius <= 0.0 {
+                    return Some(Ray::new_with_time(
+                        self.center,
+                        pixel_sample - self.center,
+                        ray_time,
+                    ));
+                }
+
+                let focus_point = self.focus_point(pixel_sample);
+                let screen = (
+                    2.0 * (i as f64 + 0.5) / self.image_width as f64 - 1.0,
+                    1.0 - 2.0 * (j as f64 + 0.5) / self.image_height as f64,
+                );
+                let ray_origin = self.defocus_disk_sample(screen)?;
+
+                Some(Ray::new_with_time(
+                    ray_origin,
+                    focus_point - ray_origin,
+                    ray_time,
+                ))
+            }
+            Projection::Orthographic => {
+                let pixel_sample = self.pixel00_loc
+                    + ((i as f64 + offset.x()) * self.pixel_delta_u)
+                    + ((j as f64 + offset.y()) * self.pixel_delta_v);
+                let w = self.camera_axis.2.as_inner();
+                let ray_origin = pixel_sample + self.focus_distance * w;
+
+                Some(Ray::new_with_time(ray_origin, -w, ray_time))
+            }
+            _ => {
+                let px = (i as f64 + 0.5 + offset.x()) / self.image_width as f64;
+                let py = (j as f64 + 0.5 + offset.y()) / self.image_height as f64;
+                let local = self.panoramic_direction(px, py)?;
+                let direction = local.x() * self.camera_axis.0.as_inner()
+                    + local.y() * self.camera_axis.1.as_inner()
+                    + local.z() * self.camera_axis.2.as_inner();
+
+                Some(Ray::new_with_time(self.center, direction, ray_time))
+            }
+        }
+    }
+
+    // 由图像上的归一化坐标（左上角为原点）计算相机坐标系下的方向，相机朝向 -z
+    fn panoramic_direction(&self, px: f64, py: f64) -> Option<Vec3> {
+        let aspect = self.image_width as f64 / self.image_height as f64;
+        let half_fov = self.vertical_fov / 2.0;
+
+        match self.projection {
+            Projection::FisheyeEquidistant | Projection::FisheyeEquisolid => {
+                // 以半个图像高度为单位的极坐标
+                let x = (2.0 * px - 1.0) * aspect;
+                let y = 1.0 - 2.0 * py;
+                let r = (x * x + y * y).sqrt();
+
+                let theta = if self.projection == Projection::FisheyeEquidistant {
+                    r * half_fov
+                } else {
+                    let s = r * (half_fov / 2.0).sin();
+                    if s > 1.0 {
+                        return None;
+                    }
+                    2.0 * s.asin()
+                };
+                if theta > PI {
+                    return None;
+                }
+                if r == 0.0 {
+                    return Some(Vec3::new(0.0, 0.0, -1.0));
+                }
+
+                let sin_theta = theta.sin();
+                Some(Vec3::new(
+                    sin_theta * x / r,
+                    sin_theta * y / r,
+                    -theta.cos(),
+                ))
+            }
+            Projection::Equirectangular => {
+                // 与 Environment 的贴图坐标一致，渲染结果可以直接作为环境贴图
+                let phi = px * 2.0 * PI;
+                let theta = (1.0 - py) * PI;
+                let sin_theta = theta.sin();
 
-        Ray::new_with_time(ray_origin, ray_direction, ray_time)
+                Some(Vec3::new(
+                    sin_theta * (PI - phi).cos(),
+                    -theta.cos(),
+                    -sin_theta * (PI - phi).sin(),
+                ))
+            }
+            Projection::CylindricalPanorama => {
+                let phi = (px - 0.5) * 2.0 * PI;
+                let h = (1.0 - 2.0 * py) * half_fov.tan();
+
+                Some(Vec3::new(phi.sin(), h, -phi.cos()))
+            }
+            Projection::Perspective | Projection::Orthographic => None,
+        }
     }
 
     fn sample_square_stratified(&self, s_i: u32, s_j: u32) -> Vec3 {
@@ -267,9 +875,44 @@ impl Camera {
         Vec3::new(px, py, 0.0)
     }
 
-    fn defocus_disk_sample(&self) -> Point3 {
-        let p = Vec3::random_in_unit_disk();
-        self.center + (p[0] * self.defocus_disk_u) + (p[1] * self.defocus_disk_v)
+    // 中心光线与（可能倾斜的）焦平面的交点
+    fn focus_point(&self, pixel_sample: Point3) -> Point3 {
+        let direction = pixel_sample - self.center;
+        let plane_point = self.center - self.focus_distance * self.camera_axis.2.as_inner();
+        let denom = direction.dot(&self.focus_plane_normal);
+        if denom.abs() < 1e-8 {
+            return pixel_sample;
+        }
+
+        let t = (plane_point - self.center).dot(&self.focus_plane_normal) / denom;
+        self.center + t * direction
+    }
+
+    // screen 为像素在画面上的位置，范围 [-1, 1]，用于计算猫眼暗角
+    fn defocus_disk_sample(&self, screen: (f64, f64)) -> Option<Point3> {
+        let (x, y) = self.aperture.sample()?;
+
+        // 出瞳被镜筒遮挡，相当于与一个向画面中心偏移的圆求交
+        if self.cat_eye > 0.0 {
+            let dx = x + self.cat_eye * screen.0;
+            let dy = y + self.cat_eye * screen.1;
+            if dx * dx + dy * dy > 1.0 {
+                return None;
+            }
+        }
+
+        Some(self.center + (x * self.defocus_disk_u) + (y * self.defocus_disk_v))
+    }
+
+    // 对间接光照的样本限制亮度以抑制萤火虫噪点
+    fn clamp_indirect(&self, color: Color, depth: u32) -> Color {
+        match self.max_indirect_luminance {
+            Some(max) if depth < self.max_depth => {
+                let l = color.luminance();
+                if l > max { color * (max / l) } else { color }
+            }
+            _ => color,
+        }
     }
 
     fn ray_color(
@@ -278,49 +921,349 @@ impl Camera {
         depth: u32,
         world: &dyn Hittable,
         lights: Option<&dyn Hittable>,
+        sample: SampleId,
     ) -> Color {
+        // 介质内部的随机游走在这里循环，不消耗 depth，也不会因为碰撞次数过多而栈溢出
+        const MAX_WALK_STEPS: u32 = 1 << 14;
+
         if depth == 0 {
             return Color::BLACK;
         }
 
-        let Some(rec) = world.hit(r, &Interval::from_range(1e-8..f64::INFINITY)) else {
-            return self.background.value(r);
+        let mut ray = *r;
+        let mut throughput = Color::WHITE;
+        let mut radiance = Color::BLACK;
+        let mut walk_steps = 0;
+        let (color, material) = loop {
+            let Some(rec) = world.hit(&ray, &Interval::from_range(1e-8..f64::INFINITY)) else {
+                break (
+                    radiance + throughput * self.background.value(&ray),
+                    "Background",
+                );
+            };
+
+            let color_from_emission = rec.mat.emitted(&ray, &rec);
+
+            let Some(scatter_record) = rec.mat.scatter(&ray, &rec) else {
+                break (radiance + throughput * color_from_emission, rec.mat.name());
+            };
+            stats::count_scatter(rec.mat);
+
+            let color_from_scatter = match scatter_record {
+                ScatterRecord::PDF(pdf_ptr) => {
+                    let light_ptr = lights.map(|lights_hit| {
+                        Box::new(HittablePDF::new(lights_hit, rec.p, *ray.time()))
+                    });
+                    let mixed_pdf: Box<dyn PDF> = if let Some(ref light) = light_ptr {
+                        Box::new(MixturePDF::new(pdf_ptr.as_ref(), light.as_ref()))
+                    } else {
+                        pdf_ptr
+                    };
+
+                    if let Some(generate_vec) = mixed_pdf.generate() {
+                        let scattered =
+                            Ray::new_with_time(rec.p, generate_vec.into_inner(), *ray.time());
+                        let (albedo_x_pscatter, pdf_value) = mixed_pdf.value(scattered.direction());
+
+                        if pdf_value == 0.0 {
+                            self.report(IssueKind::ZeroPdf, sample, depth, rec.mat.name());
+                            Color::BLACK
+                        } else {
+                            stats::count(Counter::BounceRays);
+                            let sample_color = self.clamp_indirect(
+                                self.ray_color(&scattered, depth - 1, world, lights, sample),
+                                depth,
+                            );
+                            (albedo_x_pscatter * sample_color) / pdf_value
+                        }
+                    } else {
+                        Color::BLACK
+                    }
+                }
+                ScatterRecord::Ray((attenuation, skip_pdf_ray)) => {
+                    stats::count(Counter::BounceRays);
+                    attenuation
+                        * self.clamp_indirect(
+                            self.ray_color(&skip_pdf_ray, depth - 1, world, lights, sample),
+                            depth,
+                        )
+                }
+                ScatterRecord::Walk((weight, next)) => {
+                    walk_steps += 1;
+                    if walk_steps > MAX_WALK_STEPS {
+                        break (radiance + throughput * color_from_emission, rec.mat.name());
+                    }
+                    stats::count(Counter::BounceRays);
+                    radiance += throughput * color_from_emission;
+                    throughput = throughput * weight;
+                    ray = next;
+                    continue;
+                }
+            };
+
+            break (
+                radiance + throughput * (color_from_emission + color_from_scatter),
+                rec.mat.name(),
+            );
+        };
+
+        // 异常的样本被记录后丢弃，不再向上传播
+        if let Some(kind) = IssueKind::of(&color) {
+            self.report(kind, sample, depth, material);
+            return Color::BLACK;
+        }
+        color
+    }
+
+    fn report(&self, kind: IssueKind, sample: SampleId, depth: u32, material: &str) {
+        self.diagnostics.record(SampleIssue {
+            kind,
+            sample,
+            bounce: self.max_depth - depth,
+            material: material.to_owned(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::texture::Texture;
+
+    #[derive(Debug)]
+    struct UvTexture;
+
+    impl Texture for UvTexture {
+        fn value(&self, u: f64, v: f64, _p: &Point3) -> Color {
+            Color::new(u, v, 0.0)
+        }
+    }
+
+    #[test]
+    fn test_equirectangular_matches_environment() {
+        let mut camera = Camera::new(2.0, 200);
+        camera.projection = Projection::Equirectangular;
+        camera.initilize();
+
+        let env = Environment {
+            texture: Arc::new(UvTexture),
         };
 
-        let color_from_emission = rec.mat.emitted(r, &rec);
+        for (px, py) in [(0.25, 0.5), (0.6, 0.3), (0.9, 0.8)] {
+            let dir = camera.panoramic_direction(px, py).unwrap();
+            let uv = env.value(&Ray::new(Point3::ZERO, dir));
+            assert!((uv.x() - px).abs() < 1e-9);
+            assert!((uv.y() - (1.0 - py)).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_fisheye_center_and_edge() {
+        let mut camera = Camera::new(1.0, 100);
+        camera.vertical_fov_in_degrees = 180.0;
+        camera.projection = Projection::FisheyeEquidistant;
+        camera.initilize();
+
+        let center = camera.panoramic_direction(0.5, 0.5).unwrap();
+        assert_eq!(center, Vec3::new(0.0, 0.0, -1.0));
+
+        let edge = camera.panoramic_direction(1.0, 0.5).unwrap();
+        assert!((edge.x() - 1.0).abs() < 1e-9);
+        assert!(edge.z().abs() < 1e-9);
+
+        camera.vertical_fov_in_degrees = 270.0;
+        camera.initilize();
+        assert!(camera.panoramic_direction(0.0, 0.0).is_none());
 
-        let Some(scatter_record) = rec.mat.scatter(r, &rec) else {
-            return color_from_emission;
+        camera.projection = Projection::FisheyeEquisolid;
+        let edge = camera.panoramic_direction(1.0, 0.5).unwrap();
+        assert!((edge.x() - (135.0_f64).to_radians().sin()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_lens_params_from_json() {
+        let mut camera = Camera::from_json_value(serde_json::json!({
+            "aspect_ratio": 1.0,
+            "image_width": 10,
+            "vertical_fov_in_degrees": 40.0,
+            "look_from": [0.0, 0.0, 5.0],
+            "look_at": [0.0, 0.0, 0.0],
+            "vec_up": [0.0, 1.0, 0.0],
+            "defocus_angle_in_degrees": 0.0,
+            "focus_distance": 5.0,
+            "lens": {"focal_length_mm": 50.0, "f_stop": 2.8},
+            "aperture": {"polygon": {"blades": 6, "rotation_in_degrees": 15.0}},
+            "cat_eye": 0.5,
+            "tilt_in_degrees": [2.0, 0.0],
+            "shift": [0.1, 0.0]
+        }))
+        .unwrap();
+        assert!(matches!(
+            camera.aperture,
+            Aperture::Polygon { blades: 6, .. }
+        ));
+        assert_eq!(camera.cat_eye, 0.5);
+        assert_eq!(camera.tilt_in_degrees, (2.0, 0.0));
+        assert_eq!(camera.shift, (0.1, 0.0));
+
+        // 由镜头求出的视场不覆盖用户设置的值
+        camera.initilize();
+        let lens = camera.lens.unwrap();
+        assert_eq!(camera.vertical_fov_in_degrees, 40.0);
+        assert!((camera.vertical_fov - lens.vertical_fov_in_degrees().to_radians()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_crop_renders_region_only() {
+        let mut camera = Camera::new(2.0, 40);
+        camera.samples_per_pixel = 1;
+        camera.background.texture = Arc::new(UvTexture);
+        camera.crop = Some(CropWindow::Pixels {
+            x: 10,
+            y: 5,
+            width: 8,
+            height: 4,
+        });
+
+        let world = crate::hits::Hittables::default();
+        let buffer = camera.render_buffer(&world, None);
+        assert_eq!(buffer.len(), 8 * 4);
+        assert_eq!(camera.develop(&buffer).dimensions(), (8, 4));
+
+        // 依赖整幅画面的效果不能用于裁剪
+        assert!(camera.check_crop().is_ok());
+        camera
+            .post_effects
+            .push(PostEffect::parse("vignette").unwrap());
+        assert!(camera.check_crop().is_ok());
+        camera
+            .post_effects
+            .push(PostEffect::parse("bloom").unwrap());
+        assert!(camera.check_crop().is_err());
+        camera.post_effects.clear();
+        camera.exposure = Exposure::Auto { key: 0.18 };
+        assert!(camera.check_crop().is_err());
+    }
+
+    #[test]
+    fn test_subsurface_white_furnace() {
+        let mut camera = Camera::new(1.0, 1);
+        // 边界上的 Schlick 近似在掠射角仍有少量反射，每次反射消耗一层 depth
+        camera.max_depth = 8;
+        camera.background.texture = Arc::new(SolidColor::new(Color::WHITE));
+
+        // 反照率为 1 的介质不吸收能量，介质内部的大量碰撞不应受 max_depth 限制
+        let boundary = crate::shapes::sphere::Sphere::new(
+            Point3::ZERO,
+            1.0,
+            Arc::new(crate::material::Dielectric::new(
+                Arc::new(SolidColor::new(Color::WHITE)),
+                1.0,
+            )),
+        );
+        let world = crate::volume::SubsurfaceMedium::new(
+            Box::new(boundary),
+            Color::WHITE,
+            Color::new(0.1, 0.1, 0.1),
+        );
+
+        let ray = Ray::new(Point3::new(0.0, 0.0, 3.0), Vec3::new(0.0, 0.0, -1.0));
+        let n = 500;
+        let sum = (0..n).fold(Color::BLACK, |sum, index| {
+            let sample = SampleId {
+                pixel: (0, 0),
+                index,
+            };
+            sum + camera.ray_color(&ray, camera.max_depth, &world, None, sample)
+        });
+        let mean = sum / n as f64;
+        assert!(mean.e().iter().all(|c| (c - 1.0).abs() < 0.01), "{mean:?}");
+    }
+
+    #[test]
+    fn test_non_finite_background_is_discarded() {
+        let mut camera = Camera::new(1.0, 1);
+        camera.background.texture = Arc::new(SolidColor::new(Color::new(f64::NAN, 1.0, 1.0)));
+        let world = crate::hits::Hittables::default();
+
+        let ray = Ray::new(Point3::ZERO, Vec3::new(0.0, 0.0, -1.0));
+        let sample = SampleId {
+            pixel: (0, 0),
+            index: 0,
         };
+        let color = camera.ray_color(&ray, camera.max_depth, &world, None, sample);
+        assert_eq!(color.e(), [0.0, 0.0, 0.0]);
+        assert_eq!(camera.diagnostics().total(), 1);
+    }
 
-        let color_from_scatter = match scatter_record {
-            ScatterRecord::PDF(pdf_ptr) => {
-                let light_ptr =
-                    lights.map(|lights_hit| Box::new(HittablePDF::new(lights_hit, rec.p)));
-                let mixed_pdf: Box<dyn PDF> = if let Some(ref light) = light_ptr {
-                    Box::new(MixturePDF::new(pdf_ptr.as_ref(), light.as_ref()))
-                } else {
-                    pdf_ptr
-                };
+    #[test]
+    fn test_render_stats() {
+        let mut camera = Camera::new(1.0, 8);
+        camera.samples_per_pixel = 4;
+        camera.max_depth = 3;
+        camera.look_from = Point3::new(0.0, 0.0, 3.0);
+        camera.look_at = Point3::new(0.0, 0.0, 0.0);
+        camera.vertical_fov_in_degrees = 10.0;
 
-                if let Some(generate_vec) = mixed_pdf.generate() {
-                    let scattered = Ray::new_with_time(rec.p, generate_vec.into_inner(), *r.time());
-                    let (albedo_x_pscatter, pdf_value) = mixed_pdf.value(scattered.direction());
-                    assert_ne!(pdf_value, 0.0);
+        let mat = Arc::new(crate::material::Lambertian::new(Arc::new(SolidColor::new(
+            Color::new(0.5, 0.5, 0.5),
+        ))));
+        let world = crate::bvh::BVH::from_vec(vec![Box::new(crate::shapes::sphere::Sphere::new(
+            Point3::new(0.0, 0.0, 0.0),
+            1.0,
+            mat,
+        ))]);
+        let collecting = stats::collect();
+        camera.render_buffer(&world, None);
+        drop(collecting);
 
-                    let sample_color = self.ray_color(&scattered, depth - 1, world, lights);
-                    (albedo_x_pscatter * sample_color) / pdf_value
-                } else {
-                    Color::BLACK
-                }
-            }
-            ScatterRecord::Ray((attenuation, skip_pdf_ray)) => {
-                attenuation * self.ray_color(&skip_pdf_ray, depth - 1, world, lights)
-            }
+        let stats = camera.stats();
+        assert_eq!(stats.camera_rays, 8 * 8 * 4);
+        // 画面全部被球覆盖，每条相机光线至少散射一次
+        assert!(stats.bounce_rays >= stats.camera_rays);
+        assert!(stats.average_path_length() >= 2.0);
+        assert!(stats.bvh_nodes_visited >= stats.camera_rays);
+        assert!(stats.primitive_tests <= stats.bvh_nodes_visited);
+        assert_eq!(stats.light_pdf_evaluations, 0);
+        assert_eq!(stats.scatters_by_material.len(), 1);
+        assert_eq!(camera.heatmap().pixels.len(), 8 * 8);
+        assert!(camera.heatmap().pixels.iter().all(|p| p.cost > 0));
+    }
+
+    #[test]
+    fn test_tile_jobs_merge_to_full_image() {
+        let dir = env::temp_dir().join(format!("raytracer-jobs-{}", std::process::id()));
+        let world = crate::hits::Hittables::default();
+        let inputs: Vec<PathBuf> = (0..3).map(|i| dir.join(format!("{i}.rtp"))).collect();
+
+        for (index, output) in inputs.iter().enumerate() {
+            let mut camera = Camera::new(2.0, 20);
+            camera.samples_per_pixel = 1;
+            camera.background.texture = Arc::new(UvTexture);
+            let job = RenderJob::Worker {
+                split: Split::Tiles { count: 3 },
+                index: index as u32,
+                output: output.clone(),
+            };
+            let pixels = camera.run_job(&job, &world, None).unwrap();
+            assert!(pixels.len() < 20 * 10);
+        }
+
+        let mut camera = Camera::new(2.0, 20);
+        let job = RenderJob::Merge { inputs };
+        let pixels = camera.run_job(&job, &world, None).unwrap();
+        assert_eq!(pixels.len(), 20 * 10);
+        // 背景的 v 坐标自上而下递减
+        assert!(pixels[0].y() > pixels[20 * 9].y());
+
+        // 块数多于行数时拒绝任务，而不是让多出的工作进程重复渲染
+        let job = RenderJob::Worker {
+            split: Split::Tiles { count: 11 },
+            index: 10,
+            output: dir.join("10.rtp"),
         };
+        assert!(camera.run_job(&job, &world, None).is_err());
 
-        let ret = color_from_emission + color_from_scatter;
-        assert!(!ret.e().iter().any(|x| x.is_nan()));
-        ret
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
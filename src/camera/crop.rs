@@ -0,0 +1,142 @@
+use image::{RgbImage, imageops};
+use serde::Deserialize;
+
+// 只渲染图像中的一块区域，原点在左上角
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CropWindow {
+    // 以图像宽高为单位的坐标，范围 [0, 1]
+    Normalized {
+        x0: f64,
+        y0: f64,
+        x1: f64,
+        y1: f64,
+    },
+    Pixels {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    },
+}
+
+impl CropWindow {
+    // 从命令行参数解析，"x0,y0,x1,y1" 为归一化坐标，加上前缀 "px:" 时为 "x,y,width,height" 像素坐标
+    pub fn parse(spec: &str) -> Result<CropWindow, Box<dyn std::error::Error>> {
+        let (pixels, values) = match spec.strip_prefix("px:") {
+            Some(values) => (true, values),
+            None => (false, spec),
+        };
+        let values: Vec<&str> = values.split(',').map(str::trim).collect();
+        if values.len() != 4 {
+            return Err(format!("Crop window needs 4 values: {spec}").into());
+        }
+
+        if pixels {
+            let v: Vec<u32> = values.iter().map(|s| s.parse()).collect::<Result<_, _>>()?;
+            Ok(CropWindow::Pixels {
+                x: v[0],
+                y: v[1],
+                width: v[2],
+                height: v[3],
+            })
+        } else {
+            let v: Vec<f64> = values.iter().map(|s| s.parse()).collect::<Result<_, _>>()?;
+            Ok(CropWindow::Normalized {
+                x0: v[0],
+                y0: v[1],
+                x1: v[2],
+                y1: v[3],
+            })
+        }
+    }
+
+    // 在 width x height 的图像中对应的像素范围 [x0, x1) x [y0, y1)，至少包含一个像素
+    pub fn pixel_bounds(&self, width: u32, height: u32) -> (u32, u32, u32, u32) {
+        let (x0, y0, x1, y1) = match *self {
+            CropWindow::Normalized { x0, y0, x1, y1 } => {
+                let to_pixel = |v: f64, size: u32, round: fn(f64) -> f64| {
+                    round(v.clamp(0.0, 1.0) * size as f64) as u32
+                };
+                (
+                    to_pixel(x0.min(x1), width, f64::floor),
+                    to_pixel(y0.min(y1), height, f64::floor),
+                    to_pixel(x0.max(x1), width, f64::ceil),
+                    to_pixel(y0.max(y1), height, f64::ceil),
+                )
+            }
+            CropWindow::Pixels {
+                x,
+                y,
+                width: w,
+                height: h,
+            } => (x, y, x.saturating_add(w), y.saturating_add(h)),
+        };
+
+        let x0 = x0.min(width - 1);
+        let y0 = y0.min(height - 1);
+        (x0, y0, x1.clamp(x0 + 1, width), y1.clamp(y0 + 1, height))
+    }
+
+    // 将裁剪区域的渲染结果贴回之前渲染的完整图像，image_size 为相机渲染的完整图像宽高
+    pub fn paste(
+        &self,
+        base: &mut RgbImage,
+        region: &RgbImage,
+        image_size: (u32, u32),
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if base.dimensions() != image_size {
+            return Err(format!(
+                "The image to paste into is {}x{}, but the camera renders {}x{}",
+                base.width(),
+                base.height(),
+                image_size.0,
+                image_size.1
+            )
+            .into());
+        }
+        let (x0, y0, _, _) = self.pixel_bounds(base.width(), base.height());
+        imageops::replace(base, region, x0 as i64, y0 as i64);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pixel_bounds() {
+        let crop = CropWindow::parse("0.25,0.5,0.5,1.0").unwrap();
+        assert_eq!(crop.pixel_bounds(100, 50), (25, 25, 50, 50));
+
+        let crop = CropWindow::parse("px:90,10,30,5").unwrap();
+        assert_eq!(crop.pixel_bounds(100, 50), (90, 10, 100, 15));
+
+        // 空的区域至少保留一个像素
+        let crop = CropWindow::parse("1,1,1,1").unwrap();
+        assert_eq!(crop.pixel_bounds(100, 50), (99, 49, 100, 50));
+
+        assert!(CropWindow::parse("0,0,1").is_err());
+    }
+
+    #[test]
+    fn test_paste() {
+        let mut base = RgbImage::new(4, 4);
+        let region = RgbImage::from_pixel(2, 1, image::Rgb([255, 0, 0]));
+        let crop = CropWindow::Pixels {
+            x: 1,
+            y: 2,
+            width: 2,
+            height: 1,
+        };
+        crop.paste(&mut base, &region, (4, 4)).unwrap();
+        assert_eq!(base.get_pixel(1, 2).0, [255, 0, 0]);
+        assert_eq!(base.get_pixel(2, 2).0, [255, 0, 0]);
+        assert_eq!(base.get_pixel(0, 2).0, [0, 0, 0]);
+        assert_eq!(base.get_pixel(1, 1).0, [0, 0, 0]);
+
+        // 与相机图像大小不同的底图会使像素坐标错位
+        assert!(crop.paste(&mut base, &region, (8, 4)).is_err());
+    }
+}
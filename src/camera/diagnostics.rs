@@ -0,0 +1,186 @@
+use std::{
+    fmt,
+    fs::File,
+    io::BufWriter,
+    path::Path,
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use serde::Serialize;
+
+use crate::utils::color::Color;
+
+// 一个样本在图像中的位置，用于定位异常
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct SampleId {
+    pub pixel: (u32, u32),
+    pub index: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueKind {
+    NaN,
+    Infinite,
+    Negative,
+    // 生成的方向在混合 pdf 下概率为 0
+    ZeroPdf,
+}
+
+impl IssueKind {
+    const ALL: [IssueKind; 4] = [
+        IssueKind::NaN,
+        IssueKind::Infinite,
+        IssueKind::Negative,
+        IssueKind::ZeroPdf,
+    ];
+
+    // 检查一个路径上的颜色，正常时返回 None
+    pub fn of(color: &Color) -> Option<IssueKind> {
+        let e = color.e();
+        if e.iter().any(|x| x.is_nan()) {
+            Some(IssueKind::NaN)
+        } else if e.iter().any(|x| x.is_infinite()) {
+            Some(IssueKind::Infinite)
+        } else if e.iter().any(|&x| x < 0.0) {
+            Some(IssueKind::Negative)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SampleIssue {
+    pub kind: IssueKind,
+    pub sample: SampleId,
+    // 出现异常时的弹射次数，0 为相机光线直接击中的表面
+    pub bounce: u32,
+    pub material: String,
+}
+
+// 渲染过程中被丢弃的异常样本，只保留前 MAX_RECORDS 条详细记录
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    counts: [AtomicUsize; 4],
+    records: Mutex<Vec<SampleIssue>>,
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    counts: Vec<(IssueKind, usize)>,
+    records: &'a [SampleIssue],
+}
+
+impl Diagnostics {
+    const MAX_RECORDS: usize = 1000;
+
+    pub fn clear(&mut self) {
+        *self = Diagnostics::default();
+    }
+
+    pub fn record(&self, issue: SampleIssue) {
+        let index = IssueKind::ALL
+            .iter()
+            .position(|k| *k == issue.kind)
+            .unwrap();
+        self.counts[index].fetch_add(1, Ordering::Relaxed);
+
+        let mut records = self.records.lock().unwrap();
+        if records.len() < Self::MAX_RECORDS {
+            records.push(issue);
+        }
+    }
+
+    pub fn count(&self, kind: IssueKind) -> usize {
+        let index = IssueKind::ALL.iter().position(|k| *k == kind).unwrap();
+        self.counts[index].load(Ordering::Relaxed)
+    }
+
+    pub fn total(&self) -> usize {
+        IssueKind::ALL.iter().map(|&kind| self.count(kind)).sum()
+    }
+
+    pub fn records(&self) -> Vec<SampleIssue> {
+        self.records.lock().unwrap().clone()
+    }
+
+    pub fn save_json(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let records = self.records();
+        let report = Report {
+            counts: IssueKind::ALL.iter().map(|&k| (k, self.count(k))).collect(),
+            records: &records,
+        };
+        serde_json::to_writer_pretty(BufWriter::new(File::create(path)?), &report)?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Discarded {} invalid samples (", self.total())?;
+        for (i, kind) in IssueKind::ALL.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{kind:?}: {}", self.count(*kind))?;
+        }
+        write!(f, ")")?;
+
+        for issue in self.records.lock().unwrap().iter().take(10) {
+            write!(
+                f,
+                "\n  {:?} at pixel {:?}, sample {}, bounce {}, material {}",
+                issue.kind, issue.sample.pixel, issue.sample.index, issue.bounce, issue.material
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_issue_kind() {
+        assert_eq!(IssueKind::of(&Color::WHITE), None);
+        assert_eq!(
+            IssueKind::of(&Color::new(f64::NAN, 0.0, f64::INFINITY)),
+            Some(IssueKind::NaN)
+        );
+        assert_eq!(
+            IssueKind::of(&Color::new(1.0, f64::INFINITY, 0.0)),
+            Some(IssueKind::Infinite)
+        );
+        assert_eq!(
+            IssueKind::of(&Color::new(1.0, -0.1, 0.0)),
+            Some(IssueKind::Negative)
+        );
+    }
+
+    #[test]
+    fn test_record_limit() {
+        let diagnostics = Diagnostics::default();
+        for i in 0..Diagnostics::MAX_RECORDS + 5 {
+            diagnostics.record(SampleIssue {
+                kind: IssueKind::ZeroPdf,
+                sample: SampleId {
+                    pixel: (i as u32, 0),
+                    index: 0,
+                },
+                bounce: 1,
+                material: "Lambertian".to_owned(),
+            });
+        }
+        assert_eq!(
+            diagnostics.count(IssueKind::ZeroPdf),
+            Diagnostics::MAX_RECORDS + 5
+        );
+        assert_eq!(diagnostics.records().len(), Diagnostics::MAX_RECORDS);
+        assert_eq!(diagnostics.total(), Diagnostics::MAX_RECORDS + 5);
+    }
+}
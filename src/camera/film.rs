@@ -0,0 +1,287 @@
+use std::{
+    f64::consts::PI,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{post::parse_params, utils::color::Color};
+
+// 像素重建滤波器，radius 以像素为单位，二维滤波为两个方向的乘积
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Filter {
+    Box {
+        #[serde(default = "Filter::default_box_radius")]
+        radius: f64,
+    },
+    Tent {
+        #[serde(default = "Filter::default_tent_radius")]
+        radius: f64,
+    },
+    Gaussian {
+        #[serde(default = "Filter::default_gaussian_radius")]
+        radius: f64,
+        #[serde(default = "Filter::default_gaussian_sigma")]
+        sigma: f64,
+    },
+    // b、c 为 Mitchell–Netravali 三次滤波的参数，默认取推荐值 1/3
+    Mitchell {
+        #[serde(default = "Filter::default_mitchell_radius")]
+        radius: f64,
+        #[serde(default = "Filter::default_mitchell_parameter")]
+        b: f64,
+        #[serde(default = "Filter::default_mitchell_parameter")]
+        c: f64,
+    },
+    Lanczos {
+        #[serde(default = "Filter::default_lanczos_radius")]
+        radius: f64,
+    },
+    BlackmanHarris {
+        #[serde(default = "Filter::default_blackman_harris_radius")]
+        radius: f64,
+    },
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter::Box {
+            radius: Filter::default_box_radius(),
+        }
+    }
+}
+
+impl Filter {
+    fn default_box_radius() -> f64 {
+        0.5
+    }
+
+    fn default_tent_radius() -> f64 {
+        1.0
+    }
+
+    fn default_gaussian_radius() -> f64 {
+        1.5
+    }
+
+    fn default_gaussian_sigma() -> f64 {
+        0.5
+    }
+
+    fn default_mitchell_radius() -> f64 {
+        2.0
+    }
+
+    fn default_mitchell_parameter() -> f64 {
+        1.0 / 3.0
+    }
+
+    fn default_lanczos_radius() -> f64 {
+        3.0
+    }
+
+    fn default_blackman_harris_radius() -> f64 {
+        2.0
+    }
+
+    // 从命令行参数解析，格式为 "名称:参数=值"，例如 "mitchell:radius=2"
+    pub fn parse(spec: &str) -> Result<Filter, Box<dyn std::error::Error>> {
+        let (name, params) = spec.split_once(':').unwrap_or((spec, ""));
+
+        let mut map = parse_params(params)?;
+        map.insert("type".to_owned(), Value::String(name.to_owned()));
+
+        Ok(serde_json::from_value(Value::Object(map))?)
+    }
+
+    pub fn radius(&self) -> f64 {
+        match *self {
+            Filter::Box { radius }
+            | Filter::Tent { radius }
+            | Filter::Gaussian { radius, .. }
+            | Filter::Mitchell { radius, .. }
+            | Filter::Lanczos { radius }
+            | Filter::BlackmanHarris { radius } => radius,
+        }
+    }
+
+    pub fn evaluate(&self, x: f64, y: f64) -> f64 {
+        self.evaluate_1d(x) * self.evaluate_1d(y)
+    }
+
+    fn evaluate_1d(&self, x: f64) -> f64 {
+        let x = x.abs();
+        let radius = self.radius();
+        if x > radius {
+            return 0.0;
+        }
+
+        match *self {
+            Filter::Box { .. } => 1.0,
+            Filter::Tent { radius } => radius - x,
+            Filter::Gaussian { radius, sigma } => {
+                let gaussian = |x: f64| (-x * x / (2.0 * sigma * sigma)).exp();
+                (gaussian(x) - gaussian(radius)).max(0.0)
+            }
+            Filter::Mitchell { radius, b, c } => {
+                // 将 [0, radius] 映射到三次多项式的定义域 [0, 2]
+                let x = 2.0 * x / radius;
+                let (x2, x3) = (x * x, x * x * x);
+                let value = if x < 1.0 {
+                    (12.0 - 9.0 * b - 6.0 * c) * x3
+                        + (-18.0 + 12.0 * b + 6.0 * c) * x2
+                        + (6.0 - 2.0 * b)
+                } else {
+                    (-b - 6.0 * c) * x3
+                        + (6.0 * b + 30.0 * c) * x2
+                        + (-12.0 * b - 48.0 * c) * x
+                        + (8.0 * b + 24.0 * c)
+                };
+                value / 6.0
+            }
+            Filter::Lanczos { radius } => Filter::sinc(x) * Filter::sinc(x / radius),
+            Filter::BlackmanHarris { radius } => {
+                const A0: f64 = 0.35875;
+                const A1: f64 = 0.48829;
+                const A2: f64 = 0.14128;
+                const A3: f64 = 0.01168;
+                let n = (x + radius) / (2.0 * radius);
+                A0 - A1 * (2.0 * PI * n).cos() + A2 * (4.0 * PI * n).cos()
+                    - A3 * (6.0 * PI * n).cos()
+            }
+        }
+    }
+
+    fn sinc(x: f64) -> f64 {
+        if x.abs() < 1e-8 {
+            1.0
+        } else {
+            (PI * x).sin() / (PI * x)
+        }
+    }
+}
+
+// 可以被多个线程同时写入的胶片，每个像素累积带权颜色与权重
+pub struct Film {
+    width: u32,
+    height: u32,
+    filter: Filter,
+    // 每个像素依次为 r、g、b、权重，以 f64 的位模式存储
+    pixels: Vec<[AtomicU64; 4]>,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32, filter: Filter) -> Film {
+        let pixels = (0..width * height)
+            .map(|_| std::array::from_fn(|_| AtomicU64::new(0.0_f64.to_bits())))
+            .collect();
+
+        Film {
+            width,
+            height,
+            filter,
+            pixels,
+        }
+    }
+
+    fn atomic_add(target: &AtomicU64, value: f64) {
+        let _ = target.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+            Some((f64::from_bits(bits) + value).to_bits())
+        });
+    }
+
+    // (x, y) 为样本在图像上的连续坐标，像素 (i, j) 的中心位于 (i + 0.5, j + 0.5)
+    pub fn add_sample(&self, x: f64, y: f64, color: Color) {
+        let radius = self.filter.radius();
+        let x0 = (x - 0.5 - radius).ceil().max(0.0) as u32;
+        let y0 = (y - 0.5 - radius).ceil().max(0.0) as u32;
+        let x1 = ((x - 0.5 + radius).floor() as i64).min(self.width as i64 - 1);
+        let y1 = ((y - 0.5 + radius).floor() as i64).min(self.height as i64 - 1);
+
+        for j in y0 as i64..=y1 {
+            for i in x0 as i64..=x1 {
+                let weight = self.filter.evaluate(i as f64 + 0.5 - x, j as f64 + 0.5 - y);
+                if weight == 0.0 {
+                    continue;
+                }
+
+                let pixel = &self.pixels[(j * self.width as i64 + i) as usize];
+                for (channel, value) in pixel.iter().zip(color.e()) {
+                    Film::atomic_add(channel, weight * value);
+                }
+                Film::atomic_add(&pixel[3], weight);
+            }
+        }
+    }
+
+    // 归一化得到每个像素的颜色，负瓣滤波器可能产生的负值被截断为 0
+    pub fn resolve(&self) -> Vec<Color> {
+        self.pixels
+            .iter()
+            .map(|pixel| {
+                let [r, g, b, weight] = pixel
+                    .each_ref()
+                    .map(|v| f64::from_bits(v.load(Ordering::Relaxed)));
+                if weight.abs() < 1e-12 {
+                    Color::BLACK
+                } else {
+                    (Color::new(r, g, b) / weight).clamp(0.0, f64::INFINITY)
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filters_peak_at_center() {
+        let filters = [
+            Filter::default(),
+            Filter::parse("tent").unwrap(),
+            Filter::parse("gaussian").unwrap(),
+            Filter::parse("mitchell").unwrap(),
+            Filter::parse("lanczos").unwrap(),
+            Filter::parse("blackman_harris:radius=1.5").unwrap(),
+        ];
+
+        for filter in &filters {
+            let center = filter.evaluate(0.0, 0.0);
+            assert!(center > 0.0, "{filter:?}");
+            assert!(filter.evaluate(0.3, 0.2) <= center, "{filter:?}");
+            assert_eq!(filter.evaluate(filter.radius() + 0.1, 0.0), 0.0);
+        }
+        assert_eq!(filters[5].radius(), 1.5);
+    }
+
+    #[test]
+    fn test_box_film_keeps_samples_in_pixel() {
+        let film = Film::new(2, 1, Filter::default());
+        film.add_sample(0.2, 0.5, Color::WHITE);
+        film.add_sample(0.9, 0.5, Color::WHITE * 3.0);
+
+        let pixels = film.resolve();
+        assert_eq!(pixels[0], Color::WHITE * 2.0);
+        assert_eq!(pixels[1], Color::BLACK);
+    }
+
+    #[test]
+    fn test_tent_film_splats_into_neighbours() {
+        let film = Film::new(3, 1, Filter::Tent { radius: 1.0 });
+        film.add_sample(1.5, 0.5, Color::WHITE);
+        film.add_sample(2.5, 0.5, Color::BLACK);
+
+        let pixels = film.resolve();
+        assert_eq!(pixels[0], Color::BLACK);
+        assert_eq!(pixels[1], Color::WHITE);
+        assert_eq!(pixels[2], Color::BLACK);
+
+        film.add_sample(2.0, 0.5, Color::WHITE);
+        let pixels = film.resolve();
+        assert!(pixels[2].x() > 0.0 && pixels[2].x() < 1.0);
+    }
+}
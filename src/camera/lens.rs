@@ -0,0 +1,150 @@
+use std::{f64::consts::PI, sync::Arc};
+
+use serde::{Deserialize, Deserializer};
+
+use crate::{
+    texture::{ImageTexture, Texture},
+    utils::{random::Random, vec3::Vec3},
+};
+
+// 光圈形状，采样结果都位于单位圆内
+// JSON 中遮罩以图像文件名给出，例如 {"mask": "aperture.png"}
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Aperture {
+    #[default]
+    Circle,
+    // 正多边形光圈，blades 为叶片数
+    Polygon {
+        blades: u32,
+        rotation_in_degrees: f64,
+    },
+    // 以图像亮度作为透过率的光圈遮罩，图像铺满 [-1, 1] x [-1, 1]
+    Mask(#[serde(deserialize_with = "Aperture::deserialize_mask")] Arc<ImageTexture>),
+}
+
+impl Aperture {
+    const MASK_MAX_TRIES: usize = 64;
+
+    pub fn polygon(blades: u32, rotation_in_degrees: f64) -> Aperture {
+        Aperture::Polygon {
+            blades,
+            rotation_in_degrees,
+        }
+    }
+
+    pub fn mask(file_name: &str) -> Aperture {
+        Aperture::Mask(Arc::new(ImageTexture::new_raw_image(file_name)))
+    }
+
+    fn deserialize_mask<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Arc<ImageTexture>, D::Error> {
+        let file_name = String::deserialize(deserializer)?;
+        Ok(Arc::new(ImageTexture::new_raw_image(&file_name)))
+    }
+
+    // 返回光圈上的一个采样点，遮罩全黑等情况下可能失败
+    pub fn sample(&self) -> Option<(f64, f64)> {
+        match self {
+            Aperture::Circle => {
+                let p = Vec3::random_in_unit_disk();
+                Some((p.x(), p.y()))
+            }
+            Aperture::Polygon {
+                blades,
+                rotation_in_degrees,
+            } => {
+                let blades = (*blades).max(3);
+                let step = 2.0 * PI / blades as f64;
+                let k = Random::usize(0..=(blades as usize - 1)) as f64;
+                let a0 = rotation_in_degrees.to_radians() + k * step;
+                let a1 = a0 + step;
+
+                // 在由中心和相邻两个顶点构成的三角形内均匀采样
+                let mut r1 = Random::f64();
+                let mut r2 = Random::f64();
+                if r1 + r2 > 1.0 {
+                    (r1, r2) = (1.0 - r1, 1.0 - r2);
+                }
+                Some((r1 * a0.cos() + r2 * a1.cos(), r1 * a0.sin() + r2 * a1.sin()))
+            }
+            Aperture::Mask(texture) => {
+                for _ in 0..Self::MASK_MAX_TRIES {
+                    let x = Random::random_range(-1.0..1.0);
+                    let y = Random::random_range(-1.0..1.0);
+                    let u = (x + 1.0) / 2.0;
+                    let v = (y + 1.0) / 2.0;
+                    let c = texture.value(u, v, &Vec3::ZERO);
+                    let transmittance = (c.x() + c.y() + c.z()) / 3.0;
+                    if Random::f64() < transmittance {
+                        return Some((x, y));
+                    }
+                }
+                None
+            }
+        }
+    }
+}
+
+// 以物理参数描述的镜头，设置后会覆盖视场角与虚焦角
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct PhysicalLens {
+    pub focal_length_mm: f64,
+    pub f_stop: f64,
+    #[serde(default = "PhysicalLens::default_sensor_height_mm")]
+    pub sensor_height_mm: f64,
+    // 场景中一米对应的长度单位数
+    #[serde(default = "PhysicalLens::default_units_per_meter")]
+    pub units_per_meter: f64,
+}
+
+impl PhysicalLens {
+    pub fn new(focal_length_mm: f64, f_stop: f64) -> PhysicalLens {
+        PhysicalLens {
+            focal_length_mm,
+            f_stop,
+            sensor_height_mm: Self::default_sensor_height_mm(),
+            units_per_meter: Self::default_units_per_meter(),
+        }
+    }
+
+    fn default_sensor_height_mm() -> f64 {
+        24.0
+    }
+
+    fn default_units_per_meter() -> f64 {
+        1.0
+    }
+
+    pub fn vertical_fov_in_degrees(&self) -> f64 {
+        (2.0 * (self.sensor_height_mm / (2.0 * self.focal_length_mm)).atan()).to_degrees()
+    }
+
+    // 入瞳半径，单位为场景长度
+    pub fn aperture_radius(&self) -> f64 {
+        self.focal_length_mm / self.f_stop / 2.0 / 1000.0 * self.units_per_meter
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_polygon_samples_inside_unit_disk() {
+        let aperture = Aperture::polygon(6, 15.0);
+        for _ in 0..1000 {
+            let (x, y) = aperture.sample().unwrap();
+            assert!(x * x + y * y <= 1.0 + 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_physical_lens() {
+        let lens = PhysicalLens::new(50.0, 2.0);
+        assert!((lens.aperture_radius() - 0.0125).abs() < 1e-12);
+        let fov = lens.vertical_fov_in_degrees();
+        assert!((fov - 26.99).abs() < 0.01);
+    }
+}
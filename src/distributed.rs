@@ -0,0 +1,286 @@
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::utils::color::Color;
+
+// 构建场景时使用的种子，各工作进程与合并时由随机数生成的几何相同
+pub const SCENE_SEED: u64 = 0x5EED;
+
+// 任务的划分方式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Split {
+    // 按行将图像分为 count 块，每个工作进程渲染其中一块
+    Tiles { count: u32 },
+    // 每个工作进程使用不同的种子渲染整幅图像的 1/count 样本
+    Samples { count: u32 },
+}
+
+impl Split {
+    // 从命令行参数解析，格式为 "tiles:N" 或 "samples:N"
+    pub fn parse(spec: &str) -> Result<Split, Box<dyn std::error::Error>> {
+        let (kind, count) = spec
+            .split_once(':')
+            .ok_or_else(|| format!("Invalid split: {spec}"))?;
+        let count: u32 = count.parse()?;
+        if count == 0 {
+            return Err("Split count must be positive".into());
+        }
+
+        match kind {
+            "tiles" => Ok(Split::Tiles { count }),
+            "samples" => Ok(Split::Samples { count }),
+            _ => Err(format!("Unknown split: {kind}").into()),
+        }
+    }
+
+    pub fn count(&self) -> u32 {
+        match *self {
+            Split::Tiles { count } | Split::Samples { count } => count,
+        }
+    }
+
+    // 第 index 块包含的行 [y0, y1)，调用者保证 count 不超过图像高度
+    pub fn tile_rows(count: u32, index: u32, image_height: u32) -> (u32, u32) {
+        let y0 = image_height * index / count;
+        let y1 = image_height * (index + 1) / count;
+        (y0, y1)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum RenderJob {
+    // 作为工作进程渲染任务的一部分，并将浮点结果写入 output
+    Worker {
+        split: Split,
+        index: u32,
+        output: PathBuf,
+    },
+    // 合并各工作进程的结果代替渲染
+    Merge {
+        inputs: Vec<PathBuf>,
+    },
+}
+
+// 工作进程写出的部分渲染结果，像素为线性颜色
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartialResult {
+    pub image_width: u32,
+    pub image_height: u32,
+    // 本结果覆盖的区域
+    pub x0: u32,
+    pub y0: u32,
+    pub width: u32,
+    pub height: u32,
+    pub samples_per_pixel: u64,
+    pub seed: u64,
+    pub pixels: Vec<Color>,
+}
+
+impl PartialResult {
+    const MAGIC: &[u8; 4] = b"RTPF";
+    const VERSION: u32 = 1;
+
+    pub fn write_to(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        writer.write_all(Self::MAGIC)?;
+        for v in [
+            Self::VERSION,
+            self.image_width,
+            self.image_height,
+            self.x0,
+            self.y0,
+            self.width,
+            self.height,
+        ] {
+            writer.write_all(&v.to_le_bytes())?;
+        }
+        writer.write_all(&self.samples_per_pixel.to_le_bytes())?;
+        writer.write_all(&self.seed.to_le_bytes())?;
+        for pixel in &self.pixels {
+            for c in pixel.e() {
+                writer.write_all(&c.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn read_from(reader: &mut impl Read) -> Result<PartialResult, Box<dyn std::error::Error>> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != Self::MAGIC {
+            return Err("Not a partial render result".into());
+        }
+
+        let version = read_u32(reader)?;
+        if version != Self::VERSION {
+            return Err(format!("Unsupported partial result version {version}").into());
+        }
+        let image_width = read_u32(reader)?;
+        let image_height = read_u32(reader)?;
+        let x0 = read_u32(reader)?;
+        let y0 = read_u32(reader)?;
+        let width = read_u32(reader)?;
+        let height = read_u32(reader)?;
+        let samples_per_pixel = read_u64(reader)?;
+        let seed = read_u64(reader)?;
+
+        let mut pixels = Vec::with_capacity((width * height) as usize);
+        for _ in 0..width * height {
+            let mut e = [0.0; 3];
+            for c in &mut e {
+                *c = f64::from_bits(read_u64(reader)?);
+            }
+            pixels.push(Color::from(e));
+        }
+
+        Ok(PartialResult {
+            image_width,
+            image_height,
+            x0,
+            y0,
+            width,
+            height,
+            samples_per_pixel,
+            seed,
+            pixels,
+        })
+    }
+
+    // 先写入临时文件再重命名，避免合并时读到写了一半的结果
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp_path = path.with_extension("tmp");
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        self.write_to(&mut writer)?;
+        writer.flush()?;
+        drop(writer);
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<PartialResult, Box<dyn std::error::Error>> {
+        let mut reader = BufReader::new(File::open(path)?);
+        PartialResult::read_from(&mut reader)
+    }
+
+    // 拼接图块或按样本数加权平均，返回完整图像的宽、高与像素
+    pub fn merge(
+        parts: &[PartialResult],
+    ) -> Result<(u32, u32, Vec<Color>), Box<dyn std::error::Error>> {
+        let first = parts.first().ok_or("Nothing to merge")?;
+        let (image_width, image_height) = (first.image_width, first.image_height);
+
+        let mut sum = vec![Color::BLACK; (image_width * image_height) as usize];
+        let mut weight = vec![0u64; (image_width * image_height) as usize];
+        for part in parts {
+            if (part.image_width, part.image_height) != (image_width, image_height) {
+                return Err("Partial results have different image sizes".into());
+            }
+            if part.x0 + part.width > image_width || part.y0 + part.height > image_height {
+                return Err("Partial result lies outside the image".into());
+            }
+
+            for (index, color) in part.pixels.iter().enumerate() {
+                let x = part.x0 + index as u32 % part.width;
+                let y = part.y0 + index as u32 / part.width;
+                let target = (y * image_width + x) as usize;
+                sum[target] += *color * part.samples_per_pixel as f64;
+                weight[target] += part.samples_per_pixel;
+            }
+        }
+
+        let missing = weight.iter().filter(|&&w| w == 0).count();
+        if missing > 0 {
+            return Err(format!("{missing} pixels are not covered by any partial result").into());
+        }
+
+        let pixels = sum
+            .iter()
+            .zip(weight.iter())
+            .map(|(c, &w)| *c / w as f64)
+            .collect();
+        Ok((image_width, image_height, pixels))
+    }
+}
+
+fn read_u32(reader: &mut impl Read) -> std::io::Result<u32> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(reader: &mut impl Read) -> std::io::Result<u64> {
+    let mut buf = [0; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn part(x0: u32, y0: u32, width: u32, height: u32, spp: u64, color: Color) -> PartialResult {
+        PartialResult {
+            image_width: 4,
+            image_height: 2,
+            x0,
+            y0,
+            width,
+            height,
+            samples_per_pixel: spp,
+            seed: 0,
+            pixels: vec![color; (width * height) as usize],
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let result = part(0, 1, 4, 1, 16, Color::new(0.25, 1.5, 3.0));
+        let mut bytes = Vec::new();
+        result.write_to(&mut bytes).unwrap();
+        let read = PartialResult::read_from(&mut bytes.as_slice()).unwrap();
+        assert_eq!(read, result);
+
+        bytes[0] = b'X';
+        assert!(PartialResult::read_from(&mut bytes.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_merge_tiles() {
+        let parts = [
+            part(0, 0, 4, 1, 4, Color::WHITE),
+            part(0, 1, 4, 1, 4, Color::RED),
+        ];
+        let (w, h, pixels) = PartialResult::merge(&parts).unwrap();
+        assert_eq!((w, h), (4, 2));
+        assert_eq!(pixels[0], Color::WHITE);
+        assert_eq!(pixels[7], Color::RED);
+
+        assert!(PartialResult::merge(&parts[..1]).is_err());
+    }
+
+    #[test]
+    fn test_merge_samples_weighted() {
+        let parts = [
+            part(0, 0, 4, 2, 3, Color::WHITE),
+            part(0, 0, 4, 2, 1, Color::BLACK),
+        ];
+        let (_, _, pixels) = PartialResult::merge(&parts).unwrap();
+        assert!(pixels.iter().all(|c| *c == Color::WHITE * 0.75));
+    }
+
+    #[test]
+    fn test_split() {
+        assert_eq!(Split::parse("tiles:4").unwrap(), Split::Tiles { count: 4 });
+        assert_eq!(Split::parse("samples:2").unwrap().count(), 2);
+        assert!(Split::parse("tiles:0").is_err());
+        assert!(Split::parse("rows:2").is_err());
+
+        let rows: Vec<_> = (0..3).map(|i| Split::tile_rows(3, i, 10)).collect();
+        assert_eq!(rows, vec![(0, 3), (3, 6), (6, 10)]);
+    }
+}
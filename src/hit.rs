@@ -1,7 +1,13 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicU32, Ordering},
+};
+
 use crate::{
     aabb::AABB,
     material::Material,
     utils::{
+        color::Color,
         interval::Interval,
         ray::Ray,
         vec3::{Point3, UnitVec3, Vec3},
@@ -9,15 +15,33 @@ use crate::{
 };
 
 pub struct HitRecord<'a> {
-    pub p: Point3, // 击中位置
-    pub normal: UnitVec3,
+    pub p: Point3,        // 击中位置
+    pub normal: UnitVec3, // 着色法线，用于计算散射
+    // 几何法线，与着色法线位于表面同一侧
+    pub geometric_normal: UnitVec3,
     pub mat: &'a dyn Material,
     pub t: f64, // 射线长度
 
     pub u: f64,
     pub v: f64, // 撞击点表面坐标
 
+    // 位置对表面坐标的偏导，不随法线翻转，未知时为零向量
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
+
+    // 插值的顶点颜色，由 VertexColor 纹理读取
+    pub color: Option<Color>,
+
     pub front_face: bool,
+
+    // 所属物体的编号，0 表示未标记，用于描边检测物体边界
+    pub object_id: u32,
+}
+
+// 分配一个全局唯一的物体编号
+pub fn next_object_id() -> u32 {
+    static NEXT_OBJECT_ID: AtomicU32 = AtomicU32::new(1);
+    NEXT_OBJECT_ID.fetch_add(1, Ordering::Relaxed)
 }
 
 impl<'a> HitRecord<'a> {
@@ -31,16 +55,44 @@ impl<'a> HitRecord<'a> {
         r_in: &Ray,
     ) -> HitRecord<'a> {
         let front_face = r_in.direction().dot(&normal) < 0.0;
+        let normal = if front_face { normal } else { -normal };
         HitRecord {
             p,
-            normal: if front_face { normal } else { -normal },
+            normal,
+            geometric_normal: normal,
             mat,
             t,
             u,
             v,
+            dpdu: Vec3::ZERO,
+            dpdv: Vec3::ZERO,
+            color: None,
             front_face,
+            object_id: 0,
         }
     }
+
+    // 以朝外的插值法线作为着色法线，与几何法线一样按 front_face 翻转
+    pub fn with_shading_normal(mut self, normal: UnitVec3) -> HitRecord<'a> {
+        self.normal = if self.front_face { normal } else { -normal };
+        self
+    }
+
+    pub fn with_tangents(mut self, dpdu: Vec3, dpdv: Vec3) -> HitRecord<'a> {
+        self.dpdu = dpdu;
+        self.dpdv = dpdv;
+        self
+    }
+
+    pub fn with_color(mut self, color: Color) -> HitRecord<'a> {
+        self.color = Some(color);
+        self
+    }
+
+    pub fn with_object_id(mut self, object_id: u32) -> HitRecord<'a> {
+        self.object_id = object_id;
+        self
+    }
 }
 
 pub trait Hittable: Send + Sync {
@@ -48,13 +100,33 @@ pub trait Hittable: Send + Sync {
 
     fn bounding_box(&self) -> &AABB;
 
+    // 作为光源采样，time 为散射光线的时刻，运动的物体在该时刻的位置上采样
     #[allow(unused_variables)]
-    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3, time: f64) -> f64 {
         unimplemented!()
     }
 
     #[allow(unused_variables)]
-    fn random(&self, origin: &Point3) -> UnitVec3 {
+    fn random(&self, origin: &Point3, time: f64) -> UnitVec3 {
         unimplemented!()
     }
 }
+
+// 以 Arc 共享的物体可以同时加入场景与光源列表
+impl<T: Hittable + ?Sized> Hittable for Arc<T> {
+    fn hit(&self, r: &Ray, interval: &Interval) -> Option<HitRecord> {
+        self.as_ref().hit(r, interval)
+    }
+
+    fn bounding_box(&self) -> &AABB {
+        self.as_ref().bounding_box()
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3, time: f64) -> f64 {
+        self.as_ref().pdf_value(origin, direction, time)
+    }
+
+    fn random(&self, origin: &Point3, time: f64) -> UnitVec3 {
+        self.as_ref().random(origin, time)
+    }
+}
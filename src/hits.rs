@@ -1,5 +1,3 @@
-use rand::seq::IndexedRandom;
-
 use crate::{
     aabb::AABB,
     hit::Hittable,
@@ -28,6 +26,15 @@ impl Hittables {
         self.bbox = self.bbox.union(*object.bounding_box());
         self.objects.push(object);
     }
+
+    // 对每个物体调用 update（参数为下标），然后重新计算包围盒
+    pub fn update(&mut self, mut update: impl FnMut(usize, &mut Box<dyn Hittable>)) {
+        self.bbox = AABB::EMPTY;
+        for (index, object) in self.objects.iter_mut().enumerate() {
+            update(index, object);
+            self.bbox = self.bbox.union(*object.bounding_box());
+        }
+    }
 }
 
 impl Hittable for Hittables {
@@ -53,11 +60,12 @@ impl Hittable for Hittables {
         &self,
         origin: &crate::utils::vec3::Point3,
         direction: &crate::utils::vec3::Vec3,
+        time: f64,
     ) -> f64 {
         let sum: f64 = self
             .objects
             .iter()
-            .map(|object| object.pdf_value(origin, direction))
+            .map(|object| object.pdf_value(origin, direction, time))
             .sum();
 
         let ret = sum / self.objects.len() as f64;
@@ -66,11 +74,12 @@ impl Hittable for Hittables {
         ret
     }
 
-    fn random(&self, origin: &crate::utils::vec3::Point3) -> UnitVec3 {
-        let object = self
-            .objects
-            .choose(&mut Random::rng())
-            .expect("The collection of objects is empty!");
-        object.random(origin)
+    fn random(&self, origin: &crate::utils::vec3::Point3, time: f64) -> UnitVec3 {
+        assert!(
+            !self.objects.is_empty(),
+            "The collection of objects is empty!"
+        );
+        let index = Random::usize(0..=self.objects.len() - 1);
+        self.objects[index].random(origin, time)
     }
 }
@@ -1,11 +1,16 @@
 pub mod aabb;
+pub mod animation;
 pub mod bvh;
 pub mod camera;
+pub mod distributed;
 pub mod hit;
 pub mod hits;
 pub mod material;
 pub mod pdf;
+pub mod post;
+pub mod server;
 pub mod shapes;
+pub mod stats;
 pub mod texture;
 pub mod utils;
 pub mod volume;
@@ -1,17 +1,22 @@
-use std::sync::Arc;
+use std::{path::PathBuf, sync::Arc};
 
 use console::style;
 use image::RgbImage;
 use raytracer::{
+    animation::{Animation, CameraTrack, SceneObject, Track, TransformTrack},
     bvh::BVH,
-    camera::Camera,
+    camera::{Camera, crop::CropWindow, film::Filter},
+    distributed::{RenderJob, SCENE_SEED, Split},
+    hit::Hittable,
     hits::Hittables,
     material::{
         Dielectric, DiffuseLight, EmptyMaterial, Lambertian, Metal, Mix, disney::Disney,
         portal::Portal,
     },
+    post::{PostEffect, outline::Outline},
+    server::RenderServer,
     shapes::{
-        Transform,
+        Keyframe, Transform,
         obj::Wavefont,
         quad::{Quad, build_box},
         sphere::Sphere,
@@ -23,19 +28,37 @@ use raytracer::{
         random::Random,
         vec3::{Point3, Vec3},
     },
-    volume::ConstantMedium,
+    volume::{ConstantMedium, SubsurfaceMedium},
 };
 
 fn main() {
-    let img = match 3 {
-        0 => cornell_box(),
-        1 => final_scene(400, 250, 4),
-        2 => final_scene(800, 5000, 40),
-        3 => obj_scene(),
-        4 => background_scene(),
-        5 => disney_scene(),
-        _ => portal_scene(),
-    };
+    let args: Vec<String> = std::env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("animate") => animate(&args[2..]),
+        Some("worker") => worker(&args[2..]),
+        Some("merge") => merge(&args[2..]),
+        Some("serve") => serve(&args[2..]),
+        _ => {
+            let options = RenderOptions::parse(&args[1..]);
+            save_output(&render_scene(&options));
+        }
+    }
+}
+
+fn render_scene(options: &RenderOptions) -> RgbImage {
+    match 3 {
+        0 => cornell_box(options),
+        1 => final_scene(400, 250, 4, options),
+        2 => final_scene(800, 5000, 40, options),
+        3 => obj_scene(options),
+        4 => background_scene(options),
+        5 => disney_scene(options),
+        6 => subsurface_scene(options),
+        _ => portal_scene(options),
+    }
+}
+
+fn save_output(img: &RgbImage) {
     let path_string = format!("output/{}/{}.png", "final", "final");
     let path = std::path::Path::new(&path_string);
     let prefix = path.parent().unwrap();
@@ -47,27 +70,424 @@ fn main() {
     img.save(path).expect("Cannot save the image to the file");
 }
 
-fn portal_scene() -> RgbImage {
-    let mut world = Hittables::default();
-    let portal_material = Arc::new(Portal::new(
-        Color::WHITE,
-        Vec3::new(2.0, 0.0, 0.0),
-        Quaternion::identity(),
-    ));
+// 分布式渲染的工作进程，将部分结果写入文件，之后由 merge 合并
+// 用法: raytracer worker --split tiles:N|samples:N --index I --output PART.rtp [渲染选项]
+fn worker(args: &[String]) {
+    let mut split = None;
+    let mut index = None;
+    let mut output = None;
+    let mut options = RenderOptions::default();
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--split" => {
+                let spec = iter.next().expect("Missing --split");
+                split = Some(
+                    Split::parse(spec)
+                        .unwrap_or_else(|e| panic!("Invalid --split \"{spec}\": {e}")),
+                );
+            }
+            "--index" => {
+                index = Some(
+                    iter.next()
+                        .and_then(|s| s.parse().ok())
+                        .expect("Invalid --index"),
+                )
+            }
+            "--output" => output = Some(PathBuf::from(iter.next().expect("Missing --output"))),
+            _ => options.parse_arg(arg, &mut iter),
+        }
+    }
+
+    let output = output.expect("Missing --output");
+    options.job = Some(RenderJob::Worker {
+        split: split.expect("Missing --split"),
+        index: index.expect("Missing --index"),
+        output: output.clone(),
+    });
+    render_scene(&options);
+    println!(
+        "Output partial result as \"{}\"",
+        style(output.display()).yellow()
+    );
+}
+
+// 合并工作进程的部分结果，再按场景的相机设置进行后期处理与色调映射
+// 用法: raytracer merge PART.rtp... [渲染选项]
+fn merge(args: &[String]) {
+    let mut inputs = Vec::new();
+    let mut options = RenderOptions::default();
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if arg.starts_with("--") {
+            options.parse_arg(arg, &mut iter);
+        } else {
+            inputs.push(PathBuf::from(arg));
+        }
+    }
+
+    options.job = Some(RenderJob::Merge { inputs });
+    save_output(&render_scene(&options));
+}
+
+// 在本机端口上运行渲染服务，协议见 RenderServer
+// 用法: raytracer serve [--port PORT]
+fn serve(args: &[String]) {
+    let mut port = 7878;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--port" => {
+                port = iter
+                    .next()
+                    .and_then(|s| s.parse().ok())
+                    .expect("Invalid --port")
+            }
+            _ => panic!("Unknown argument: {arg}"),
+        }
+    }
+
+    RenderServer::new()
+        .serve(("127.0.0.1", port))
+        .expect("Render server failed");
+}
+
+// 场景中的物体与用于重要性采样的光源
+type World = (Box<dyn Hittable>, Option<Box<dyn Hittable>>);
+
+// 命令行中与具体场景无关的渲染选项
+// 用法: raytracer [--post EFFECT]... [--outline PARAMS] [--filter FILTER]
+//                  [--max-indirect-luminance X] [--diagnostics REPORT.json]
+//                  [--stats STATS.json] [--time-heatmap TIME.png] [--cost-heatmap COST.png]
+//                  [--crop WINDOW [--paste-into FULL.png]]
+// 格式分别见 PostEffect::parse、Outline::parse、Filter::parse 与 CropWindow::parse，
+// --post 可重复指定以串联多个效果，--paste-into 将裁剪区域的结果贴回之前的完整渲染
+// 裁剪时不能使用自动曝光、描边与读取周围像素的后期效果，见 Camera::check_crop
+#[derive(Default)]
+struct RenderOptions {
+    post_effects: Vec<PostEffect>,
+    outline: Option<Outline>,
+    filter: Option<Filter>,
+    max_indirect_luminance: Option<f64>,
+    diagnostics_report: Option<PathBuf>,
+    stats_report: Option<PathBuf>,
+    time_heatmap: Option<PathBuf>,
+    cost_heatmap: Option<PathBuf>,
+    crop: Option<CropWindow>,
+    paste_into: Option<PathBuf>,
+    job: Option<RenderJob>,
+}
+
+impl RenderOptions {
+    fn parse(args: &[String]) -> RenderOptions {
+        let mut options = RenderOptions::default();
+
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            options.parse_arg(arg, &mut iter);
+        }
+
+        options
+    }
+
+    fn parse_arg<'a>(&mut self, arg: &str, iter: &mut impl Iterator<Item = &'a String>) {
+        match arg {
+            "--post" => {
+                let spec = iter.next().expect("Missing --post");
+                let effect = PostEffect::parse(spec)
+                    .unwrap_or_else(|e| panic!("Invalid --post \"{spec}\": {e}"));
+                self.post_effects.push(effect);
+            }
+            "--outline" => {
+                let spec = iter.next().expect("Missing --outline");
+                let outline = Outline::parse(spec)
+                    .unwrap_or_else(|e| panic!("Invalid --outline \"{spec}\": {e}"));
+                self.outline = Some(outline);
+            }
+            "--filter" => {
+                let spec = iter.next().expect("Missing --filter");
+                let filter = Filter::parse(spec)
+                    .unwrap_or_else(|e| panic!("Invalid --filter \"{spec}\": {e}"));
+                self.filter = Some(filter);
+            }
+            "--max-indirect-luminance" => {
+                let value = iter
+                    .next()
+                    .and_then(|s| s.parse().ok())
+                    .expect("Invalid --max-indirect-luminance");
+                self.max_indirect_luminance = Some(value);
+            }
+            "--diagnostics" => {
+                let path = iter.next().expect("Missing --diagnostics");
+                self.diagnostics_report = Some(PathBuf::from(path));
+            }
+            "--stats" => {
+                let path = iter.next().expect("Missing --stats");
+                self.stats_report = Some(PathBuf::from(path));
+            }
+            "--time-heatmap" => {
+                let path = iter.next().expect("Missing --time-heatmap");
+                self.time_heatmap = Some(PathBuf::from(path));
+            }
+            "--cost-heatmap" => {
+                let path = iter.next().expect("Missing --cost-heatmap");
+                self.cost_heatmap = Some(PathBuf::from(path));
+            }
+            "--crop" => {
+                let spec = iter.next().expect("Missing --crop");
+                let crop = CropWindow::parse(spec)
+                    .unwrap_or_else(|e| panic!("Invalid --crop \"{spec}\": {e}"));
+                self.crop = Some(crop);
+            }
+            "--paste-into" => {
+                let path = iter.next().expect("Missing --paste-into");
+                self.paste_into = Some(PathBuf::from(path));
+            }
+            _ => panic!("Unknown argument: {arg}"),
+        }
+    }
+
+    // 命令行指定的后期效果追加在场景文件中的效果之后，其余选项覆盖场景文件的设置
+    fn apply(&self, camera: &mut Camera) {
+        camera
+            .post_effects
+            .extend(self.post_effects.iter().cloned());
+        if let Some(outline) = &self.outline {
+            camera.outline = Some(outline.clone());
+        }
+        if let Some(filter) = self.filter {
+            camera.filter = filter;
+        }
+        if self.max_indirect_luminance.is_some() {
+            camera.max_indirect_luminance = self.max_indirect_luminance;
+        }
+        if self.diagnostics_report.is_some() {
+            camera.diagnostics_report = self.diagnostics_report.clone();
+        }
+        if self.stats_report.is_some() {
+            camera.stats_report = self.stats_report.clone();
+        }
+        if self.time_heatmap.is_some() {
+            camera.time_heatmap = self.time_heatmap.clone();
+        }
+        if self.cost_heatmap.is_some() {
+            camera.cost_heatmap = self.cost_heatmap.clone();
+        }
+        if self.crop.is_some() {
+            camera.crop = self.crop;
+        }
+        if self.job.is_some() {
+            camera.job = self.job.clone();
+        }
+    }
 
-    let quad = Quad::new(
-        Vec3::new(-1.0, 0.0, -1.0),
-        Vec3::new(0.0, 0.0, 2.0),
-        Vec3::new(2.0, 0.0, 0.0),
-        portal_material,
+    // 设置相机后构建场景并渲染
+    // 分布式任务以固定的种子构建场景，使各进程中由随机数生成的几何一致
+    // 合并任务只需要相机，仅在描边需要几何缓冲时才构建场景
+    fn render(&self, mut camera: Camera, build: impl FnOnce() -> World) -> RgbImage {
+        self.apply(&mut camera);
+        match &camera.job {
+            Some(RenderJob::Merge { .. }) if camera.outline.is_none() => {
+                let img = camera.render(&Hittables::default(), None);
+                return self.paste(img, camera.image_size());
+            }
+            Some(_) => Random::seed(SCENE_SEED),
+            None => {}
+        }
+
+        let (world, lights) = build();
+        let img = camera.render(world.as_ref(), lights.as_deref());
+        self.paste(img, camera.image_size())
+    }
+
+    // 设置了 --paste-into 时将裁剪区域的结果贴回完整图像
+    fn paste(&self, img: RgbImage, image_size: (u32, u32)) -> RgbImage {
+        match (&self.paste_into, &self.crop) {
+            (Some(base_path), Some(crop)) => {
+                let mut base = image::open(base_path)
+                    .expect("Cannot open the image to paste into")
+                    .into_rgb8();
+                crop.paste(&mut base, &img, image_size).unwrap_or_else(|e| {
+                    panic!("Cannot paste into \"{}\": {e}", base_path.display())
+                });
+                base
+            }
+            (Some(_), None) => panic!("--paste-into requires --crop"),
+            _ => img,
+        }
+    }
+}
+
+// 用法: raytracer animate [--start N] [--end N] [--output DIR] [--no-resume] [渲染选项]
+fn animate(args: &[String]) {
+    let mut start = 1;
+    let mut end = 48;
+    let mut output = String::from("output/animation");
+    let mut resume = true;
+    let mut options = RenderOptions::default();
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--start" => {
+                start = iter
+                    .next()
+                    .and_then(|s| s.parse().ok())
+                    .expect("Invalid --start")
+            }
+            "--end" => {
+                end = iter
+                    .next()
+                    .and_then(|s| s.parse().ok())
+                    .expect("Invalid --end")
+            }
+            "--output" => output = iter.next().expect("Missing --output").clone(),
+            "--no-resume" => resume = false,
+            _ => options.parse_arg(arg, &mut iter),
+        }
+    }
+
+    let mut animation = cornell_box_animation();
+    options.apply(&mut animation.camera);
+    println!(
+        "Output frames {start}..={end} to \"{}\"",
+        style(&output).yellow()
     );
+    animation
+        .render_sequence(start..=end, std::path::Path::new(&output), resume)
+        .expect("Cannot render the animation");
+}
+
+fn cornell_box_animation() -> Animation {
+    let box_track = TransformTrack::new()
+        .key(
+            1.0,
+            Keyframe::new(
+                0.0,
+                Some(Vec3::new(265.0, 0.0, 295.0)),
+                Some(Quaternion::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), 15.0)),
+                None,
+            ),
+        )
+        .key(
+            48.0,
+            Keyframe::new(
+                0.0,
+                Some(Vec3::new(265.0, 0.0, 295.0)),
+                Some(Quaternion::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), 195.0)),
+                None,
+            ),
+        );
+
+    // 盒子的几何与实例只构建一次，之后每帧复制实例并替换关键帧，物体编号保持不变
+    let box1: Arc<dyn Hittable> = Arc::new(build_box(
+        Point3::ZERO,
+        Point3::new(165.0, 330.0, 165.0),
+        Arc::new(Lambertian::new(Arc::new(SolidColor::new(Color::new(
+            0.73, 0.73, 0.73,
+        ))))),
+    ));
+    let box_instance = Transform::instance(box1, None, None, None);
+    let box_at = move |frame: f64| {
+        let mut instance = box_instance.clone();
+        instance.set_keyframes(box_track.keyframes_for_frame(frame, 0.5));
+        instance
+    };
+    let scene_box_at = box_at.clone();
+    // 盒子是最后加入世界的物体
+    const BOX_INDEX: usize = 6;
+
+    let scene = Box::new(move |frame: f64| {
+        let mut world = Hittables::default();
+        let mut lights = Hittables::default();
+
+        let red = Arc::new(Lambertian::new(Arc::new(SolidColor::new(Color::new(
+            0.65, 0.05, 0.05,
+        )))));
+        let white = Arc::new(Lambertian::new(Arc::new(SolidColor::new(Color::new(
+            0.73, 0.73, 0.73,
+        )))));
+        let green = Arc::new(Lambertian::new(Arc::new(SolidColor::new(Color::new(
+            0.12, 0.45, 0.15,
+        )))));
+        let light = Arc::new(DiffuseLight::new(Arc::new(SolidColor::new(Color::new(
+            15.0, 15.0, 15.0,
+        )))));
+
+        world.add(Box::new(Quad::new(
+            Point3::new(555.0, 0.0, 0.0),
+            Vec3::new(0.0, 555.0, 0.0),
+            Vec3::new(0.0, 0.0, 555.0),
+            green,
+        )));
+        world.add(Box::new(Quad::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 555.0, 0.0),
+            Vec3::new(0.0, 0.0, 555.0),
+            red,
+        )));
+        world.add(Box::new(Quad::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Vec3::new(555.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 555.0),
+            white.clone(),
+        )));
+        world.add(Box::new(Quad::new(
+            Point3::new(555.0, 555.0, 555.0),
+            Vec3::new(-555.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, -555.0),
+            white.clone(),
+        )));
+        world.add(Box::new(Quad::new(
+            Point3::new(0.0, 0.0, 555.0),
+            Vec3::new(555.0, 0.0, 0.0),
+            Vec3::new(0.0, 555.0, 0.0),
+            white.clone(),
+        )));
 
-    let sphere_material = Arc::new(Lambertian::new(Arc::new(SolidColor::new(Color::WHITE))));
-    let sphere = Sphere::new(Vec3::new(2.0, -1.5, 0.0), 1.0, sphere_material);
+        let light_quad = Quad::new(
+            Point3::new(343.0, 554.0, 332.0),
+            Vec3::new(-130.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, -105.0),
+            light,
+        );
+        world.add(Box::new(light_quad.clone()));
+        lights.add(Box::new(light_quad));
 
-    world.add(Box::new(quad));
-    world.add(Box::new(sphere));
+        debug_assert_eq!(world.objects.len(), BOX_INDEX);
+        world.add(Box::new(scene_box_at(frame)));
+
+        (world, Some(lights))
+    });
+
+    let update = Box::new(move |frame: f64, index: SceneObject| {
+        (index == SceneObject::World(BOX_INDEX))
+            .then(|| Box::new(box_at(frame)) as Box<dyn Hittable>)
+    });
 
+    let mut camera = Camera::default();
+    camera.aspect_ratio = 1.0;
+    camera.image_width = 400;
+    camera.samples_per_pixel = 100;
+    camera.max_depth = 10;
+    camera.vec_up = Vec3::new(0.0, 1.0, 0.0);
+
+    let camera_track = CameraTrack {
+        look_from: Track::new()
+            .key(1.0, Point3::new(278.0, 278.0, -800.0))
+            .key(48.0, Point3::new(278.0, 278.0, -500.0)),
+        look_at: Track::new().key(1.0, Point3::new(278.0, 278.0, 0.0)),
+        vertical_fov_in_degrees: Track::new().key(1.0, 40.0).key(48.0, 55.0),
+        ..Default::default()
+    };
+
+    Animation::new(camera, camera_track, scene).with_update(update)
+}
+
+fn portal_scene(options: &RenderOptions) -> RgbImage {
     let mut camera = Camera::default();
 
     camera.aspect_ratio = 16.0 / 9.0;
@@ -86,51 +506,32 @@ fn portal_scene() -> RgbImage {
     let back_tex = ImageTexture::new("rogland_clear_night_4k.exr");
     camera.background.texture = Arc::new(back_tex);
 
-    camera.render(&world, None)
+    options.render(camera, || {
+        let mut world = Hittables::default();
+        let portal_material = Arc::new(Portal::new(
+            Color::WHITE,
+            Vec3::new(2.0, 0.0, 0.0),
+            Quaternion::identity(),
+        ));
+
+        let quad = Quad::new(
+            Vec3::new(-1.0, 0.0, -1.0),
+            Vec3::new(0.0, 0.0, 2.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            portal_material,
+        );
+
+        let sphere_material = Arc::new(Lambertian::new(Arc::new(SolidColor::new(Color::WHITE))));
+        let sphere = Sphere::new(Vec3::new(2.0, -1.5, 0.0), 1.0, sphere_material);
+
+        world.add(Box::new(quad));
+        world.add(Box::new(sphere));
+
+        (Box::new(world), None)
+    })
 }
 
-fn disney_scene() -> RgbImage {
-    let mut world = Hittables::default();
-
-    let disney = Arc::new(
-        Disney::builder()
-            .base_color(Color::WHITE)
-            .roughness(0.0)
-            .anisotropic(0.0)
-            .sheen(0.0)
-            .sheen_tint(0.0)
-            .clearcoat(0.0)
-            .clearcoat_gloss(0.0)
-            .specular_tint(0.0)
-            .metallic(1.0)
-            .ior(1.5)
-            .flatness(0.0)
-            .spec_trans(0.0)
-            .diff_trans(0.0)
-            .thin(false)
-            .build(),
-    );
-
-    // let lab = Arc::new(Lambertian::new(Arc::new(SolidColor::new(Color::WHITE))));
-    // let metal = Arc::new(Metal::new(Color::WHITE, 0.5));
-    // let die = Arc::new(Dielectric::new(1.5));
-
-    // world.add(Box::new(Quad::new(
-    //     Vec3::new(-1.0, 0.0, -1.0),
-    //     Vec3::new(0.0, 0.0, 2.0),
-    //     Vec3::new(2.0, 0.0, 0.0),
-    //     disney,
-    // )));
-    world.add(Box::new(Sphere::new(Vec3::ZERO, 1.0, disney)));
-    // let light = Sphere::new(
-    //     Vec3::new(0.0, -0.3, 0.0),
-    //     0.2,
-    //     Arc::new(DiffuseLight::new(Arc::new(SolidColor::new(Color::new(
-    //         3.0, 3.0, 3.0,
-    //     ))))),
-    // );
-    // world.add(Box::new(light.clone()));
-
+fn disney_scene(options: &RenderOptions) -> RgbImage {
     let mut camera = Camera::default();
 
     camera.aspect_ratio = 16.0 / 9.0;
@@ -149,36 +550,110 @@ fn disney_scene() -> RgbImage {
     let back_tex = ImageTexture::new("rogland_clear_night_4k.exr");
     camera.background.texture = Arc::new(back_tex);
 
-    camera.render(&world, None)
+    options.render(camera, || {
+        let mut world = Hittables::default();
+
+        let disney = Arc::new(
+            Disney::builder()
+                .base_color(Color::WHITE)
+                .roughness(0.0)
+                .anisotropic(0.0)
+                .sheen(0.0)
+                .sheen_tint(0.0)
+                .clearcoat(0.0)
+                .clearcoat_gloss(0.0)
+                .specular_tint(0.0)
+                .metallic(1.0)
+                .ior(1.5)
+                .flatness(0.0)
+                .spec_trans(0.0)
+                .diff_trans(0.0)
+                .thin(false)
+                .build(),
+        );
+
+        // let lab = Arc::new(Lambertian::new(Arc::new(SolidColor::new(Color::WHITE))));
+        // let metal = Arc::new(Metal::new(Color::WHITE, 0.5));
+        // let die = Arc::new(Dielectric::new(1.5));
+
+        // world.add(Box::new(Quad::new(
+        //     Vec3::new(-1.0, 0.0, -1.0),
+        //     Vec3::new(0.0, 0.0, 2.0),
+        //     Vec3::new(2.0, 0.0, 0.0),
+        //     disney,
+        // )));
+        world.add(Box::new(Sphere::new(Vec3::ZERO, 1.0, disney)));
+        // let light = Sphere::new(
+        //     Vec3::new(0.0, -0.3, 0.0),
+        //     0.2,
+        //     Arc::new(DiffuseLight::new(Arc::new(SolidColor::new(Color::new(
+        //         3.0, 3.0, 3.0,
+        //     ))))),
+        // );
+        // world.add(Box::new(light.clone()));
+
+        (Box::new(world), None)
+    })
 }
 
-fn background_scene() -> RgbImage {
-    let mut world = Hittables::default();
-    // world.add(Box::new(Sphere::new(
-    //     Vec3::new(0.0, 0.0, 0.0),
-    //     1.0,
-    //     Arc::new(Dielectric::new(1.5)),
-    // )));
-
-    let metal_mat = Arc::new(Metal::new(Color::WHITE, 0.0));
-    let lam_mat = Arc::new(Lambertian::new(Arc::new(SolidColor::new(Color::new(
-        0.8, 0.8, 0.8,
-    )))));
-    let mix_mat = Arc::new(Mix::new(metal_mat.clone(), lam_mat.clone(), 0.5));
-    world.add(Box::new(Quad::new(
-        Vec3::new(-2.0, -2.0, -2.0),
-        Vec3::new(4.0, 0.0, 0.0),
-        Vec3::new(0.0, 0.0, 4.0),
-        mix_mat,
-    )));
-
-    let light_mat = Arc::new(DiffuseLight::new(Arc::new(SolidColor::new(
-        Color::new(0.75, 1.0, 0.58) * 1.0,
-    ))));
-    let mix_mat2 = Arc::new(Mix::new(light_mat.clone(), metal_mat.clone(), 0.5));
-    let light = Sphere::new(Vec3::new(1.5, -1.5, 0.0), 0.2, mix_mat2);
-    world.add(Box::new(light.clone()));
+fn subsurface_scene(options: &RenderOptions) -> RgbImage {
+    let mut camera = Camera::default();
+
+    camera.aspect_ratio = 16.0 / 9.0;
+    camera.image_width = 1280;
+    camera.samples_per_pixel = 500;
+    camera.max_depth = 64;
+
+    camera.vertical_fov_in_degrees = 40.0;
+    camera.look_from = Point3::new(-2.0, 1.0, 3.0) * 1.5;
+    camera.look_at = Point3::new(0.0, 0.0, 0.0);
+    camera.vec_up = Vec3::new(0.0, 1.0, 0.0);
+
+    camera.defocus_angle_in_degrees = 0.0;
+    camera.toon_map = ToonMap::ACES;
+
+    options.render(camera, || {
+        let mut world = Hittables::default();
+
+        let skin = Arc::new(
+            Disney::builder()
+                .base_color(Color::new(0.9, 0.65, 0.55))
+                .roughness(0.4)
+                .subsurface(1.0)
+                .build(),
+        );
+        let ball = Sphere::new(Vec3::ZERO, 1.0, skin);
+        world.add(Box::new(SubsurfaceMedium::new(
+            Box::new(ball),
+            Color::new(0.9, 0.65, 0.55),
+            Color::new(0.3, 0.12, 0.06),
+        )));
+
+        let ground = Arc::new(Lambertian::new(Arc::new(SolidColor::new(Color::new(
+            0.5, 0.5, 0.5,
+        )))));
+        world.add(Box::new(Quad::new(
+            Vec3::new(-4.0, -1.0, -4.0),
+            Vec3::new(8.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 8.0),
+            ground,
+        )));
+
+        let light = Quad::new(
+            Vec3::new(-1.0, 3.0, -1.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 2.0),
+            Arc::new(DiffuseLight::new(Arc::new(SolidColor::new(Color::new(
+                8.0, 8.0, 8.0,
+            ))))),
+        );
+        world.add(Box::new(light.clone()));
+
+        (Box::new(world), Some(Box::new(light)))
+    })
+}
 
+fn background_scene(options: &RenderOptions) -> RgbImage {
     let mut camera = Camera::default();
 
     camera.aspect_ratio = 16.0 / 9.0;
@@ -197,140 +672,38 @@ fn background_scene() -> RgbImage {
     let back_tex = ImageTexture::new("rogland_clear_night_4k.exr");
     camera.background.texture = Arc::new(back_tex);
 
-    let img = camera.render(&world, Some(&light));
+    options.render(camera, || {
+        let mut world = Hittables::default();
+        // world.add(Box::new(Sphere::new(
+        //     Vec3::new(0.0, 0.0, 0.0),
+        //     1.0,
+        //     Arc::new(Dielectric::new(1.5)),
+        // )));
+
+        let metal_mat = Arc::new(Metal::new(Color::WHITE, 0.0));
+        let lam_mat = Arc::new(Lambertian::new(Arc::new(SolidColor::new(Color::new(
+            0.8, 0.8, 0.8,
+        )))));
+        let mix_mat = Arc::new(Mix::new(metal_mat.clone(), lam_mat.clone(), 0.5));
+        world.add(Box::new(Quad::new(
+            Vec3::new(-2.0, -2.0, -2.0),
+            Vec3::new(4.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 4.0),
+            mix_mat,
+        )));
 
-    drop(world);
+        let light_mat = Arc::new(DiffuseLight::new(Arc::new(SolidColor::new(
+            Color::new(0.75, 1.0, 0.58) * 1.0,
+        ))));
+        let mix_mat2 = Arc::new(Mix::new(light_mat.clone(), metal_mat.clone(), 0.5));
+        let light = Sphere::new(Vec3::new(1.5, -1.5, 0.0), 0.2, mix_mat2);
+        world.add(Box::new(light.clone()));
 
-    img
+        (Box::new(world), Some(Box::new(light)))
+    })
 }
 
-fn obj_scene() -> RgbImage {
-    let miku = Wavefont::new("初音未来.obj", "Final", false).unwrap();
-    let ball = Wavefont::new("玻璃球.obj", "Final", false).unwrap();
-    let frame = Wavefont::new("外框.obj", "Final", false).unwrap();
-    let sound_box = Wavefont::new("声匣.obj", "Final", false).unwrap();
-    let mirror_door = Wavefont::new("镜子门.obj", "Final", false).unwrap();
-    let mirror = Wavefont::new("镜子.obj", "Final", true).unwrap();
-    let ring = Wavefont::new("环.obj", "Final", false).unwrap();
-    let portal_frame = Wavefont::new("传送门框.obj", "Final", false).unwrap();
-    let under_water = Wavefont::new("水下.obj", "Final", false).unwrap();
-    let water = Wavefont::new("水面.obj", "Final", true).unwrap();
-    let text = Wavefont::new("文字.obj", "Final", false).unwrap();
-    let mc = Wavefont::new("mc.obj", "Final", false).unwrap();
-    let umbralla = Wavefont::new("伞.obj", "Final", false).unwrap();
-    let checker = Wavefont::new("卒.obj", "Final", false).unwrap();
-
-    let forg = Wavefont::new("雾.obj", "Final", false).unwrap();
-    let forg = ConstantMedium::new_with_tex(
-        Box::new(forg),
-        0.05,
-        Arc::new(SolidColor::new(Color::new(1.0, 0.936, 0.381))),
-    );
-
-    let portal_material = Arc::new(Portal::new(
-        Color::WHITE,
-        Vec3::new(0.0, -6.3, 1.1),
-        Quaternion::identity(),
-    ));
-
-    let portal_anchor = Vec3::new(-5.8035, -0.9983, -7.7198);
-    let portal_u = Vec3::new(-3.8206, -0.9983, -8.3722) - portal_anchor;
-    let portal_v = Vec3::new(-5.8035, 3.1159, -7.7198) - portal_anchor;
-    let portal = Quad::new(portal_anchor, portal_u, portal_v, portal_material);
-
-    let translucent_material = Arc::new(
-        Disney::builder()
-            .diff_trans(1.0)
-            .roughness(1.0)
-            .thin(true)
-            .build(),
-    );
-    let translucent_board = Quad::new(
-        Vec3::new(-1.0, 0.0, -1.0),
-        Vec3::new(0.0, 0.0, 2.0),
-        Vec3::new(2.0, 0.0, 0.0),
-        translucent_material,
-    );
-    let translucent_board = Transform::new(
-        Box::new(translucent_board),
-        Some(Vec3::new(2.8145, -0.23603, -19.501)),
-        Some(Quaternion::from_axis_angle(
-            Vec3::new(0.993, -0.082, 0.082),
-            90.4,
-        )),
-        Some(Vec3::new(2.616, 1.0, 1.0)),
-    );
-
-    let light_material = Arc::new(DiffuseLight::new(Arc::new(SolidColor::new(Color::new(
-        4.0, 4.0, 4.0,
-    )))));
-    let light_board = Quad::new(
-        Vec3::new(-1.0, 0.0, -1.0),
-        Vec3::new(0.0, 0.0, 2.0),
-        Vec3::new(2.0, 0.0, 0.0),
-        light_material,
-    );
-    let light_board = Transform::new(
-        Box::new(light_board),
-        Some(Vec3::new(-0.44579, 5.2955, 0.89889)),
-        Some(Quaternion::from_axis_angle(
-            Vec3::new(0.921, 0.021, 0.389),
-            34.7,
-        )),
-        Some(Vec3::new(3.415, 3.415, 3.415)),
-    );
-    let yellow_material = Arc::new(DiffuseLight::new(Arc::new(SolidColor::new(
-        5.0 * Color::new(1.0, 0.687, 0.0),
-    ))));
-    let yellow_board = Quad::new(
-        Vec3::new(-1.0, 0.0, -1.0),
-        Vec3::new(0.0, 0.0, 2.0),
-        Vec3::new(2.0, 0.0, 0.0),
-        yellow_material,
-    );
-    let yellow_board = Transform::new(
-        Box::new(yellow_board),
-        Some(Vec3::new(-1.0053, -1.9655, -4.242)),
-        Some(Quaternion::from_axis_angle(
-            Vec3::new(0.766, 0.483, -0.423),
-            85.7,
-        )),
-        Some(Vec3::new(1.0, 1.0, 1.0) * 1.499),
-    );
-
-    let black_box = Transform::new(
-        Box::new(build_box(
-            Vec3::new(-1.0, -1.0, -1.0),
-            Vec3::new(1.0, 1.0, 1.0),
-            Arc::new(DiffuseLight::new(Arc::new(SolidColor::new(Color::BLACK)))),
-        )),
-        Some(Vec3::new(-4.9891, -6.4998, -8.3939)),
-        None,
-        Some(Vec3::new(1.0, 1.0, 1.0) * 6.244),
-    );
-
-    let mut world = Hittables::default();
-    world.add(Box::new(miku));
-    world.add(Box::new(light_board));
-    world.add(Box::new(ball));
-    world.add(Box::new(frame));
-    world.add(Box::new(sound_box));
-    world.add(Box::new(mirror_door));
-    world.add(Box::new(mirror));
-    world.add(Box::new(ring));
-    world.add(Box::new(portal_frame));
-    world.add(Box::new(under_water));
-    world.add(Box::new(water));
-    world.add(Box::new(text));
-    world.add(Box::new(translucent_board));
-    world.add(Box::new(mc));
-    world.add(Box::new(portal));
-    world.add(Box::new(umbralla));
-    world.add(Box::new(yellow_board));
-    world.add(Box::new(black_box));
-    world.add(Box::new(forg));
-    world.add(Box::new(checker));
-
+fn obj_scene(options: &RenderOptions) -> RgbImage {
     let mut camera = Camera::from_json("Final/camera.json").unwrap();
 
     camera.samples_per_pixel = 3000;
@@ -339,183 +712,178 @@ fn obj_scene() -> RgbImage {
     let backtex = ImageTexture::new("13.hdr");
     camera.background.texture = Arc::new(backtex);
 
-    let light_board = Quad::new(
-        Vec3::new(-1.0, 0.0, -1.0),
-        Vec3::new(0.0, 0.0, 2.0),
-        Vec3::new(2.0, 0.0, 0.0),
-        Arc::new(EmptyMaterial),
-    );
-    let light_board = Transform::new(
-        Box::new(light_board),
-        Some(Vec3::new(-0.44579, 5.2955, 0.89889)),
-        Some(Quaternion::from_axis_angle(
-            Vec3::new(0.921, 0.021, 0.389),
-            34.7,
-        )),
-        Some(Vec3::new(3.415, 3.415, 3.415)),
-    );
-    let yellow_board = Quad::new(
-        Vec3::new(-1.0, 0.0, -1.0),
-        Vec3::new(0.0, 0.0, 2.0),
-        Vec3::new(2.0, 0.0, 0.0),
-        Arc::new(EmptyMaterial),
-    );
-    let yellow_board = Transform::new(
-        Box::new(yellow_board),
-        Some(Vec3::new(-1.0053, -1.9655, -4.242)),
-        Some(Quaternion::from_axis_angle(
-            Vec3::new(0.766, 0.483, -0.423),
-            85.7,
-        )),
-        Some(Vec3::new(1.0, 1.0, 1.0) * 1.499),
-    );
-
-    let mut lights = Hittables::default();
-    lights.add(Box::new(light_board));
-    lights.add(Box::new(yellow_board));
-
-    let img = camera.render(&world, Some(&lights));
-
-    drop(world);
-
-    img
+    options.render(camera, || {
+        let miku = Wavefont::new("初音未来.obj", "Final", false).unwrap();
+        let ball = Wavefont::new("玻璃球.obj", "Final", false).unwrap();
+        let frame = Wavefont::new("外框.obj", "Final", false).unwrap();
+        let sound_box = Wavefont::new("声匣.obj", "Final", false).unwrap();
+        let mirror_door = Wavefont::new("镜子门.obj", "Final", false).unwrap();
+        let mirror = Wavefont::new("镜子.obj", "Final", true).unwrap();
+        let ring = Wavefont::new("环.obj", "Final", false).unwrap();
+        let portal_frame = Wavefont::new("传送门框.obj", "Final", false).unwrap();
+        let under_water = Wavefont::new("水下.obj", "Final", false).unwrap();
+        let water = Wavefont::new("水面.obj", "Final", true).unwrap();
+        let text = Wavefont::new("文字.obj", "Final", false).unwrap();
+        let mc = Wavefont::new("mc.obj", "Final", false).unwrap();
+        let umbralla = Wavefont::new("伞.obj", "Final", false).unwrap();
+        let checker = Wavefont::new("卒.obj", "Final", false).unwrap();
+
+        let forg = Wavefont::new("雾.obj", "Final", false).unwrap();
+        let forg = ConstantMedium::new_with_tex(
+            Box::new(forg),
+            0.05,
+            Arc::new(SolidColor::new(Color::new(1.0, 0.936, 0.381))),
+        );
+
+        let portal_material = Arc::new(Portal::new(
+            Color::WHITE,
+            Vec3::new(0.0, -6.3, 1.1),
+            Quaternion::identity(),
+        ));
+
+        let portal_anchor = Vec3::new(-5.8035, -0.9983, -7.7198);
+        let portal_u = Vec3::new(-3.8206, -0.9983, -8.3722) - portal_anchor;
+        let portal_v = Vec3::new(-5.8035, 3.1159, -7.7198) - portal_anchor;
+        let portal = Quad::new(portal_anchor, portal_u, portal_v, portal_material);
+
+        let translucent_material = Arc::new(
+            Disney::builder()
+                .diff_trans(1.0)
+                .roughness(1.0)
+                .thin(true)
+                .build(),
+        );
+        let translucent_board = Quad::new(
+            Vec3::new(-1.0, 0.0, -1.0),
+            Vec3::new(0.0, 0.0, 2.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            translucent_material,
+        );
+        let translucent_board = Transform::new(
+            Box::new(translucent_board),
+            Some(Vec3::new(2.8145, -0.23603, -19.501)),
+            Some(Quaternion::from_axis_angle(
+                Vec3::new(0.993, -0.082, 0.082),
+                90.4,
+            )),
+            Some(Vec3::new(2.616, 1.0, 1.0)),
+        );
+
+        let light_material = Arc::new(DiffuseLight::new(Arc::new(SolidColor::new(Color::new(
+            4.0, 4.0, 4.0,
+        )))));
+        let light_board = Quad::new(
+            Vec3::new(-1.0, 0.0, -1.0),
+            Vec3::new(0.0, 0.0, 2.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            light_material,
+        );
+        let light_board = Transform::new(
+            Box::new(light_board),
+            Some(Vec3::new(-0.44579, 5.2955, 0.89889)),
+            Some(Quaternion::from_axis_angle(
+                Vec3::new(0.921, 0.021, 0.389),
+                34.7,
+            )),
+            Some(Vec3::new(3.415, 3.415, 3.415)),
+        );
+        let yellow_material = Arc::new(DiffuseLight::new(Arc::new(SolidColor::new(
+            5.0 * Color::new(1.0, 0.687, 0.0),
+        ))));
+        let yellow_board = Quad::new(
+            Vec3::new(-1.0, 0.0, -1.0),
+            Vec3::new(0.0, 0.0, 2.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            yellow_material,
+        );
+        let yellow_board = Transform::new(
+            Box::new(yellow_board),
+            Some(Vec3::new(-1.0053, -1.9655, -4.242)),
+            Some(Quaternion::from_axis_angle(
+                Vec3::new(0.766, 0.483, -0.423),
+                85.7,
+            )),
+            Some(Vec3::new(1.0, 1.0, 1.0) * 1.499),
+        );
+
+        let black_box = Transform::new(
+            Box::new(build_box(
+                Vec3::new(-1.0, -1.0, -1.0),
+                Vec3::new(1.0, 1.0, 1.0),
+                Arc::new(DiffuseLight::new(Arc::new(SolidColor::new(Color::BLACK)))),
+            )),
+            Some(Vec3::new(-4.9891, -6.4998, -8.3939)),
+            None,
+            Some(Vec3::new(1.0, 1.0, 1.0) * 6.244),
+        );
+
+        let mut world = Hittables::default();
+        world.add(Box::new(miku));
+        world.add(Box::new(light_board));
+        world.add(Box::new(ball));
+        world.add(Box::new(frame));
+        world.add(Box::new(sound_box));
+        world.add(Box::new(mirror_door));
+        world.add(Box::new(mirror));
+        world.add(Box::new(ring));
+        world.add(Box::new(portal_frame));
+        world.add(Box::new(under_water));
+        world.add(Box::new(water));
+        world.add(Box::new(text));
+        world.add(Box::new(translucent_board));
+        world.add(Box::new(mc));
+        world.add(Box::new(portal));
+        world.add(Box::new(umbralla));
+        world.add(Box::new(yellow_board));
+        world.add(Box::new(black_box));
+        world.add(Box::new(forg));
+        world.add(Box::new(checker));
+
+        let light_board = Quad::new(
+            Vec3::new(-1.0, 0.0, -1.0),
+            Vec3::new(0.0, 0.0, 2.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            Arc::new(EmptyMaterial),
+        );
+        let light_board = Transform::new(
+            Box::new(light_board),
+            Some(Vec3::new(-0.44579, 5.2955, 0.89889)),
+            Some(Quaternion::from_axis_angle(
+                Vec3::new(0.921, 0.021, 0.389),
+                34.7,
+            )),
+            Some(Vec3::new(3.415, 3.415, 3.415)),
+        );
+        let yellow_board = Quad::new(
+            Vec3::new(-1.0, 0.0, -1.0),
+            Vec3::new(0.0, 0.0, 2.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            Arc::new(EmptyMaterial),
+        );
+        let yellow_board = Transform::new(
+            Box::new(yellow_board),
+            Some(Vec3::new(-1.0053, -1.9655, -4.242)),
+            Some(Quaternion::from_axis_angle(
+                Vec3::new(0.766, 0.483, -0.423),
+                85.7,
+            )),
+            Some(Vec3::new(1.0, 1.0, 1.0) * 1.499),
+        );
+
+        let mut lights = Hittables::default();
+        lights.add(Box::new(light_board));
+        lights.add(Box::new(yellow_board));
+
+        (Box::new(world), Some(Box::new(lights)))
+    })
 }
 
-fn final_scene(image_width: u32, samples_per_pixel: usize, max_depth: u32) -> RgbImage {
-    let mut boxes1 = Hittables::default();
-    let ground_tex = Arc::new(SolidColor::new(Color::new(0.48, 0.83, 0.53)));
-    let ground = Arc::new(Lambertian::new(ground_tex));
-
-    const BOXES_PER_SIDE: usize = 20;
-    for i in 0..BOXES_PER_SIDE {
-        for j in 0..BOXES_PER_SIDE {
-            let w = 100.0;
-            let x0 = -1000.0 + i as f64 * w;
-            let z0 = -1000.0 + j as f64 * w;
-            let y0 = 0.0;
-            let x1 = x0 + w;
-            let y1 = Random::random_range(1.0..101.0);
-            let z1 = z0 + w;
-
-            boxes1.add(Box::new(build_box(
-                Point3::new(x0, y0, z0),
-                Point3::new(x1, y1, z1),
-                ground.clone(),
-            )));
-        }
-    }
-
-    let earth_tex = Arc::new(ImageTexture::new("earthmap.jpg"));
-    let earth_material = Arc::new(Lambertian::new(earth_tex));
-    let earth = Sphere::new(Point3::new(400.0, 200.0, 400.0), 100.0, earth_material);
-
-    let mut world = Hittables::default();
-
-    world.add(Box::new(earth));
-
-    world.add(Box::new(BVH::new(boxes1)));
-
-    let light_tex = Arc::new(SolidColor::new(Color::new(7.0, 7.0, 7.0)));
-    let light_material = Arc::new(DiffuseLight::new(light_tex));
-    let light = Box::new(Quad::new(
-        Point3::new(123.0, 554.0, 147.0),
-        Vec3::new(300.0, 0.0, 0.0),
-        Vec3::new(0.0, 0.0, 265.0),
-        light_material,
-    ));
-    world.add(light);
-
-    let center1 = Point3::new(400.0, 400.0, 200.0);
-    let center2 = center1 + Vec3::new(30.0, 0.0, 0.0);
-    let sphere_tex = Arc::new(SolidColor::new(Color::new(0.7, 0.3, 0.1)));
-    let sphere_material = Arc::new(Lambertian::new(sphere_tex));
-    world.add(Box::new(Sphere::new_with_motion(
-        center1,
-        center2,
-        50.0,
-        sphere_material,
-    )));
-
-    let glass_material = Arc::new(Dielectric::new(
-        Arc::new(SolidColor::new(Color::WHITE)),
-        1.5,
-    ));
-    world.add(Box::new(Sphere::new(
-        Point3::new(260.0, 150.0, 45.0),
-        50.0,
-        glass_material.clone(),
-    )));
-
-    let metal_material = Arc::new(Metal::new(Color::new(0.8, 0.8, 0.9), 1.0));
-    world.add(Box::new(Sphere::new(
-        Point3::new(0.0, 150.0, 145.0),
-        50.0,
-        metal_material,
-    )));
-
-    let boundary = Box::new(Sphere::new(
-        Point3::new(360.0, 150.0, 145.0),
-        70.0,
-        glass_material,
-    ));
-
-    world.add(boundary);
-
-    let boundary = Box::new(Sphere::new(
-        Point3::new(360.0, 150.0, 145.0),
-        70.0,
-        Arc::new(EmptyMaterial),
-    ));
-
-    let smoke_tex = Arc::new(SolidColor::new(Color::new(0.2, 0.4, 0.9)));
-    world.add(Box::new(ConstantMedium::new_with_tex(
-        boundary, 0.2, smoke_tex,
-    )));
-    let boundary = Box::new(Sphere::new(
-        Point3::new(0.0, 0.0, 0.0),
-        5000.0,
-        Arc::new(EmptyMaterial),
-    ));
-    let white_tex = Arc::new(SolidColor::new(Color::WHITE));
-    world.add(Box::new(ConstantMedium::new_with_tex(
-        boundary, 0.0001, white_tex,
-    )));
-
-    let pertext = Arc::new(NoiseTexture::new(0.2));
-    let noise_tex = Arc::new(Lambertian::new(pertext));
-    world.add(Box::new(Sphere::new(
-        Point3::new(220.0, 280.0, 300.0),
-        80.0,
-        noise_tex,
-    )));
-
-    let mut boxes2 = Hittables::default();
-    let dim_white_color = Arc::new(SolidColor::new(Color::new(0.73, 0.73, 0.73)));
-    let white = Arc::new(Lambertian::new(dim_white_color));
-    const NS: usize = 1000;
-    for _ in 0..NS {
-        boxes2.add(Box::new(Sphere::new(
-            Point3::random_range(0.0..165.0),
-            10.0,
-            white.clone(),
-        )));
-    }
-
-    world.add(Box::new(Transform::new(
-        Box::new(BVH::new(boxes2)),
-        Some(Vec3::new(-100.0, 270.0, 395.0)),
-        Some(Quaternion::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), 15.0)),
-        None,
-    )));
-
-    let mut lights = Hittables::default();
-    lights.add(Box::new(Quad::new(
-        Point3::new(123.0, 554.0, 147.0),
-        Vec3::new(300.0, 0.0, 0.0),
-        Vec3::new(0.0, 0.0, 265.0),
-        Arc::new(EmptyMaterial),
-    )));
-
+fn final_scene(
+    image_width: u32,
+    samples_per_pixel: usize,
+    max_depth: u32,
+    options: &RenderOptions,
+) -> RgbImage {
     let mut camera = Camera::default();
 
     camera.aspect_ratio = 1.0;
@@ -530,93 +898,146 @@ fn final_scene(image_width: u32, samples_per_pixel: usize, max_depth: u32) -> Rg
 
     camera.defocus_angle_in_degrees = 0.0;
 
-    let img = camera.render(&world, Some(&lights));
+    options.render(camera, || {
+        let mut boxes1 = Hittables::default();
+        let ground_tex = Arc::new(SolidColor::new(Color::new(0.48, 0.83, 0.53)));
+        let ground = Arc::new(Lambertian::new(ground_tex));
+
+        const BOXES_PER_SIDE: usize = 20;
+        for i in 0..BOXES_PER_SIDE {
+            for j in 0..BOXES_PER_SIDE {
+                let w = 100.0;
+                let x0 = -1000.0 + i as f64 * w;
+                let z0 = -1000.0 + j as f64 * w;
+                let y0 = 0.0;
+                let x1 = x0 + w;
+                let y1 = Random::random_range(1.0..101.0);
+                let z1 = z0 + w;
+
+                boxes1.add(Box::new(build_box(
+                    Point3::new(x0, y0, z0),
+                    Point3::new(x1, y1, z1),
+                    ground.clone(),
+                )));
+            }
+        }
 
-    drop(world);
-    drop(lights);
+        let earth_tex = Arc::new(ImageTexture::new("earthmap.jpg"));
+        let earth_material = Arc::new(Lambertian::new(earth_tex));
+        let earth = Sphere::new(Point3::new(400.0, 200.0, 400.0), 100.0, earth_material);
+
+        let mut world = Hittables::default();
+
+        world.add(Box::new(earth));
+
+        world.add(Box::new(BVH::new(boxes1)));
+
+        let light_tex = Arc::new(SolidColor::new(Color::new(7.0, 7.0, 7.0)));
+        let light_material = Arc::new(DiffuseLight::new(light_tex));
+        let light = Box::new(Quad::new(
+            Point3::new(123.0, 554.0, 147.0),
+            Vec3::new(300.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 265.0),
+            light_material,
+        ));
+        world.add(light);
+
+        let center1 = Point3::new(400.0, 400.0, 200.0);
+        let center2 = center1 + Vec3::new(30.0, 0.0, 0.0);
+        let sphere_tex = Arc::new(SolidColor::new(Color::new(0.7, 0.3, 0.1)));
+        let sphere_material = Arc::new(Lambertian::new(sphere_tex));
+        world.add(Box::new(Sphere::new_with_motion(
+            center1,
+            center2,
+            50.0,
+            sphere_material,
+        )));
 
-    img
-}
+        let glass_material = Arc::new(Dielectric::new(
+            Arc::new(SolidColor::new(Color::WHITE)),
+            1.5,
+        ));
+        world.add(Box::new(Sphere::new(
+            Point3::new(260.0, 150.0, 45.0),
+            50.0,
+            glass_material.clone(),
+        )));
 
-fn cornell_box() -> RgbImage {
-    let mut world = Hittables::default();
-    let mut lights = Hittables::default();
-
-    let red_tex = Arc::new(SolidColor::new(Color::new(0.65, 0.05, 0.05)));
-    let white_tex = Arc::new(SolidColor::new(Color::new(0.73, 0.73, 0.73)));
-    let green_tex = Arc::new(SolidColor::new(Color::new(0.12, 0.45, 0.15)));
-    let light_tex = Arc::new(SolidColor::new(Color::new(15.0, 15.0, 15.0)));
-
-    let red = Arc::new(Lambertian::new(red_tex));
-    let white = Arc::new(Lambertian::new(white_tex));
-    let green = Arc::new(Lambertian::new(green_tex));
-    let light = Arc::new(DiffuseLight::new(light_tex));
-
-    world.add(Box::new(Quad::new(
-        Point3::new(555.0, 0.0, 0.0),
-        Vec3::new(0.0, 555.0, 0.0),
-        Vec3::new(0.0, 0.0, 555.0),
-        green,
-    )));
-    world.add(Box::new(Quad::new(
-        Point3::new(0.0, 0.0, 0.0),
-        Vec3::new(0.0, 555.0, 0.0),
-        Vec3::new(0.0, 0.0, 555.0),
-        red,
-    )));
-    world.add(Box::new(Quad::new(
-        Point3::new(343.0, 554.0, 332.0),
-        Vec3::new(-130.0, 0.0, 0.0),
-        Vec3::new(0.0, 0.0, -105.0),
-        light.clone(),
-    )));
-    world.add(Box::new(Quad::new(
-        Point3::new(0.0, 0.0, 0.0),
-        Vec3::new(555.0, 0.0, 0.0),
-        Vec3::new(0.0, 0.0, 555.0),
-        white.clone(),
-    )));
-    world.add(Box::new(Quad::new(
-        Point3::new(555.0, 555.0, 555.0),
-        Vec3::new(-555.0, 0.0, 0.0),
-        Vec3::new(0.0, 0.0, -555.0),
-        white.clone(),
-    )));
-    world.add(Box::new(Quad::new(
-        Point3::new(0.0, 0.0, 555.0),
-        Vec3::new(555.0, 0.0, 0.0),
-        Vec3::new(0.0, 555.0, 0.0),
-        white.clone(),
-    )));
-
-    let box1 = Box::new(build_box(
-        Point3::ZERO,
-        Point3::new(165.0, 330.0, 165.0),
-        white.clone(),
-    ));
-    let box1 = Box::new(Transform::new(
-        box1,
-        Some(Vec3::new(265.0, 0.0, 295.0)),
-        Some(Quaternion::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), 15.0)),
-        None,
-    ));
+        let metal_material = Arc::new(Metal::new(Color::new(0.8, 0.8, 0.9), 1.0));
+        world.add(Box::new(Sphere::new(
+            Point3::new(0.0, 150.0, 145.0),
+            50.0,
+            metal_material,
+        )));
+
+        let boundary = Box::new(Sphere::new(
+            Point3::new(360.0, 150.0, 145.0),
+            70.0,
+            glass_material,
+        ));
+
+        world.add(boundary);
 
-    world.add(box1);
+        let boundary = Box::new(Sphere::new(
+            Point3::new(360.0, 150.0, 145.0),
+            70.0,
+            Arc::new(EmptyMaterial),
+        ));
 
-    // let glass = Dielectric::new(1.5);
-    // world.add(Box::new(Sphere::new(
-    //     Point3::new(190.0, 90.0, 190.0),
-    //     90.0,
-    //     &glass,
-    // )));
+        let smoke_tex = Arc::new(SolidColor::new(Color::new(0.2, 0.4, 0.9)));
+        world.add(Box::new(ConstantMedium::new_with_tex(
+            boundary, 0.2, smoke_tex,
+        )));
+        let boundary = Box::new(Sphere::new(
+            Point3::new(0.0, 0.0, 0.0),
+            5000.0,
+            Arc::new(EmptyMaterial),
+        ));
+        let white_tex = Arc::new(SolidColor::new(Color::WHITE));
+        world.add(Box::new(ConstantMedium::new_with_tex(
+            boundary, 0.0001, white_tex,
+        )));
+
+        let pertext = Arc::new(NoiseTexture::new(0.2));
+        let noise_tex = Arc::new(Lambertian::new(pertext));
+        world.add(Box::new(Sphere::new(
+            Point3::new(220.0, 280.0, 300.0),
+            80.0,
+            noise_tex,
+        )));
+
+        let mut boxes2 = Hittables::default();
+        let dim_white_color = Arc::new(SolidColor::new(Color::new(0.73, 0.73, 0.73)));
+        let white = Arc::new(Lambertian::new(dim_white_color));
+        const NS: usize = 1000;
+        for _ in 0..NS {
+            boxes2.add(Box::new(Sphere::new(
+                Point3::random_range(0.0..165.0),
+                10.0,
+                white.clone(),
+            )));
+        }
+
+        world.add(Box::new(Transform::new(
+            Box::new(BVH::new(boxes2)),
+            Some(Vec3::new(-100.0, 270.0, 395.0)),
+            Some(Quaternion::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), 15.0)),
+            None,
+        )));
 
-    lights.add(Box::new(Quad::new(
-        Point3::new(343.0, 554.0, 332.0),
-        Vec3::new(-130.0, 0.0, 0.0),
-        Vec3::new(0.0, 0.0, -105.0),
-        light,
-    )));
+        let mut lights = Hittables::default();
+        lights.add(Box::new(Quad::new(
+            Point3::new(123.0, 554.0, 147.0),
+            Vec3::new(300.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 265.0),
+            Arc::new(EmptyMaterial),
+        )));
+
+        (Box::new(world), Some(Box::new(lights)))
+    })
+}
 
+fn cornell_box(options: &RenderOptions) -> RgbImage {
     let mut camera = Camera::default();
     camera.aspect_ratio = 1.0;
     camera.image_width = 1080;
@@ -630,10 +1051,85 @@ fn cornell_box() -> RgbImage {
 
     camera.defocus_angle_in_degrees = 0.0;
 
-    let img = camera.render(&world, Some(&lights));
+    options.render(camera, || {
+        let mut world = Hittables::default();
+        let mut lights = Hittables::default();
+
+        let red_tex = Arc::new(SolidColor::new(Color::new(0.65, 0.05, 0.05)));
+        let white_tex = Arc::new(SolidColor::new(Color::new(0.73, 0.73, 0.73)));
+        let green_tex = Arc::new(SolidColor::new(Color::new(0.12, 0.45, 0.15)));
+        let light_tex = Arc::new(SolidColor::new(Color::new(15.0, 15.0, 15.0)));
+
+        let red = Arc::new(Lambertian::new(red_tex));
+        let white = Arc::new(Lambertian::new(white_tex));
+        let green = Arc::new(Lambertian::new(green_tex));
+        let light = Arc::new(DiffuseLight::new(light_tex));
+
+        world.add(Box::new(Quad::new(
+            Point3::new(555.0, 0.0, 0.0),
+            Vec3::new(0.0, 555.0, 0.0),
+            Vec3::new(0.0, 0.0, 555.0),
+            green,
+        )));
+        world.add(Box::new(Quad::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 555.0, 0.0),
+            Vec3::new(0.0, 0.0, 555.0),
+            red,
+        )));
+        world.add(Box::new(Quad::new(
+            Point3::new(343.0, 554.0, 332.0),
+            Vec3::new(-130.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, -105.0),
+            light.clone(),
+        )));
+        world.add(Box::new(Quad::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Vec3::new(555.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 555.0),
+            white.clone(),
+        )));
+        world.add(Box::new(Quad::new(
+            Point3::new(555.0, 555.0, 555.0),
+            Vec3::new(-555.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, -555.0),
+            white.clone(),
+        )));
+        world.add(Box::new(Quad::new(
+            Point3::new(0.0, 0.0, 555.0),
+            Vec3::new(555.0, 0.0, 0.0),
+            Vec3::new(0.0, 555.0, 0.0),
+            white.clone(),
+        )));
 
-    drop(world);
-    drop(lights);
+        let box1 = Box::new(build_box(
+            Point3::ZERO,
+            Point3::new(165.0, 330.0, 165.0),
+            white.clone(),
+        ));
+        let box1 = Box::new(Transform::new(
+            box1,
+            Some(Vec3::new(265.0, 0.0, 295.0)),
+            Some(Quaternion::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), 15.0)),
+            None,
+        ));
+
+        world.add(box1);
+
+        // let glass = Dielectric::new(1.5);
+        // world.add(Box::new(Sphere::new(
+        //     Point3::new(190.0, 90.0, 190.0),
+        //     90.0,
+        //     &glass,
+        // )));
+
+        lights.add(Box::new(Quad::new(
+            Point3::new(343.0, 554.0, 332.0),
+            Vec3::new(-130.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, -105.0),
+            light,
+        )));
 
-    img
+        (Box::new(world), Some(Box::new(lights)))
+    })
 }
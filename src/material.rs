@@ -1,5 +1,6 @@
 pub mod disney;
 pub mod portal;
+pub mod subsurface;
 
 use std::sync::Arc;
 
@@ -18,6 +19,8 @@ use crate::{
 pub enum ScatterRecord<'a> {
     PDF(Box<dyn PDF + 'a>),
     Ray((Color, Ray)),
+    // 随机游走在介质内部的碰撞（包括空碰撞），不计入 max_depth
+    Walk((Color, Ray)),
 }
 
 pub trait Material: Send + Sync {
@@ -31,6 +34,21 @@ pub trait Material: Send + Sync {
     fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Color {
         Color::BLACK
     }
+
+    // 材质的标识，默认为对象地址，包装其他材质时应返回被包装材质的标识
+    fn id(&self) -> usize {
+        (self as *const Self).cast::<()>() as usize
+    }
+
+    // 用于诊断信息的材质名称
+    fn name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
+
+    // 参与介质的相函数，描边等只关心表面的计算会穿过这类碰撞
+    fn is_medium(&self) -> bool {
+        false
+    }
 }
 
 pub struct EmptyMaterial;
@@ -40,7 +58,9 @@ impl Material for EmptyMaterial {
 
     fn scatter(&self, _r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
         let albedo = Color::new(0.75, 0.75, 0.75);
-        let pdf_ptr = Box::new(CosinePDF::new(albedo, &rec.normal));
+        let pdf_ptr = Box::new(
+            CosinePDF::new(albedo, &rec.normal).with_geometric_normal(&rec.geometric_normal),
+        );
 
         Some(ScatterRecord::PDF(pdf_ptr))
     }
@@ -58,8 +78,10 @@ impl Lambertian {
 
 impl Material for Lambertian {
     fn scatter(&self, _r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
-        let albedo = self.texture.value(rec.u, rec.v, &rec.p);
-        let pdf_ptr = Box::new(CosinePDF::new(albedo, &rec.normal));
+        let albedo = self.texture.value_at(rec);
+        let pdf_ptr = Box::new(
+            CosinePDF::new(albedo, &rec.normal).with_geometric_normal(&rec.geometric_normal),
+        );
 
         Some(ScatterRecord::PDF(pdf_ptr))
     }
@@ -137,7 +159,7 @@ impl Material for Dielectric {
         };
 
         Some(ScatterRecord::Ray((
-            self.attentuation.value(rec.u, rec.v, &rec.p),
+            self.attentuation.value_at(rec),
             Ray::new_with_time(rec.p, direction, *r_in.time()),
         )))
     }
@@ -169,7 +191,7 @@ impl DiffuseLight {
 
 impl Material for DiffuseLight {
     fn emitted(&self, ray: &Ray, rec: &HitRecord) -> Color {
-        let self_emit = self.texture.value(rec.u, rec.v, &rec.p);
+        let self_emit = self.texture.value_at(rec);
         let mat_emit = match &self.material {
             Some(material) => material.emitted(ray, rec),
             None => Color::BLACK,
@@ -197,13 +219,17 @@ impl Isotropic {
 
 impl Material for Isotropic {
     fn scatter(&self, _r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
-        let albedo = self.texture.value(rec.u, rec.v, &rec.p);
+        let albedo = self.texture.value_at(rec);
         let pdf_ptr = Box::new(SpherePDF {
             attenuation: albedo,
         });
 
         Some(ScatterRecord::PDF(pdf_ptr))
     }
+
+    fn is_medium(&self) -> bool {
+        true
+    }
 }
 
 pub struct Transparent;
@@ -1,15 +1,17 @@
-use std::f64::consts::PI;
+use std::{f64::consts::PI, sync::Arc};
 
 use crate::{
+    hit::HitRecord,
     material::{Material, ScatterRecord},
     pdf::PDF,
+    texture::Texture,
     utils::{
         color::Color,
         fresnel::{dielectric, schlick, schlick_f64, schlick_r0_from_relative_ior, schlick_weight},
         lerp,
         onb::OrthonormalBasis,
         random::Random,
-        vec3::{Point3, UnitVec3, Vec3},
+        vec3::{UnitVec3, Vec3},
     },
 };
 
@@ -29,6 +31,8 @@ pub struct DisneyParameters {
     pub flatness: f64,
     pub spec_trans: f64,
     pub diff_trans: f64,
+    // 漫反射中进入物体内部进行随机游走的比例，需要配合 SubsurfaceMedium 使用
+    pub subsurface: f64,
 
     pub thin: bool,
 }
@@ -49,12 +53,14 @@ impl Default for DisneyParameters {
             flatness: 0.0,
             spec_trans: 0.0,
             diff_trans: 0.0,
+            subsurface: 0.0,
             thin: false,
         }
     }
 }
 
-type DisneyParamFn = Box<dyn Fn(f64, f64, &Point3) -> DisneyParameters + Send + Sync>;
+// 由击中点求材质参数，纹理可以通过 Texture::value_at 读取顶点颜色等属性
+type DisneyParamFn = Box<dyn Fn(&HitRecord) -> DisneyParameters + Send + Sync>;
 
 pub struct Disney {
     pub param_fn: DisneyParamFn,
@@ -63,7 +69,7 @@ pub struct Disney {
 impl Default for Disney {
     fn default() -> Self {
         Self {
-            param_fn: Box::new(|_, _, _| DisneyParameters::default()),
+            param_fn: Box::new(|_| DisneyParameters::default()),
         }
     }
 }
@@ -72,16 +78,20 @@ impl Material for Disney {
     fn scatter(
         &self,
         r_in: &crate::utils::ray::Ray,
-        rec: &crate::hit::HitRecord,
+        rec: &HitRecord,
     ) -> Option<super::ScatterRecord> {
         let v_out = UnitVec3::from_vec3(-r_in.direction()).unwrap();
 
+        let params = (self.param_fn)(rec);
+
         let disney_pdf = Box::new(DisneyPDF::new(
             self,
             &rec.normal,
+            &rec.geometric_normal,
+            &rec.dpdu,
             &v_out,
             rec.front_face,
-            (self.param_fn)(rec.u, rec.v, &rec.p),
+            params,
         ));
 
         Some(ScatterRecord::PDF(disney_pdf))
@@ -341,9 +351,24 @@ impl Disney {
 
             let sheen = Disney::evaluate_sheen(param, v_out, &v_half, v_in);
 
-            reflectance += diffuse_weight * (diffuse * param.base_color + sheen);
-            forward_pdf += p_diffuse * forward_diffuse_pdf_w;
-            reverse_pdf += p_diffuse * reverse_diffuse_pdf_w;
+            // 次表面散射在进入和离开时各乘一次颜色，因此每次只取平方根
+            let diffuse_color = if is_transmission && !param.thin && param.subsurface > 0.0 {
+                param.base_color.sqrt()
+            } else {
+                param.base_color
+            };
+
+            // 漫反射与漫透射按采样时翻转方向的概率分配能量，pdf 也乘以落在这一侧的概率
+            let p_flip = Disney::diffuse_flip_probability(param);
+            let p_side = if is_transmission {
+                p_flip
+            } else {
+                1.0 - p_flip
+            };
+
+            reflectance += p_side * diffuse_weight * (diffuse * diffuse_color + sheen);
+            forward_pdf += p_diffuse * p_side * forward_diffuse_pdf_w;
+            reverse_pdf += p_diffuse * p_side * reverse_diffuse_pdf_w;
         };
 
         if trans_weight > 0.0 {
@@ -400,6 +425,12 @@ impl Disney {
         (reflectance, forward_pdf, reverse_pdf)
     }
 
+    // 漫反射采样时翻到另一侧的概率，来自漫透射与（非薄片的）次表面散射
+    fn diffuse_flip_probability(param: &DisneyParameters) -> f64 {
+        let subsurface = if param.thin { 0.0 } else { param.subsurface };
+        (param.diff_trans + (1.0 - param.diff_trans) * subsurface).clamp(0.0, 1.0)
+    }
+
     fn calculate_lobe_pdfs(param: &DisneyParameters) -> (f64, f64, f64, f64) {
         let metallic_brdf = param.metallic;
         let specular_bsdf = (1.0 - param.metallic) * param.spec_trans;
@@ -515,6 +546,9 @@ fn thin_transmission_roughness(ior: f64, roughness: f64) -> f64 {
 
 pub struct DisneyPDF {
     uvw: OrthonormalBasis,
+    // 世界坐标下的几何法线与出射方向，用于检查方向是否与着色法线位于几何表面的同一侧
+    geometric_normal: UnitVec3,
+    world_v_out: UnitVec3,
     v_out: UnitVec3,
     front_face: bool,
     params: DisneyParameters,
@@ -524,21 +558,37 @@ impl DisneyPDF {
     pub fn new(
         _material: &Disney,
         normal: &UnitVec3,
+        geometric_normal: &UnitVec3,
+        tangent: &Vec3,
         v_out: &UnitVec3,
         front_face: bool,
         params: DisneyParameters,
     ) -> Self {
-        let uvw = OrthonormalBasis::new(normal);
+        // 各向异性的主方向沿表面 u 方向
+        let uvw = OrthonormalBasis::from_tangent(normal, tangent);
+        let world_v_out = *v_out;
         let v_out = UnitVec3::from_vec3_raw(uvw.world_to_onb(v_out.into_inner()));
 
         Self {
             uvw,
+            geometric_normal: *geometric_normal,
+            world_v_out,
             v_out,
             front_face,
             params,
         }
     }
 
+    // 对着色法线是反射（或透射）的方向，对几何法线也必须是反射（或透射）
+    fn consistent_with_surface(&self, direction: &Vec3) -> bool {
+        let n_g = self.geometric_normal.as_inner();
+        let n_s = self.uvw.v();
+        let v_out = self.world_v_out.as_inner();
+        let geometric = direction.dot(n_g) * v_out.dot(n_g);
+        let shading = direction.dot(n_s) * v_out.dot(n_s);
+        geometric != 0.0 && (geometric > 0.0) == (shading > 0.0)
+    }
+
     fn sample_disney_brdf(&self) -> Option<UnitVec3> {
         let v_out = &self.v_out;
 
@@ -592,7 +642,7 @@ impl DisneyPDF {
 
         let mut v_in =
             UnitVec3::from_vec3_raw(sign * UnitVec3::random_cosine_direction().into_inner());
-        if Random::f64() <= self.params.diff_trans {
+        if Random::f64() < Disney::diffuse_flip_probability(&self.params) {
             v_in = -v_in;
         }
 
@@ -660,6 +710,9 @@ impl DisneyPDF {
 
 impl PDF for DisneyPDF {
     fn value(&self, direction: &Vec3) -> (Color, f64) {
+        if !self.consistent_with_surface(direction) {
+            return (Color::BLACK, 0.0);
+        }
         let v_in = UnitVec3::from_vec3_raw(
             self.uvw
                 .world_to_onb(UnitVec3::from_vec3(*direction).unwrap().into_inner()),
@@ -675,7 +728,7 @@ impl PDF for DisneyPDF {
 
         let p = Random::f64();
 
-        if p <= p_specular {
+        let direction = if p <= p_specular {
             self.sample_disney_brdf()
         } else if p <= p_specular + p_clearcoat {
             self.sample_disney_clearcoat()
@@ -685,7 +738,8 @@ impl PDF for DisneyPDF {
             self.disney_spec_transmission()
         } else {
             panic!("The conditions should be exhausted!");
-        }
+        };
+        direction.filter(|d| self.consistent_with_surface(d.as_inner()))
     }
 }
 
@@ -719,6 +773,7 @@ fn sample_ggx_vndf_anisotropic(v_out: &UnitVec3, ax: f64, ay: f64, u1: f64, u2:
 #[derive(Default)]
 pub struct DisneyBuilder {
     params: DisneyParameters,
+    base_color_texture: Option<Arc<dyn Texture>>,
 }
 
 impl DisneyBuilder {
@@ -731,6 +786,12 @@ impl DisneyBuilder {
         self
     }
 
+    // 设置后以纹理在击中点的值代替 base_color
+    pub fn base_color_texture(mut self, texture: Arc<dyn Texture>) -> Self {
+        self.base_color_texture = Some(texture);
+        self
+    }
+
     pub fn roughness(mut self, roughness: f64) -> Self {
         self.params.roughness = roughness;
         self
@@ -791,6 +852,11 @@ impl DisneyBuilder {
         self
     }
 
+    pub fn subsurface(mut self, subsurface: f64) -> Self {
+        self.params.subsurface = subsurface;
+        self
+    }
+
     pub fn thin(mut self, thin: bool) -> Self {
         self.params.thin = thin;
         self
@@ -798,8 +864,45 @@ impl DisneyBuilder {
 
     pub fn build(self) -> Disney {
         let params = self.params;
-        Disney {
-            param_fn: Box::new(move |_, _, _| params.clone()),
-        }
+        let param_fn: DisneyParamFn = match self.base_color_texture {
+            Some(texture) => Box::new(move |rec| DisneyParameters {
+                base_color: texture.value_at(rec),
+                ..params.clone()
+            }),
+            None => Box::new(move |_| params.clone()),
+        };
+        Disney { param_fn }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diffuse_flip_pdf() {
+        let params = DisneyParameters {
+            subsurface: 0.25,
+            ..DisneyParameters::default()
+        };
+        let disney = Disney::default();
+        let v_out = UnitVec3::from_vec3(Vec3::new(1.0, 1.0, 0.0)).unwrap();
+        let pdf = DisneyPDF::new(
+            &disney,
+            &UnitVec3::Y_AXIS,
+            &UnitVec3::Y_AXIS,
+            UnitVec3::X_AXIS.as_inner(),
+            &v_out,
+            true,
+            params,
+        );
+
+        // 透射一侧只有漫反射被翻转的部分，pdf 乘以翻转概率
+        let (_, pdf_transmitted) = pdf.value(&Vec3::new(-0.6, -0.8, 0.0));
+        let (_, p_diffuse, ..) = Disney::calculate_lobe_pdfs(&pdf.params);
+        assert!((pdf_transmitted - p_diffuse * 0.25 * 0.8).abs() < 1e-9);
+
+        let (_, pdf_reflected) = pdf.value(&Vec3::new(-0.6, 0.8, 0.0));
+        assert!(pdf_reflected > pdf_transmitted);
     }
 }
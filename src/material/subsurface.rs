@@ -0,0 +1,102 @@
+use crate::{
+    hit::HitRecord,
+    material::{Material, ScatterRecord},
+    utils::{color::Color, random::Random, ray::Ray, vec3::UnitVec3},
+};
+
+// 随机游走次表面散射在介质内部碰撞点使用的材质
+// 使用以最大消光系数为上界的 delta tracking，每次（可能为空的）碰撞按通道计算权重，
+// 因此不同颜色通道可以有不同的平均自由程
+pub struct RandomWalk {
+    sigma_s: Color,
+    sigma_n: Color,
+    majorant: f64,
+}
+
+impl RandomWalk {
+    // albedo 为多次散射后的表面反照率，mean_free_path 为各通道的平均自由程
+    pub fn new(albedo: Color, mean_free_path: Color) -> RandomWalk {
+        let sigma_t = Color::from(mean_free_path.e().map(|x| 1.0 / x.max(1e-8)));
+        let single_albedo = Color::from(albedo.e().map(RandomWalk::single_scattering_albedo));
+        let sigma_s = sigma_t * single_albedo;
+        let majorant = sigma_t.e().into_iter().fold(0.0, f64::max);
+        let sigma_n = Color::from(sigma_t.e().map(|x| majorant - x));
+
+        RandomWalk {
+            sigma_s,
+            sigma_n,
+            majorant,
+        }
+    }
+
+    pub fn majorant(&self) -> f64 {
+        self.majorant
+    }
+
+    // 由多次散射反照率反推单次散射反照率，
+    // 参考 Christensen & Burley, "Approximate Reflectance Profiles for Efficient Subsurface Scattering"
+    // 反照率为 1 时不吸收，游走由相机的 MAX_WALK_STEPS 终止
+    fn single_scattering_albedo(albedo: f64) -> f64 {
+        let a = albedo.clamp(0.0, 1.0);
+        let s = 4.09712 + 4.20863 * a - (9.59217 + 41.6808 * a + 17.7126 * a * a).sqrt();
+        (1.0 - s * s).clamp(0.0, 1.0)
+    }
+
+    fn mean(c: &Color) -> f64 {
+        (c[0] + c[1] + c[2]) / 3.0
+    }
+}
+
+impl Material for RandomWalk {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let p_scatter = RandomWalk::mean(&self.sigma_s) / self.majorant;
+        let p_null = RandomWalk::mean(&self.sigma_n) / self.majorant;
+
+        let xi = Random::f64();
+        if xi < p_scatter {
+            let weight = self.sigma_s / (p_scatter * self.majorant);
+            let direction = UnitVec3::random_unit_vector().into_inner();
+            Some(ScatterRecord::Walk((
+                weight,
+                Ray::new_with_time(rec.p, direction, *r_in.time()),
+            )))
+        } else if xi < p_scatter + p_null {
+            let weight = self.sigma_n / (p_null * self.majorant);
+            Some(ScatterRecord::Walk((
+                weight,
+                Ray::new_with_time(rec.p, *r_in.direction(), *r_in.time()),
+            )))
+        } else {
+            // 被吸收
+            None
+        }
+    }
+
+    fn is_medium(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_single_scattering_albedo_is_monotonic() {
+        let mut prev = RandomWalk::single_scattering_albedo(0.0);
+        assert!(prev.abs() < 1e-3);
+        for i in 1..=10 {
+            let cur = RandomWalk::single_scattering_albedo(i as f64 / 10.0);
+            assert!(cur >= prev);
+            assert!(cur <= 1.0);
+            prev = cur;
+        }
+    }
+
+    #[test]
+    fn test_majorant_is_max_extinction() {
+        let walk = RandomWalk::new(Color::WHITE, Color::new(1.0, 0.5, 0.25));
+        assert_eq!(walk.majorant(), 4.0);
+        assert_eq!(walk.sigma_n, Color::new(3.0, 2.0, 0.0));
+    }
+}
@@ -2,6 +2,7 @@ use std::f64::consts::PI;
 
 use crate::{
     hit::Hittable,
+    stats::{self, Counter},
     utils::{
         color::Color,
         onb::OrthonormalBasis,
@@ -36,6 +37,8 @@ impl PDF for SpherePDF {
 pub struct CosinePDF {
     attentuation: Color,
     uvw: OrthonormalBasis,
+    // 设置后拒绝几何表面下方的方向，避免插值的着色法线使光线穿入物体
+    geometric_normal: Option<UnitVec3>,
 }
 
 impl CosinePDF {
@@ -43,12 +46,26 @@ impl CosinePDF {
         CosinePDF {
             attentuation,
             uvw: OrthonormalBasis::new(w),
+            geometric_normal: None,
         }
     }
+
+    pub fn with_geometric_normal(mut self, normal: &UnitVec3) -> CosinePDF {
+        self.geometric_normal = Some(*normal);
+        self
+    }
+
+    fn below_surface(&self, direction: &Vec3) -> bool {
+        self.geometric_normal
+            .is_some_and(|n| direction.dot(n.as_inner()) <= 0.0)
+    }
 }
 
 impl PDF for CosinePDF {
     fn value(&self, direction: &Vec3) -> (Vec3, f64) {
+        if self.below_surface(direction) {
+            return (Color::BLACK, 0.0);
+        }
         let cosine_theta = Vec3::dot(&UnitVec3::from_vec3(*direction).unwrap(), self.uvw.v());
         let pdf_value = f64::max(0.0, cosine_theta / PI);
         // For Lambertian BRDF: f * cos(theta) = (albedo/PI) * cos(theta)
@@ -57,33 +74,45 @@ impl PDF for CosinePDF {
     }
 
     fn generate(&self) -> Option<UnitVec3> {
-        Some(UnitVec3::from_vec3_raw(self.uvw.onb_to_world(
-            UnitVec3::random_cosine_direction().into_inner(),
-        )))
+        let direction = self
+            .uvw
+            .onb_to_world(UnitVec3::random_cosine_direction().into_inner());
+        if self.below_surface(&direction) {
+            None
+        } else {
+            Some(UnitVec3::from_vec3_raw(direction))
+        }
     }
 }
 
+// time 为散射光线的时刻，运动的光源在该时刻采样
 pub struct HittablePDF<'a> {
     objects: &'a dyn Hittable,
     origin: Point3,
+    time: f64,
 }
 
 impl<'a> HittablePDF<'a> {
-    pub fn new(objects: &'a dyn Hittable, origin: Point3) -> HittablePDF<'a> {
-        HittablePDF { objects, origin }
+    pub fn new(objects: &'a dyn Hittable, origin: Point3, time: f64) -> HittablePDF<'a> {
+        HittablePDF {
+            objects,
+            origin,
+            time,
+        }
     }
 }
 
 impl<'a> PDF for HittablePDF<'a> {
     fn value(&self, direction: &Vec3) -> (Color, f64) {
+        stats::count(Counter::LightPdfEvaluations);
         (
             Color::BLACK,
-            self.objects.pdf_value(&self.origin, direction),
+            self.objects.pdf_value(&self.origin, direction, self.time),
         )
     }
 
     fn generate(&self) -> Option<UnitVec3> {
-        Some(self.objects.random(&self.origin))
+        Some(self.objects.random(&self.origin, self.time))
     }
 }
 
@@ -102,11 +131,6 @@ impl<'a> PDF for MixturePDF<'a> {
     fn value(&self, direction: &Vec3) -> (Color, f64) {
         let (attentuation, value0) = self.p[0].value(direction);
         let (_, value1) = self.p[1].value(direction);
-        if value0 == 0.0 && value1 == 0.0 {
-            self.p[0].value(direction);
-            self.p[1].value(direction);
-            panic!();
-        }
         (attentuation, value0 * 0.5 + value1 * 0.5)
     }
 
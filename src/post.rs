@@ -0,0 +1,393 @@
+use std::f64::consts::PI;
+
+use rayon::prelude::*;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::utils::{color::Color, random::Random, vec3::Vec3};
+
+pub mod outline;
+
+// 色调映射之前作用在线性浮点帧缓冲上的后期效果
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PostEffect {
+    Bloom(Bloom),
+    Glare(Glare),
+    Vignette(Vignette),
+    ChromaticAberration(ChromaticAberration),
+    FilmGrain(FilmGrain),
+    Sharpen(Sharpen),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Bloom {
+    pub threshold: f64,
+    pub intensity: f64,
+    // 以图像高度为单位的模糊半径
+    pub radius: f64,
+}
+
+impl Default for Bloom {
+    fn default() -> Self {
+        Self {
+            threshold: 1.0,
+            intensity: 0.1,
+            radius: 0.02,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Glare {
+    pub threshold: f64,
+    pub intensity: f64,
+    pub streaks: u32,
+    pub rotation_in_degrees: f64,
+    // 以像素为单位的光芒长度
+    pub length: u32,
+}
+
+impl Default for Glare {
+    fn default() -> Self {
+        Self {
+            threshold: 2.0,
+            intensity: 0.05,
+            streaks: 4,
+            rotation_in_degrees: 45.0,
+            length: 48,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Vignette {
+    // 画面角落处光线的倾斜程度，按 cos^4 规律衰减
+    pub strength: f64,
+}
+
+impl Default for Vignette {
+    fn default() -> Self {
+        Self { strength: 0.5 }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ChromaticAberration {
+    // 画面边缘处红蓝通道相对缩放的比例
+    pub strength: f64,
+}
+
+impl Default for ChromaticAberration {
+    fn default() -> Self {
+        Self { strength: 0.003 }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct FilmGrain {
+    pub intensity: f64,
+}
+
+impl Default for FilmGrain {
+    fn default() -> Self {
+        Self { intensity: 0.05 }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Sharpen {
+    pub amount: f64,
+    // 以像素为单位的高斯模糊标准差
+    pub radius: f64,
+}
+
+impl Default for Sharpen {
+    fn default() -> Self {
+        Self {
+            amount: 0.5,
+            radius: 1.0,
+        }
+    }
+}
+
+// 帧缓冲在完整画面中的位置，裁剪渲染时缓冲只覆盖画面的一部分
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Region {
+    pub x0: u32,
+    pub y0: u32,
+    pub width: u32,
+    pub height: u32,
+    pub frame_width: u32,
+    pub frame_height: u32,
+}
+
+impl Region {
+    pub fn full(width: u32, height: u32) -> Region {
+        Region {
+            x0: 0,
+            y0: 0,
+            width,
+            height,
+            frame_width: width,
+            frame_height: height,
+        }
+    }
+
+    // 缓冲中第 index 个像素相对画面中心的坐标
+    fn centered(&self, index: usize) -> (f64, f64) {
+        let w = self.width as usize;
+        let x = (self.x0 as usize + index % w) as f64 - (self.frame_width as f64 - 1.0) / 2.0;
+        let y = (self.y0 as usize + index / w) as f64 - (self.frame_height as f64 - 1.0) / 2.0;
+        (x, y)
+    }
+}
+
+impl PostEffect {
+    // 效果需要读取周围的像素，只渲染画面的一部分时会在边界处与完整渲染不一致
+    pub fn reads_neighbours(&self) -> bool {
+        match self {
+            PostEffect::Bloom(_)
+            | PostEffect::Glare(_)
+            | PostEffect::ChromaticAberration(_)
+            | PostEffect::Sharpen(_) => true,
+            PostEffect::Vignette(_) | PostEffect::FilmGrain(_) => false,
+        }
+    }
+
+    // 从命令行参数解析，格式为 "名称:参数=值:参数=值"，例如 "bloom:threshold=1.5:intensity=0.2"
+    pub fn parse(spec: &str) -> Result<PostEffect, Box<dyn std::error::Error>> {
+        let (name, params) = spec.split_once(':').unwrap_or((spec, ""));
+
+        let mut map = parse_params(params)?;
+        map.insert("type".to_owned(), Value::String(name.to_owned()));
+
+        Ok(serde_json::from_value(Value::Object(map))?)
+    }
+
+    // 位置相关的效果以完整画面的中心与尺寸计算
+    pub fn apply(&self, buffer: &mut [Color], region: Region) {
+        let (w, h) = (region.width as usize, region.height as usize);
+        let (fw, fh) = (region.frame_width as f64, region.frame_height as f64);
+        match self {
+            PostEffect::Bloom(bloom) => {
+                let bright = bright_pass(buffer, bloom.threshold);
+                let sigma = (bloom.radius * fh).max(0.5);
+                let blurred = gaussian_blur(&bright, w, h, sigma);
+                buffer
+                    .par_iter_mut()
+                    .zip(blurred.par_iter())
+                    .for_each(|(c, b)| *c += bloom.intensity * b);
+            }
+            PostEffect::Glare(glare) => {
+                let bright = bright_pass(buffer, glare.threshold);
+                let streaks = glare.streaks.max(1);
+                let directions: Vec<(f64, f64)> = (0..streaks)
+                    .map(|k| {
+                        let angle = glare.rotation_in_degrees.to_radians()
+                            + 2.0 * PI * k as f64 / streaks as f64;
+                        (angle.cos(), angle.sin())
+                    })
+                    .collect();
+                let decay = 0.01_f64.powf(1.0 / glare.length.max(1) as f64);
+
+                let streak: Vec<Color> = (0..w * h)
+                    .into_par_iter()
+                    .map(|index| {
+                        let (x, y) = ((index % w) as f64, (index / w) as f64);
+                        let mut sum = Color::BLACK;
+                        for (dx, dy) in &directions {
+                            let mut weight = 1.0;
+                            for k in 1..=glare.length {
+                                weight *= decay;
+                                let sx = x - dx * k as f64;
+                                let sy = y - dy * k as f64;
+                                sum += weight * sample_bilinear(&bright, w, h, sx, sy);
+                            }
+                        }
+                        sum / streaks as f64
+                    })
+                    .collect();
+                buffer
+                    .par_iter_mut()
+                    .zip(streak.par_iter())
+                    .for_each(|(c, s)| *c += glare.intensity * s);
+            }
+            PostEffect::Vignette(vignette) => {
+                let half_diagonal = (fw * fw + fh * fh).sqrt() / 2.0;
+                buffer.par_iter_mut().enumerate().for_each(|(index, c)| {
+                    let (dx, dy) = region.centered(index);
+                    let tan_theta = vignette.strength * (dx * dx + dy * dy).sqrt() / half_diagonal;
+                    let cos2 = 1.0 / (1.0 + tan_theta * tan_theta);
+                    *c *= cos2 * cos2;
+                });
+            }
+            PostEffect::ChromaticAberration(aberration) => {
+                let source = buffer.to_vec();
+                // 画面中心在缓冲中的坐标
+                let cx = (fw - 1.0) / 2.0 - region.x0 as f64;
+                let cy = (fh - 1.0) / 2.0 - region.y0 as f64;
+                buffer.par_iter_mut().enumerate().for_each(|(index, c)| {
+                    let (dx, dy) = region.centered(index);
+                    let r_scale = 1.0 + aberration.strength;
+                    let b_scale = 1.0 - aberration.strength;
+                    let red = sample_bilinear(&source, w, h, cx + dx * r_scale, cy + dy * r_scale);
+                    let blue = sample_bilinear(&source, w, h, cx + dx * b_scale, cy + dy * b_scale);
+                    *c = Color::new(red.x(), c.y(), blue.z());
+                });
+            }
+            PostEffect::FilmGrain(grain) => {
+                buffer.par_iter_mut().for_each(|c| {
+                    // 近似高斯噪声，亮部的颗粒更明显
+                    let noise = (0..4).map(|_| Random::f64()).sum::<f64>() / 2.0 - 1.0;
+                    let amount = 1.0 + grain.intensity * noise * 1.7320508;
+                    *c = (*c * amount).clamp(0.0, f64::INFINITY);
+                });
+            }
+            PostEffect::Sharpen(sharpen) => {
+                let blurred = gaussian_blur(buffer, w, h, sharpen.radius.max(0.1));
+                buffer
+                    .par_iter_mut()
+                    .zip(blurred.par_iter())
+                    .for_each(|(c, b)| {
+                        *c = (*c + sharpen.amount * (*c - b)).clamp(0.0, f64::INFINITY);
+                    });
+            }
+        }
+    }
+}
+
+// 解析 "参数=值:参数=值" 形式的参数列表，值按 JSON 解析
+pub fn parse_params(params: &str) -> Result<Map<String, Value>, Box<dyn std::error::Error>> {
+    let mut map = Map::new();
+    for part in params.split(':').filter(|part| !part.is_empty()) {
+        let (key, value) = part
+            .split_once('=')
+            .ok_or_else(|| format!("Invalid parameter: {part}"))?;
+        let value: Value = serde_json::from_str(value)?;
+        map.insert(key.to_owned(), value);
+    }
+
+    Ok(map)
+}
+
+fn bright_pass(buffer: &[Color], threshold: f64) -> Vec<Color> {
+    buffer
+        .par_iter()
+        .map(|c| {
+            let l = c.luminance();
+            if l > threshold {
+                c * ((l - threshold) / l)
+            } else {
+                Color::BLACK
+            }
+        })
+        .collect()
+}
+
+fn sample_bilinear(buffer: &[Color], w: usize, h: usize, x: f64, y: f64) -> Color {
+    let x = x.clamp(0.0, w as f64 - 1.0);
+    let y = y.clamp(0.0, h as f64 - 1.0);
+    let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+    let (x1, y1) = ((x0 + 1).min(w - 1), (y0 + 1).min(h - 1));
+    let (tx, ty) = (x - x0 as f64, y - y0 as f64);
+
+    let top = buffer[y0 * w + x0] * (1.0 - tx) + buffer[y0 * w + x1] * tx;
+    let bottom = buffer[y1 * w + x0] * (1.0 - tx) + buffer[y1 * w + x1] * tx;
+    top * (1.0 - ty) + bottom * ty
+}
+
+pub fn gaussian_blur(buffer: &[Color], w: usize, h: usize, sigma: f64) -> Vec<Color> {
+    let radius = (3.0 * sigma).ceil() as isize;
+    let kernel: Vec<f64> = (-radius..=radius)
+        .map(|i| (-((i * i) as f64) / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let norm: f64 = kernel.iter().sum();
+    let kernel: Vec<f64> = kernel.iter().map(|k| k / norm).collect();
+
+    let pass = |source: &[Color], horizontal: bool| -> Vec<Color> {
+        (0..w * h)
+            .into_par_iter()
+            .map(|index| {
+                let (x, y) = ((index % w) as isize, (index / w) as isize);
+                kernel
+                    .iter()
+                    .enumerate()
+                    .map(|(k, weight)| {
+                        let offset = k as isize - radius;
+                        let (sx, sy) = if horizontal {
+                            ((x + offset).clamp(0, w as isize - 1), y)
+                        } else {
+                            (x, (y + offset).clamp(0, h as isize - 1))
+                        };
+                        *weight * source[sy as usize * w + sx as usize]
+                    })
+                    .sum::<Vec3>()
+            })
+            .collect()
+    };
+
+    let horizontal = pass(buffer, true);
+    pass(&horizontal, false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_effect() {
+        let effect = PostEffect::parse("bloom:threshold=1.5:intensity=0.2").unwrap();
+        let PostEffect::Bloom(bloom) = effect else {
+            panic!("Expected bloom");
+        };
+        assert_eq!(bloom.threshold, 1.5);
+        assert_eq!(bloom.intensity, 0.2);
+        assert_eq!(bloom.radius, Bloom::default().radius);
+
+        assert!(PostEffect::parse("unknown").is_err());
+        assert!(PostEffect::parse("vignette:strength").is_err());
+    }
+
+    #[test]
+    fn test_gaussian_blur_preserves_energy() {
+        let (w, h) = (16, 16);
+        let mut buffer = vec![Color::BLACK; w * h];
+        buffer[8 * w + 8] = Color::WHITE;
+        let blurred = gaussian_blur(&buffer, w, h, 1.5);
+        let sum: Color = blurred.iter().copied().sum();
+        assert!((sum.x() - 1.0).abs() < 1e-9);
+        assert!(blurred[8 * w + 8].x() < 1.0);
+    }
+
+    #[test]
+    fn test_vignette_keeps_center() {
+        let (w, h) = (5, 5);
+        let mut buffer = vec![Color::WHITE; w * h];
+        let vignette = PostEffect::Vignette(Vignette { strength: 1.0 });
+        vignette.apply(&mut buffer, Region::full(w as u32, h as u32));
+        assert_eq!(buffer[2 * w + 2], Color::WHITE);
+        assert!(buffer[0].x() < 1.0);
+
+        // 裁剪区域与完整画面中对应的像素相同
+        let mut crop = vec![Color::WHITE; 2 * 3];
+        let region = Region {
+            x0: 3,
+            y0: 1,
+            width: 2,
+            height: 3,
+            frame_width: w as u32,
+            frame_height: h as u32,
+        };
+        vignette.apply(&mut crop, region);
+        for (index, c) in crop.iter().enumerate() {
+            assert_eq!(*c, buffer[(1 + index / 2) * w + 3 + index % 2]);
+        }
+    }
+}
@@ -0,0 +1,220 @@
+use image::RgbImage;
+use rayon::prelude::*;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{
+    post::parse_params,
+    utils::{
+        color::{Color, ToonMap},
+        vec3::{Point3, Vec3},
+    },
+};
+
+// 主光线击中的第一个表面
+#[derive(Debug, Clone, Copy)]
+pub struct GSample {
+    pub position: Point3,
+    pub normal: Vec3,
+    pub distance: f64,
+    pub object_id: u32,
+    pub material_id: usize,
+}
+
+// 超采样的几何缓冲，None 表示背景
+#[derive(Debug, Clone)]
+pub struct GBuffer {
+    pub width: usize,
+    pub height: usize,
+    pub samples: Vec<Option<GSample>>,
+}
+
+// 根据法线、深度与物体编号绘制卡通风格的描边
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Outline {
+    // 以像素为单位的线宽
+    pub width: f64,
+    pub color: Color,
+    pub opacity: f64,
+    // 每个像素每个方向上的几何缓冲采样数，用于抗锯齿
+    pub supersampling: u32,
+
+    // 轮廓：物体之间以及深度不连续处
+    pub silhouettes: bool,
+    // 折痕：法线夹角大于 crease_angle_in_degrees 处
+    pub creases: bool,
+    pub crease_angle_in_degrees: f64,
+    // 材质边界
+    pub material_boundaries: bool,
+    // 相邻采样到对方切平面的距离超过自身距离的这一比例时视为深度不连续
+    pub depth_threshold: f64,
+}
+
+impl Default for Outline {
+    fn default() -> Self {
+        Self {
+            width: 1.5,
+            color: Color::BLACK,
+            opacity: 1.0,
+            supersampling: 3,
+            silhouettes: true,
+            creases: true,
+            crease_angle_in_degrees: 60.0,
+            material_boundaries: true,
+            depth_threshold: 0.02,
+        }
+    }
+}
+
+impl Outline {
+    // 从命令行参数解析，格式为 "参数=值:参数=值"，例如 "width=2:color=[1,0,0]"
+    pub fn parse(spec: &str) -> Result<Outline, Box<dyn std::error::Error>> {
+        Ok(serde_json::from_value(Value::Object(parse_params(spec)?))?)
+    }
+
+    fn is_edge(&self, a: &Option<GSample>, b: &Option<GSample>, cos_crease: f64) -> bool {
+        let (a, b) = match (a, b) {
+            (None, None) => return false,
+            (Some(a), Some(b)) => (a, b),
+            _ => return self.silhouettes,
+        };
+
+        if self.silhouettes {
+            if a.object_id != b.object_id {
+                return true;
+            }
+            let offset = b.position - a.position;
+            let gap = a.normal.dot(&offset).abs().max(b.normal.dot(&offset).abs());
+            if gap > self.depth_threshold * a.distance.min(b.distance) {
+                return true;
+            }
+        }
+        if self.material_boundaries && a.material_id != b.material_id {
+            return true;
+        }
+        self.creases && a.normal.dot(&b.normal) < cos_crease
+    }
+
+    // 每个像素的描边覆盖率，取值 [0, 1]
+    pub fn coverage(&self, gbuffer: &GBuffer) -> Vec<f64> {
+        let s = self.supersampling.max(1) as usize;
+        let (w, h) = (gbuffer.width, gbuffer.height);
+        let cos_crease = self.crease_angle_in_degrees.to_radians().cos();
+
+        // 边界两侧的采样都会被标记，因此半径取线宽的一半
+        let radius = (self.width * s as f64 / 2.0).max(0.5);
+        let r = radius.ceil() as isize;
+        let offsets: Vec<(isize, isize)> = (-r..=r)
+            .flat_map(|dy| (-r..=r).map(move |dx| (dx, dy)))
+            .filter(|&(dx, dy)| {
+                (dx, dy) != (0, 0) && ((dx * dx + dy * dy) as f64) <= radius * radius
+            })
+            .collect();
+
+        let edges: Vec<bool> = (0..w * h)
+            .into_par_iter()
+            .map(|index| {
+                let (x, y) = ((index % w) as isize, (index / w) as isize);
+                let a = &gbuffer.samples[index];
+                offsets.iter().any(|&(dx, dy)| {
+                    let (nx, ny) = (x + dx, y + dy);
+                    if nx < 0 || ny < 0 || nx >= w as isize || ny >= h as isize {
+                        return false;
+                    }
+                    let b = &gbuffer.samples[ny as usize * w + nx as usize];
+                    self.is_edge(a, b, cos_crease)
+                })
+            })
+            .collect();
+
+        let (out_w, out_h) = (w / s, h / s);
+        (0..out_w * out_h)
+            .into_par_iter()
+            .map(|index| {
+                let (x, y) = (index % out_w, index / out_w);
+                let count = (0..s)
+                    .flat_map(|sy| (0..s).map(move |sx| (y * s + sy) * w + x * s + sx))
+                    .filter(|&i| edges[i])
+                    .count();
+                count as f64 / (s * s) as f64
+            })
+            .collect()
+    }
+
+    // 在色调映射后的图像上叠加描边
+    pub fn draw(&self, img: &mut RgbImage, gbuffer: &GBuffer) {
+        let coverage = self.coverage(gbuffer);
+        let color = self.color.to_rgb(&ToonMap::None).map(f64::from);
+
+        img.par_chunks_mut(3)
+            .zip(coverage.par_iter())
+            .for_each(|(pixel, alpha)| {
+                let alpha = (alpha * self.opacity).clamp(0.0, 1.0);
+                for (p, c) in pixel.iter_mut().zip(color) {
+                    *p = (*p as f64 * (1.0 - alpha) + c * alpha).round() as u8;
+                }
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plane_sample(object_id: u32, distance: f64) -> Option<GSample> {
+        Some(GSample {
+            position: Point3::new(0.0, 0.0, -distance),
+            normal: Vec3::new(0.0, 0.0, 1.0),
+            distance,
+            object_id,
+            material_id: 0,
+        })
+    }
+
+    #[test]
+    fn test_outline_between_objects() {
+        let outline = Outline {
+            width: 1.0,
+            supersampling: 2,
+            ..Default::default()
+        };
+        let (w, h) = (8, 4);
+        let samples = (0..w * h)
+            .map(|i| plane_sample(if i % w < w / 2 { 1 } else { 2 }, 1.0))
+            .collect();
+        let gbuffer = GBuffer {
+            width: w,
+            height: h,
+            samples,
+        };
+
+        let coverage = outline.coverage(&gbuffer);
+        assert_eq!(coverage.len(), 4 * 2);
+        assert_eq!(coverage[0], 0.0);
+        assert!(coverage[1] > 0.0);
+        assert!(coverage[2] > 0.0);
+        assert_eq!(coverage[3], 0.0);
+    }
+
+    #[test]
+    fn test_no_outline_on_flat_surface() {
+        let outline = Outline::default();
+        let samples = (0..36).map(|_| plane_sample(1, 5.0)).collect();
+        let gbuffer = GBuffer {
+            width: 6,
+            height: 6,
+            samples,
+        };
+        assert!(outline.coverage(&gbuffer).iter().all(|&c| c == 0.0));
+    }
+
+    #[test]
+    fn test_parse_outline() {
+        let outline = Outline::parse("width=2:color=[1,0,0]:creases=false").unwrap();
+        assert_eq!(outline.width, 2.0);
+        assert_eq!(outline.color, Color::RED);
+        assert!(!outline.creases);
+        assert!(outline.silhouettes);
+    }
+}
@@ -0,0 +1,627 @@
+use std::{
+    io::{BufRead, BufReader, Cursor, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    sync::Arc,
+    time::Instant,
+};
+
+use image::{ImageFormat, RgbImage, imageops};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+use crate::{
+    bvh::Accelerator,
+    camera::{Camera, crop::CropWindow},
+    hit::Hittable,
+    hits::Hittables,
+    material::{Dielectric, DiffuseLight, Lambertian, Material, Metal},
+    shapes::{
+        obj::Wavefont,
+        ply::Ply,
+        quad::{Quad, build_box},
+        sphere::Sphere,
+    },
+    texture::{ImageTexture, SolidColor, Texture, VertexColor},
+    utils::{color::Color, vec3::Point3},
+};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MaterialDescription {
+    Lambertian { albedo: Color },
+    Metal { albedo: Color, fuzz: f64 },
+    Dielectric { ior: f64 },
+    DiffuseLight { emit: Color },
+}
+
+impl MaterialDescription {
+    fn build(&self) -> Arc<dyn Material> {
+        match self {
+            MaterialDescription::Lambertian { albedo } => {
+                Arc::new(Lambertian::new(Arc::new(SolidColor::new(*albedo))))
+            }
+            MaterialDescription::Metal { albedo, fuzz } => Arc::new(Metal::new(*albedo, *fuzz)),
+            MaterialDescription::Dielectric { ior } => Arc::new(Dielectric::new(
+                Arc::new(SolidColor::new(Color::WHITE)),
+                *ior,
+            )),
+            MaterialDescription::DiffuseLight { emit } => {
+                Arc::new(DiffuseLight::new(Arc::new(SolidColor::new(*emit))))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ShapeDescription {
+    Obj {
+        file: String,
+        prefix: String,
+        #[serde(default)]
+        vanilla_material: bool,
+    },
+    // 未指定材质时使用顶点颜色的 Lambertian
+    Ply {
+        file: String,
+        prefix: String,
+        #[serde(default)]
+        material: Option<MaterialDescription>,
+    },
+    Sphere {
+        center: Point3,
+        radius: f64,
+        material: MaterialDescription,
+    },
+    Quad {
+        anchor: Point3,
+        u: Point3,
+        v: Point3,
+        material: MaterialDescription,
+    },
+    Box {
+        a: Point3,
+        b: Point3,
+        material: MaterialDescription,
+    },
+}
+
+impl ShapeDescription {
+    fn build(&self) -> Result<Box<dyn Hittable>, Box<dyn std::error::Error>> {
+        Ok(match self {
+            ShapeDescription::Obj {
+                file,
+                prefix,
+                vanilla_material,
+            } => Box::new(
+                Wavefont::new(file, prefix, *vanilla_material)
+                    .ok_or_else(|| format!("Cannot load {prefix}/{file}"))?,
+            ),
+            ShapeDescription::Ply {
+                file,
+                prefix,
+                material,
+            } => {
+                let material = match material {
+                    Some(material) => material.build(),
+                    None => Arc::new(Lambertian::new(Arc::new(VertexColor::default()))),
+                };
+                Box::new(Ply::new(file, prefix, material)?)
+            }
+            ShapeDescription::Sphere {
+                center,
+                radius,
+                material,
+            } => Box::new(Sphere::new(*center, *radius, material.build())),
+            ShapeDescription::Quad {
+                anchor,
+                u,
+                v,
+                material,
+            } => Box::new(Quad::new(*anchor, *u, *v, material.build())),
+            ShapeDescription::Box { a, b, material } => {
+                Box::new(build_box(*a, *b, material.build()))
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ObjectDescription {
+    #[serde(flatten)]
+    pub shape: ShapeDescription,
+    // 同时作为重要性采样的光源
+    #[serde(default)]
+    pub light: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackgroundDescription {
+    Color(Color),
+    Image(String),
+}
+
+// 通过套接字发送的场景描述，camera 的格式与相机 JSON 文件相同
+#[derive(Debug, Clone, Deserialize)]
+pub struct SceneDescription {
+    pub camera: Value,
+    #[serde(default)]
+    pub background: Option<BackgroundDescription>,
+    pub objects: Vec<ObjectDescription>,
+    // 顶层加速结构，默认为 BVH
+    #[serde(default)]
+    pub accelerator: Accelerator,
+}
+
+// 常驻内存的渲染服务，场景只在收到新的场景描述时重建
+// 协议为每行一个 JSON 命令：
+//   {"command": "load_scene", "scene": SceneDescription}
+//   {"command": "set_camera", "camera": 相机 JSON}
+//   {"command": "render", "tile_size": 64, "format": "png" | "float"}
+//   {"command": "shutdown"}
+// 每条回复为一行 JSON，其中 {"type": "tile", ..., "length": N} 之后紧跟 N 字节的图块数据，
+// png 为编码后的图块，float 为按行优先排列的小端 f32 RGB
+// 图块渲染完成即发送；png 使用自动曝光、描边或读取周围像素的后期效果时需要对整幅图像显影，
+// 此时全部渲染完成后再依次发送
+pub struct RenderServer {
+    camera: Option<Camera>,
+    background: Option<Arc<dyn Texture>>,
+    world: Option<(Box<dyn Hittable>, Option<Hittables>)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TileFormat {
+    Png,
+    Float,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+enum Command {
+    LoadScene {
+        scene: SceneDescription,
+    },
+    SetCamera {
+        camera: Value,
+    },
+    Render {
+        #[serde(default = "Command::default_tile_size")]
+        tile_size: u32,
+        #[serde(default = "Command::default_format")]
+        format: TileFormat,
+    },
+    Shutdown,
+}
+
+impl Command {
+    fn default_tile_size() -> u32 {
+        64
+    }
+
+    fn default_format() -> TileFormat {
+        TileFormat::Png
+    }
+}
+
+impl Default for RenderServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RenderServer {
+    pub fn new() -> RenderServer {
+        RenderServer {
+            camera: None,
+            background: None,
+            world: None,
+        }
+    }
+
+    // 只接受本机连接，依次处理每个客户端，直到收到 shutdown
+    pub fn serve(&mut self, addr: impl ToSocketAddrs) -> Result<(), Box<dyn std::error::Error>> {
+        let listener = TcpListener::bind(addr)?;
+        println!("Render server listening on {}", listener.local_addr()?);
+
+        for stream in listener.incoming() {
+            // 单个客户端的错误（例如渲染中途断开）只结束该连接，不影响服务本身
+            let result = stream
+                .map_err(|e| e.into())
+                .and_then(|stream| self.handle_connection(stream));
+            match result {
+                Ok(true) => {}
+                Ok(false) => return Ok(()),
+                Err(e) => eprintln!("Connection failed: {e}"),
+            }
+        }
+
+        Ok(())
+    }
+
+    // 处理一个客户端的所有命令，返回 false 表示收到了 shutdown
+    fn handle_connection(&mut self, stream: TcpStream) -> Result<bool, Box<dyn std::error::Error>> {
+        if !stream.peer_addr()?.ip().is_loopback() {
+            return Ok(true);
+        }
+
+        let mut writer = stream.try_clone()?;
+        let reader = BufReader::new(stream);
+        for line in reader.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            if !self.handle_line(&line, &mut writer)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    // 处理一条命令，返回 false 表示服务应当退出，命令本身的错误作为消息回复给客户端
+    pub fn handle_line(
+        &mut self,
+        line: &str,
+        writer: &mut impl Write,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let result = serde_json::from_str::<Command>(line)
+            .map_err(|e| e.into())
+            .and_then(|command| self.execute(command, writer));
+
+        match result {
+            Ok(running) => Ok(running),
+            Err(e) => {
+                send_message(writer, &json!({"type": "error", "message": e.to_string()}))?;
+                Ok(true)
+            }
+        }
+    }
+
+    fn execute(
+        &mut self,
+        command: Command,
+        writer: &mut impl Write,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        match command {
+            Command::LoadScene { scene } => {
+                let start = Instant::now();
+                self.load_scene(scene)?;
+                send_message(
+                    writer,
+                    &json!({"type": "scene_loaded", "seconds": start.elapsed().as_secs_f64()}),
+                )?;
+            }
+            Command::SetCamera { camera } => {
+                self.set_camera(camera)?;
+                send_message(writer, &json!({"type": "camera_set"}))?;
+            }
+            Command::Render { tile_size, format } => {
+                self.render(tile_size, format, writer)?;
+            }
+            Command::Shutdown => {
+                send_message(writer, &json!({"type": "bye"}))?;
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    pub fn load_scene(
+        &mut self,
+        scene: SceneDescription,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut objects = Hittables::default();
+        let mut lights = Hittables::default();
+        for object in &scene.objects {
+            // 光源与场景共享同一份几何
+            let shape: Arc<dyn Hittable> = Arc::from(object.shape.build()?);
+            if object.light {
+                lights.add(Box::new(shape.clone()));
+            }
+            objects.add(Box::new(shape));
+        }
+
+        let lights = if lights.objects.is_empty() {
+            None
+        } else {
+            Some(lights)
+        };
+        self.world = Some((scene.accelerator.build(objects.objects), lights));
+        self.background = scene.background.map(|background| -> Arc<dyn Texture> {
+            match background {
+                BackgroundDescription::Color(color) => Arc::new(SolidColor::new(color)),
+                BackgroundDescription::Image(file) => Arc::new(ImageTexture::new(&file)),
+            }
+        });
+        self.set_camera(scene.camera)
+    }
+
+    // 只替换相机，保留已经构建的场景与背景纹理
+    pub fn set_camera(&mut self, camera: Value) -> Result<(), Box<dyn std::error::Error>> {
+        let mut camera = Camera::from_json_value(camera)?;
+        if let Some(background) = &self.background {
+            camera.background.texture = background.clone();
+        }
+        self.camera = Some(camera);
+        Ok(())
+    }
+
+    // 逐块渲染浮点帧缓冲，float 格式每完成一块立即发送
+    // png 格式在显影只取决于单个像素时也逐块显影并立即发送；使用自动曝光、描边或
+    // 读取周围像素的后期效果时，在拼合后对整幅图像显影一次再发送，使结果与完整渲染一致
+    fn render(
+        &mut self,
+        tile_size: u32,
+        format: TileFormat,
+        writer: &mut impl Write,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let (Some(camera), Some((world, lights))) = (&mut self.camera, &self.world) else {
+            return Err("No scene loaded".into());
+        };
+        let lights = lights.as_ref().map(|l| l as &dyn Hittable);
+
+        let (width, height) = camera.image_size();
+        let tile_size = tile_size.max(1);
+        let start = Instant::now();
+        let progressive = camera.check_local_develop().is_ok();
+        let mut tiles = Vec::new();
+        let mut frame = vec![Color::BLACK; (width * height) as usize];
+        for y in (0..height).step_by(tile_size as usize) {
+            for x in (0..width).step_by(tile_size as usize) {
+                let tile = CropWindow::Pixels {
+                    x,
+                    y,
+                    width: tile_size.min(width - x),
+                    height: tile_size.min(height - y),
+                };
+                camera.crop = Some(tile);
+                let bounds = tile.pixel_bounds(width, height);
+                let pixels = camera.render_region(world.as_ref(), lights);
+
+                match format {
+                    TileFormat::Float => {
+                        let data: Vec<u8> = pixels
+                            .iter()
+                            .flat_map(|c| c.e().map(|v| v as f32))
+                            .flat_map(f32::to_le_bytes)
+                            .collect();
+                        send_tile(writer, bounds, format, &data)?;
+                    }
+                    TileFormat::Png if progressive => {
+                        send_png(writer, bounds, &camera.develop(&pixels))?;
+                    }
+                    TileFormat::Png => {
+                        let (x0, y0, x1, _) = bounds;
+                        for (row, line) in pixels.chunks((x1 - x0) as usize).enumerate() {
+                            let offset = ((y0 + row as u32) * width + x0) as usize;
+                            frame[offset..offset + line.len()].copy_from_slice(line);
+                        }
+                        tiles.push(bounds);
+                    }
+                }
+            }
+        }
+        camera.crop = None;
+
+        if !tiles.is_empty() {
+            let img = camera.finish(&frame, world.as_ref());
+            for (x0, y0, x1, y1) in tiles {
+                let tile = imageops::crop_imm(&img, x0, y0, x1 - x0, y1 - y0).to_image();
+                send_png(writer, (x0, y0, x1, y1), &tile)?;
+            }
+        }
+
+        send_message(
+            writer,
+            &json!({
+                "type": "done",
+                "width": width,
+                "height": height,
+                "seconds": start.elapsed().as_secs_f64(),
+            }),
+        )
+    }
+}
+
+fn send_png(
+    writer: &mut impl Write,
+    bounds: (u32, u32, u32, u32),
+    tile: &RgbImage,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut bytes = Cursor::new(Vec::new());
+    tile.write_to(&mut bytes, ImageFormat::Png)?;
+    send_tile(writer, bounds, TileFormat::Png, &bytes.into_inner())
+}
+
+fn send_tile(
+    writer: &mut impl Write,
+    (x0, y0, x1, y1): (u32, u32, u32, u32),
+    format: TileFormat,
+    data: &[u8],
+) -> Result<(), Box<dyn std::error::Error>> {
+    send_message(
+        writer,
+        &json!({
+            "type": "tile",
+            "x": x0,
+            "y": y0,
+            "width": x1 - x0,
+            "height": y1 - y0,
+            "format": format,
+            "length": data.len(),
+        }),
+    )?;
+    writer.write_all(data)?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn send_message(
+    writer: &mut impl Write,
+    message: &Value,
+) -> Result<(), Box<dyn std::error::Error>> {
+    writeln!(writer, "{message}")?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(output: &[u8]) -> Vec<Value> {
+        // 跳过图块数据，只解析 JSON 行
+        let mut result = Vec::new();
+        let mut rest = output;
+        while let Some(end) = rest.iter().position(|&b| b == b'\n') {
+            let message: Value = serde_json::from_slice(&rest[..end]).unwrap();
+            rest = &rest[end + 1..];
+            if let Some(length) = message.get("length").and_then(Value::as_u64) {
+                rest = &rest[length as usize..];
+            }
+            result.push(message);
+        }
+        result
+    }
+
+    #[test]
+    fn test_render_tiles() {
+        let scene = json!({
+            "command": "load_scene",
+            "scene": {
+                "camera": {
+                    "aspect_ratio": 2.0,
+                    "image_width": 12,
+                    "vertical_fov_in_degrees": 40.0,
+                    "look_from": [0.0, 0.0, 5.0],
+                    "look_at": [0.0, 0.0, 0.0],
+                    "vec_up": [0.0, 1.0, 0.0],
+                    "defocus_angle_in_degrees": 0.0,
+                    "focus_distance": 5.0,
+                    "samples_per_pixel": 1,
+                    "max_depth": 2
+                },
+                "background": {"color": [0.5, 0.7, 1.0]},
+                "objects": [
+                    {"type": "sphere", "center": [0.0, 0.0, 0.0], "radius": 1.0,
+                     "material": {"type": "lambertian", "albedo": [0.8, 0.2, 0.2]}},
+                    {"type": "quad", "anchor": [-1.0, 3.0, -1.0], "u": [2.0, 0.0, 0.0],
+                     "v": [0.0, 0.0, 2.0], "light": true,
+                     "material": {"type": "diffuse_light", "emit": [4.0, 4.0, 4.0]}}
+                ]
+            }
+        });
+
+        let mut server = RenderServer::new();
+        let mut output = Vec::new();
+        assert!(server.handle_line(&scene.to_string(), &mut output).unwrap());
+        let commands = [
+            r#"{"command": "render", "tile_size": 8, "format": "float"}"#,
+            r#"{"command": "set_camera", "camera": {"aspect_ratio": 1.0, "image_width": 4, "vertical_fov_in_degrees": 40.0, "look_from": [0.0, 0.0, 5.0], "look_at": [0.0, 0.0, 0.0], "vec_up": [0.0, 1.0, 0.0], "defocus_angle_in_degrees": 0.0, "focus_distance": 5.0, "samples_per_pixel": 1}}"#,
+            r#"{"command": "render"}"#,
+            r#"{"command": "unknown"}"#,
+        ];
+        for command in commands {
+            assert!(server.handle_line(command, &mut output).unwrap());
+        }
+        assert!(
+            !server
+                .handle_line(r#"{"command": "shutdown"}"#, &mut output)
+                .unwrap()
+        );
+
+        let messages = messages(&output);
+        let types: Vec<&str> = messages
+            .iter()
+            .map(|m| m["type"].as_str().unwrap())
+            .collect();
+        // 12 x 6 的图像分为 2 块，修改相机后 4 x 4 的图像为 1 块
+        assert_eq!(
+            types,
+            [
+                "scene_loaded",
+                "tile",
+                "tile",
+                "done",
+                "camera_set",
+                "tile",
+                "done",
+                "error",
+                "bye"
+            ]
+        );
+        assert_eq!(messages[1]["length"], 8 * 6 * 3 * 4);
+        assert_eq!(messages[2]["x"], 8);
+        assert_eq!(messages[5]["format"], "png");
+    }
+
+    #[test]
+    fn test_png_tiles_match_full_render() {
+        // 只有暗角时逐块显影，加入泛光后在拼合的帧上显影，两种方式都应与整幅渲染相同
+        let vignette = json!({"type": "vignette", "strength": 1.0});
+        let bloom = json!({"type": "bloom", "threshold": 0.5, "intensity": 1.0, "radius": 0.2});
+        for effects in [json!([vignette]), json!([vignette, bloom])] {
+            check_png_tiles(effects);
+        }
+    }
+
+    fn check_png_tiles(post_effects: Value) {
+        let camera = json!({
+            "aspect_ratio": 2.0,
+            "image_width": 12,
+            "vertical_fov_in_degrees": 40.0,
+            "look_from": [0.0, 0.0, 5.0],
+            "look_at": [0.0, 0.0, 0.0],
+            "vec_up": [0.0, 1.0, 0.0],
+            "defocus_angle_in_degrees": 0.0,
+            "focus_distance": 5.0,
+            "samples_per_pixel": 1,
+            "post_effects": post_effects
+        });
+        let scene = json!({
+            "command": "load_scene",
+            "scene": {
+                "camera": camera,
+                "background": {"color": [0.5, 0.7, 1.0]},
+                // 位于相机背后，不会被击中
+                "objects": [{"type": "sphere", "center": [0.0, 0.0, 20.0], "radius": 1.0,
+                             "material": {"type": "lambertian", "albedo": [0.5, 0.5, 0.5]}}]
+            }
+        });
+
+        let mut server = RenderServer::new();
+        let mut output = Vec::new();
+        server.handle_line(&scene.to_string(), &mut output).unwrap();
+        output.clear();
+        server
+            .handle_line(r#"{"command": "render", "tile_size": 4}"#, &mut output)
+            .unwrap();
+
+        // 只有背景，结果是确定的，拼合的图块应与整幅渲染相同
+        let mut expected = Camera::from_json_value(camera).unwrap();
+        expected.background.texture = Arc::new(SolidColor::new(Color::new(0.5, 0.7, 1.0)));
+        let expected = expected.render(&Hittables::default(), None);
+
+        let mut assembled = image::RgbImage::new(12, 6);
+        let mut rest = &output[..];
+        while let Some(end) = rest.iter().position(|&b| b == b'\n') {
+            let message: Value = serde_json::from_slice(&rest[..end]).unwrap();
+            rest = &rest[end + 1..];
+            let Some(length) = message.get("length").and_then(Value::as_u64) else {
+                continue;
+            };
+            let tile = image::load_from_memory(&rest[..length as usize])
+                .unwrap()
+                .into_rgb8();
+            rest = &rest[length as usize..];
+            let (x, y) = (
+                message["x"].as_u64().unwrap(),
+                message["y"].as_u64().unwrap(),
+            );
+            imageops::replace(&mut assembled, &tile, x as i64, y as i64);
+        }
+        assert_eq!(assembled, expected);
+    }
+}
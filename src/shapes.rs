@@ -1,7 +1,11 @@
+use std::sync::Arc;
+
 use crate::{
     aabb::AABB,
-    hit::Hittable,
+    hit::{Hittable, next_object_id},
+    material::Material,
     utils::{
+        lerp,
         quaternion::Quaternion,
         ray::Ray,
         vec3::{Point3, UnitVec3, Vec3},
@@ -9,7 +13,9 @@ use crate::{
 };
 
 pub mod environment;
+pub mod mesh;
 pub mod obj;
+pub mod ply;
 pub mod quad;
 pub mod sphere;
 pub mod triangle;
@@ -20,67 +26,227 @@ pub trait Planar {
     fn is_interior(a: f64, b: f64) -> Option<(f64, f64)>;
 }
 
+// 变换的一个关键帧，time 为快门区间 [0, 1] 内的时刻
+#[derive(Debug, Clone, Copy)]
+pub struct Keyframe {
+    pub time: f64,
+    pub offset: Vec3,
+    pub quaternion: Quaternion,
+    pub scale: Vec3,
+}
+
+impl Keyframe {
+    pub fn new(
+        time: f64,
+        offset: Option<Vec3>,
+        quaternion: Option<Quaternion>,
+        scale: Option<Vec3>,
+    ) -> Keyframe {
+        Keyframe {
+            time,
+            offset: offset.unwrap_or(Vec3::ZERO),
+            quaternion: quaternion.unwrap_or(Quaternion::identity()),
+            scale: scale.unwrap_or(Vec3::new(1.0, 1.0, 1.0)),
+        }
+    }
+
+    pub fn interpolate(&self, rhs: &Keyframe, time: f64) -> Keyframe {
+        let span = rhs.time - self.time;
+        let t = if span > 0.0 {
+            ((time - self.time) / span).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+
+        Keyframe {
+            time,
+            offset: lerp(self.offset, rhs.offset, t),
+            quaternion: self.quaternion.slerp(rhs.quaternion, t),
+            scale: lerp(self.scale, rhs.scale, t),
+        }
+    }
+
+    pub fn transform(&self, v: Vec3) -> Vec3 {
+        let scaled = v * self.scale;
+        let rotated = self.quaternion.rotate_vector(scaled);
+        rotated + self.offset
+    }
+
+    pub fn detransform(&self, v: Vec3) -> Vec3 {
+        let offseted = v - self.offset;
+        let rotated = self.quaternion.conjugate().rotate_vector(offseted);
+        rotated / self.scale
+    }
+
+    // 变换方向向量，不含平移
+    pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+        self.quaternion.rotate_vector(v * self.scale)
+    }
+
+    pub fn transform_normal(&self, normal: &UnitVec3) -> UnitVec3 {
+        UnitVec3::from_vec3(
+            self.quaternion
+                .rotate_vector(normal.as_inner() / self.scale),
+        )
+        .expect("The transformed normal can't be normalized!")
+    }
+}
+
+// 对物体施加平移、旋转与缩放
+// 物体以 Arc 共享时作为实例使用，多个实例引用同一个底层 BVH，各自拥有变换与可选的材质
+#[derive(Clone)]
 pub struct Transform {
-    object: Box<dyn Hittable>,
-    offset: Vec3,
-    quaternion: Quaternion,
-    scale: Vec3,
+    object: Arc<dyn Hittable>,
+    keyframes: Vec<Keyframe>,
     bbox: AABB,
+    // 设置后代替物体自身的材质
+    material: Option<Arc<dyn Material>>,
+    // 实例的物体编号，使同一物体的不同实例在描边时可以区分
+    object_id: Option<u32>,
 }
 
 impl Transform {
+    // 计算运动包围盒时每两个关键帧之间的采样数
+    const BBOX_STEPS: usize = 16;
+
     pub fn new(
         object: Box<dyn Hittable>,
         offset: Option<Vec3>,
         quaternion: Option<Quaternion>,
         scale: Option<Vec3>,
+    ) -> Transform {
+        Transform::new_with_keyframes(object, vec![Keyframe::new(0.0, offset, quaternion, scale)])
+    }
+
+    // 关键帧在快门时间内插值，位置与缩放线性插值，旋转使用球面线性插值
+    pub fn new_with_keyframes(object: Box<dyn Hittable>, keyframes: Vec<Keyframe>) -> Transform {
+        Transform::build(Arc::from(object), keyframes, None)
+    }
+
+    // 共享物体的一个实例，物体只存储一份，每个实例只占用变换所需的内存
+    pub fn instance(
+        object: Arc<dyn Hittable>,
+        offset: Option<Vec3>,
+        quaternion: Option<Quaternion>,
+        scale: Option<Vec3>,
+    ) -> Transform {
+        Transform::instance_with_keyframes(
+            object,
+            vec![Keyframe::new(0.0, offset, quaternion, scale)],
+        )
+    }
+
+    pub fn instance_with_keyframes(
+        object: Arc<dyn Hittable>,
+        keyframes: Vec<Keyframe>,
+    ) -> Transform {
+        Transform::build(object, keyframes, Some(next_object_id()))
+    }
+
+    // 就地替换关键帧并重新计算包围盒，实例的物体编号保持不变
+    pub fn set_keyframes(&mut self, mut keyframes: Vec<Keyframe>) {
+        assert!(
+            !keyframes.is_empty(),
+            "Transform must contain at least one keyframe"
+        );
+        keyframes.sort_by(|a, b| f64::total_cmp(&a.time, &b.time));
+        self.keyframes = keyframes;
+        self.calculate_bbox();
+    }
+
+    // 用 material 代替物体自身的材质
+    pub fn with_material(mut self, material: Arc<dyn Material>) -> Transform {
+        self.material = Some(material);
+        self
+    }
+
+    fn build(
+        object: Arc<dyn Hittable>,
+        keyframes: Vec<Keyframe>,
+        object_id: Option<u32>,
     ) -> Transform {
         let mut t = Transform {
             bbox: AABB::EMPTY,
             object,
-            offset: offset.unwrap_or(Vec3::ZERO),
-            quaternion: quaternion.unwrap_or(Quaternion::identity()),
-            scale: scale.unwrap_or(Vec3::new(1.0, 1.0, 1.0)),
+            keyframes: Vec::new(),
+            material: None,
+            object_id,
         };
-        t.calculate_bbox();
+        t.set_keyframes(keyframes);
         t
     }
 
     fn calculate_bbox(&mut self) {
         let points = self.object.bounding_box().all_points();
 
-        let (min, max) = points.iter().map(|p| self.transform(*p)).fold(
-            (
-                Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
-                Vec3::new(-f64::INFINITY, -f64::INFINITY, -f64::INFINITY),
-            ),
-            |(min, max), p| {
-                let min_x = min.x().min(p.x());
-                let min_y = min.y().min(p.y());
-                let min_z = min.z().min(p.z());
-                let max_x = max.x().max(p.x());
-                let max_y = max.y().max(p.y());
-                let max_z = max.z().max(p.z());
+        let frames: Vec<Keyframe> = if self.keyframes.len() == 1 {
+            self.keyframes.clone()
+        } else {
+            self.keyframes
+                .windows(2)
+                .flat_map(|w| {
+                    (0..=Self::BBOX_STEPS).map(move |i| {
+                        let time = lerp(w[0].time, w[1].time, i as f64 / Self::BBOX_STEPS as f64);
+                        w[0].interpolate(&w[1], time)
+                    })
+                })
+                .collect()
+        };
+
+        let (min, max) = frames
+            .iter()
+            .flat_map(|frame| points.iter().map(|p| frame.transform(*p)))
+            .fold(
                 (
-                    Vec3::new(min_x, min_y, min_z),
-                    Vec3::new(max_x, max_y, max_z),
-                )
-            },
-        );
+                    Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+                    Vec3::new(-f64::INFINITY, -f64::INFINITY, -f64::INFINITY),
+                ),
+                |(min, max), p| {
+                    let min_x = min.x().min(p.x());
+                    let min_y = min.y().min(p.y());
+                    let min_z = min.z().min(p.z());
+                    let max_x = max.x().max(p.x());
+                    let max_y = max.y().max(p.y());
+                    let max_z = max.z().max(p.z());
+                    (
+                        Vec3::new(min_x, min_y, min_z),
+                        Vec3::new(max_x, max_y, max_z),
+                    )
+                },
+            );
 
-        self.bbox = AABB::from_points(min, max);
-    }
+        // 采样之间物体仍在运动，按相邻采样间的最长轨迹扩展，使旋转的圆弧不会超出包围盒
+        let pad = frames
+            .windows(2)
+            .map(|w| {
+                let angle = 2.0 * w[0].quaternion.dot(w[1].quaternion).abs().min(1.0).acos();
+                points
+                    .iter()
+                    .map(|&p| {
+                        let radius = (p * w[0].scale).length().max((p * w[1].scale).length());
+                        radius * angle + (p * (w[1].scale - w[0].scale)).length()
+                    })
+                    .fold(0.0, f64::max)
+            })
+            .fold(0.0, f64::max);
+        let pad = Vec3::new(pad, pad, pad);
 
-    fn transform(&self, v: Vec3) -> Vec3 {
-        let scaled = v * self.scale;
-        let rotated = self.quaternion.rotate_vector(scaled);
-        rotated + self.offset
+        self.bbox = AABB::from_points(min - pad, max + pad);
     }
 
-    fn detransform(&self, v: Vec3) -> Vec3 {
-        let offseted = v - self.offset;
-        let rotated = self.quaternion.conjugate().rotate_vector(offseted);
-        rotated / self.scale
+    pub fn keyframe_at(&self, time: f64) -> Keyframe {
+        let first = &self.keyframes[0];
+        let last = &self.keyframes[self.keyframes.len() - 1];
+
+        if time <= first.time {
+            return *first;
+        }
+        if time >= last.time {
+            return *last;
+        }
+
+        let next = self.keyframes.partition_point(|k| k.time <= time);
+        self.keyframes[next - 1].interpolate(&self.keyframes[next], time)
     }
 }
 
@@ -90,22 +256,29 @@ impl Hittable for Transform {
         r: &crate::utils::ray::Ray,
         interval: &crate::utils::interval::Interval,
     ) -> Option<crate::hit::HitRecord> {
+        let frame = self.keyframe_at(*r.time());
+
         let origin = r.origin();
         let to = r.at(1.0);
 
-        let local_origin = self.detransform(*origin);
-        let local_to = self.detransform(to);
+        let local_origin = frame.detransform(*origin);
+        let local_to = frame.detransform(to);
 
         let local_ray = Ray::new_with_time(local_origin, local_to - local_origin, *r.time());
 
         let mut rec = self.object.hit(&local_ray, interval)?;
 
-        rec.p = self.transform(rec.p);
-        rec.normal = UnitVec3::from_vec3(
-            self.quaternion
-                .rotate_vector(rec.normal.into_inner() / self.scale),
-        )
-        .expect("The transformed normal can't be normalized!");
+        rec.p = frame.transform(rec.p);
+        rec.normal = frame.transform_normal(&rec.normal);
+        rec.geometric_normal = frame.transform_normal(&rec.geometric_normal);
+        rec.dpdu = frame.transform_vector(rec.dpdu);
+        rec.dpdv = frame.transform_vector(rec.dpdv);
+        if let Some(material) = &self.material {
+            rec.mat = material.as_ref();
+        }
+        if let Some(object_id) = self.object_id {
+            rec.object_id = object_id;
+        }
 
         Some(rec)
     }
@@ -114,20 +287,178 @@ impl Hittable for Transform {
         &self.bbox
     }
 
-    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
-        let local_origin = self.detransform(*origin);
-        let local_to = self.detransform(origin + direction);
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3, time: f64) -> f64 {
+        let frame = self.keyframe_at(time);
+        let local_origin = frame.detransform(*origin);
+        let local_to = frame.detransform(origin + direction);
         let local_direction = local_to - local_origin;
 
-        self.object.pdf_value(&local_origin, &local_direction)
+        self.object.pdf_value(&local_origin, &local_direction, time)
     }
 
-    fn random(&self, origin: &Point3) -> UnitVec3 {
-        let local_origin = self.detransform(*origin);
-        let local_dir = self.object.random(&local_origin);
+    fn random(&self, origin: &Point3, time: f64) -> UnitVec3 {
+        let frame = self.keyframe_at(time);
+        let local_origin = frame.detransform(*origin);
+        let local_dir = self.object.random(&local_origin, time);
         let local_to = local_origin + local_dir.as_inner();
-        let world_to = self.transform(local_to);
+        let world_to = frame.transform(local_to);
         let world_dir = world_to - origin;
         UnitVec3::from_vec3(world_dir).expect("Random direction can't be normalized!")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{material::EmptyMaterial, shapes::sphere::Sphere, utils::interval::Interval};
+
+    use super::*;
+
+    #[test]
+    fn test_keyframe_interpolation() {
+        let sphere = Sphere::new(Point3::ZERO, 1.0, Arc::new(EmptyMaterial));
+        let t = Transform::new_with_keyframes(
+            Box::new(sphere),
+            vec![
+                Keyframe::new(0.0, None, None, None),
+                Keyframe::new(1.0, Some(Vec3::new(10.0, 0.0, 0.0)), None, None),
+            ],
+        );
+
+        let frame = t.keyframe_at(0.25);
+        assert!((frame.offset.x() - 2.5).abs() < 1e-10);
+
+        let bbox = t.bounding_box();
+        assert!(bbox.x().contains(-1.0));
+        assert!(bbox.x().contains(11.0));
+    }
+
+    #[test]
+    fn test_motion_blurred_hit() {
+        let sphere = Sphere::new(Point3::ZERO, 1.0, Arc::new(EmptyMaterial));
+        let t = Transform::new_with_keyframes(
+            Box::new(sphere),
+            vec![
+                Keyframe::new(0.0, None, None, None),
+                Keyframe::new(1.0, Some(Vec3::new(10.0, 0.0, 0.0)), None, None),
+            ],
+        );
+
+        let interval = Interval::new(1e-8, f64::INFINITY);
+        let r0 = Ray::new_with_time(Point3::new(10.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0), 0.0);
+        let r1 = Ray::new_with_time(Point3::new(10.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0), 1.0);
+        assert!(t.hit(&r0, &interval).is_none());
+        assert!(t.hit(&r1, &interval).is_some());
+
+        // 作为光源采样时使用散射光线的时刻
+        let origin = r0.origin();
+        let direction = r0.direction();
+        assert_eq!(t.pdf_value(origin, direction, 0.0), 0.0);
+        assert!(t.pdf_value(origin, direction, 1.0) > 0.0);
+        let sampled = t.random(origin, 1.0);
+        assert!(t.pdf_value(origin, sampled.as_inner(), 1.0) > 0.0);
+    }
+
+    #[test]
+    fn test_shared_instances() {
+        use crate::{bvh::BVH, material::Lambertian, texture::SolidColor, utils::color::Color};
+
+        let mesh: Arc<dyn Hittable> = Arc::new(BVH::from_vec(
+            (0..10)
+                .map(|i| {
+                    Box::new(Sphere::new(
+                        Point3::new(i as f64 * 0.2, 0.0, 0.0),
+                        0.1,
+                        Arc::new(EmptyMaterial),
+                    )) as Box<dyn Hittable>
+                })
+                .collect(),
+        ));
+        let red: Arc<dyn Material> =
+            Arc::new(Lambertian::new(Arc::new(SolidColor::new(Color::RED))));
+
+        // 顶层 BVH 中的实例共享同一个底层 BVH
+        let instances: Vec<Box<dyn Hittable>> = (0..100)
+            .map(|i| {
+                let instance = Transform::instance(
+                    mesh.clone(),
+                    Some(Vec3::new(0.0, i as f64 * 3.0, 0.0)),
+                    None,
+                    None,
+                );
+                let instance = if i % 2 == 1 {
+                    instance.with_material(red.clone())
+                } else {
+                    instance
+                };
+                Box::new(instance) as Box<dyn Hittable>
+            })
+            .collect();
+        let world = BVH::from_vec(instances);
+        assert_eq!(Arc::strong_count(&mesh), 101);
+
+        let interval = Interval::new(1e-8, f64::INFINITY);
+        let ray = |y: f64| Ray::new(Point3::new(0.0, y, -5.0), Vec3::new(0.0, 0.0, 1.0));
+        let even = world.hit(&ray(6.0), &interval).unwrap();
+        let odd = world.hit(&ray(9.0), &interval).unwrap();
+        assert!((even.t - 4.9).abs() < 1e-8);
+        assert!((odd.p.y() - 9.0).abs() < 1e-8);
+        assert!(even.mat.name().ends_with("EmptyMaterial"));
+        assert_eq!(odd.mat.id(), red.id());
+        assert_ne!(even.object_id, odd.object_id);
+        assert!(world.hit(&ray(7.5), &interval).is_none());
+    }
+
+    #[test]
+    fn test_set_keyframes_in_place() {
+        let sphere: Arc<dyn Hittable> =
+            Arc::new(Sphere::new(Point3::ZERO, 1.0, Arc::new(EmptyMaterial)));
+        let mut object = Transform::instance(sphere, None, None, None);
+        let interval = Interval::new(1e-8, f64::INFINITY);
+        let ray = |x: f64| Ray::new(Point3::new(x, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0));
+        let id = object.hit(&ray(0.0), &interval).unwrap().object_id;
+
+        object.set_keyframes(vec![Keyframe::new(
+            0.0,
+            Some(Vec3::new(5.0, 0.0, 0.0)),
+            None,
+            None,
+        )]);
+        assert!(object.bounding_box().x().contains(5.5));
+        assert!(!object.bounding_box().x().contains(0.0));
+        assert_eq!(object.hit(&ray(5.0), &interval).unwrap().object_id, id);
+    }
+
+    #[test]
+    fn test_rotation_stays_in_bbox() {
+        use crate::aabb::AABB;
+
+        let sphere = Sphere::new(Point3::new(3.0, 0.0, 0.0), 0.5, Arc::new(EmptyMaterial));
+        let axis = Vec3::new(0.0, 1.0, 0.0);
+        let t = Transform::new_with_keyframes(
+            Box::new(sphere),
+            vec![
+                Keyframe::new(0.0, None, None, None),
+                Keyframe::new(
+                    1.0,
+                    None,
+                    Some(Quaternion::from_axis_angle(axis, 170.0)),
+                    None,
+                ),
+            ],
+        );
+
+        // 在采样之间的时刻，球的包围盒角点仍在运动包围盒内
+        let corners = AABB::from_points(Point3::new(2.5, -0.5, -0.5), Point3::new(3.5, 0.5, 0.5))
+            .all_points();
+        let bbox = t.bounding_box();
+        for i in 0..=1000 {
+            let frame = t.keyframe_at(i as f64 / 1000.0);
+            for &corner in &corners {
+                let p = frame.transform(corner);
+                assert!(bbox.x().contains(p.x()) && bbox.z().contains(p.z()));
+            }
+        }
+    }
+}
@@ -0,0 +1,473 @@
+use std::sync::Arc;
+
+use crate::{
+    aabb::AABB,
+    bvh::Primitives,
+    hit::HitRecord,
+    material::Material,
+    shapes::triangle::{self, intersect},
+    stats::{self, Counter},
+    texture::{ImageTexture, Texture},
+    utils::{
+        color::Color,
+        interval::Interval,
+        random::Random,
+        ray::Ray,
+        vec3::{Point3, UnitVec3, Vec3},
+    },
+};
+
+// 以索引共享顶点属性的三角形网格，法线、纹理坐标、切线与颜色可以为空
+// 三角形以下标作为 BVH 的图元，在求交时插值顶点属性
+pub struct TriangleMesh {
+    positions: Vec<Point3>,
+    normals: Vec<Vec3>,
+    texcoords: Vec<[f64; 2]>,
+    // 由纹理坐标计算的逐顶点切线，指向 u 增大的方向
+    tangents: Vec<Vec3>,
+    // 线性空间的顶点颜色，插值后记录在 HitRecord::color 中
+    colors: Vec<Color>,
+    indices: Vec<[u32; 3]>,
+    material: Arc<dyn Material>,
+    normal_texture: Option<Arc<ImageTexture>>,
+}
+
+impl TriangleMesh {
+    // 退化的三角形被去掉，normals 与 texcoords 为空或与 positions 一一对应
+    pub fn new(
+        positions: Vec<Point3>,
+        normals: Vec<Vec3>,
+        texcoords: Vec<[f64; 2]>,
+        indices: Vec<[u32; 3]>,
+        material: Arc<dyn Material>,
+    ) -> TriangleMesh {
+        assert!(normals.is_empty() || normals.len() == positions.len());
+        assert!(texcoords.is_empty() || texcoords.len() == positions.len());
+        assert!(
+            indices
+                .iter()
+                .flatten()
+                .all(|&i| (i as usize) < positions.len()),
+            "Vertex index out of range"
+        );
+
+        let mut mesh = TriangleMesh {
+            positions,
+            normals,
+            texcoords,
+            tangents: Vec::new(),
+            colors: Vec::new(),
+            indices,
+            material,
+            normal_texture: None,
+        };
+        mesh.indices.retain(|&index| {
+            let [p0, p1, p2] = index.map(|i| mesh.positions[i as usize]);
+            UnitVec3::from_vec3(Vec3::cross(&(p1 - p0), &(p2 - p0))).is_some()
+        });
+        mesh.calculate_tangents();
+        mesh
+    }
+
+    // 法线贴图在切线空间中扰动插值后的法线
+    pub fn with_normal_texture(mut self, normal_texture: Arc<ImageTexture>) -> TriangleMesh {
+        self.normal_texture = Some(normal_texture);
+        self
+    }
+
+    pub fn with_colors(mut self, colors: Vec<Color>) -> TriangleMesh {
+        assert!(colors.is_empty() || colors.len() == self.positions.len());
+        self.colors = colors;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.indices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    pub fn positions(&self) -> &[Point3] {
+        &self.positions
+    }
+
+    pub fn normals(&self) -> &[Vec3] {
+        &self.normals
+    }
+
+    pub fn texcoords(&self) -> &[[f64; 2]] {
+        &self.texcoords
+    }
+
+    pub fn colors(&self) -> &[Color] {
+        &self.colors
+    }
+
+    pub fn indices(&self) -> &[[u32; 3]] {
+        &self.indices
+    }
+
+    // 各三角形按面积累加到顶点上
+    fn calculate_tangents(&mut self) {
+        if self.texcoords.is_empty() {
+            return;
+        }
+
+        let mut tangents = vec![Vec3::ZERO; self.positions.len()];
+        for index in &self.indices {
+            let [p0, p1, p2] = index.map(|i| self.positions[i as usize]);
+            let [t0, t1, t2] = index.map(|i| self.texcoords[i as usize]);
+            let (e1, e2) = (p1 - p0, p2 - p0);
+            let (du1, dv1, du2, dv2) = (t1[0] - t0[0], t1[1] - t0[1], t2[0] - t0[0], t2[1] - t0[1]);
+            let det = du1 * dv2 - du2 * dv1;
+            if det.abs() < 1e-12 {
+                continue;
+            }
+
+            let tangent = (e1 * dv2 - e2 * dv1) / det;
+            for i in index {
+                tangents[*i as usize] += tangent;
+            }
+        }
+        self.tangents = tangents;
+    }
+
+    fn vertices(&self, index: u32) -> [usize; 3] {
+        self.indices[index as usize].map(|i| i as usize)
+    }
+
+    fn points(&self, index: u32) -> [Point3; 3] {
+        self.vertices(index).map(|i| self.positions[i])
+    }
+
+    fn area(&self, index: u32) -> f64 {
+        let [p0, p1, p2] = self.points(index);
+        Vec3::cross(&(p1 - p0), &(p2 - p0)).length() / 2.0
+    }
+}
+
+// 网格的每个三角形是一个图元，BVH 的叶节点只保存三角形的下标
+impl Primitives for TriangleMesh {
+    fn len(&self) -> usize {
+        self.indices.len()
+    }
+
+    fn bounding_box(&self, index: u32) -> AABB {
+        let [p0, p1, p2] = self.points(index);
+        AABB::union(AABB::from_points(p0, p1), AABB::from_points(p0, p2))
+    }
+
+    fn hit(&self, index: u32, r: &Ray, interval: &Interval) -> Option<HitRecord<'_>> {
+        stats::count(Counter::PrimitiveTests);
+
+        let vertices = self.vertices(index);
+        let p = vertices.map(|i| self.positions[i]);
+        let (t, b) = intersect(p, r, interval)?;
+        let interpolate = |v: [Vec3; 3]| b[0] * v[0] + b[1] * v[1] + b[2] * v[2];
+
+        let edges = (p[1] - p[0], p[2] - p[0]);
+        let (u, v, (dpdu, dpdv)) = if self.texcoords.is_empty() {
+            (b[1], b[2], edges)
+        } else {
+            let uv = vertices.map(|i| self.texcoords[i]);
+            (
+                b[0] * uv[0][0] + b[1] * uv[1][0] + b[2] * uv[2][0],
+                b[0] * uv[0][1] + b[1] * uv[1][1] + b[2] * uv[2][1],
+                triangle::tangents(p, uv).unwrap_or(edges),
+            )
+        };
+
+        let geometric = UnitVec3::from_vec3(Vec3::cross(&(p[1] - p[0]), &(p[2] - p[0])))?;
+        let intersection = r.at(t);
+        let mut rec = HitRecord::new(intersection, geometric, self.material.as_ref(), t, u, v, r)
+            .with_tangents(dpdu, dpdv);
+        if !self.colors.is_empty() {
+            rec = rec.with_color(interpolate(vertices.map(|i| self.colors[i])));
+        }
+
+        if self.normals.is_empty() {
+            return Some(rec);
+        }
+        let Some(mut normal) = UnitVec3::from_vec3(interpolate(vertices.map(|i| self.normals[i])))
+        else {
+            return Some(rec);
+        };
+
+        if let (Some(normal_texture), false) = (&self.normal_texture, self.tangents.is_empty()) {
+            // 切线对法线正交化，副切线的方向由纹理坐标的朝向决定
+            let tangent = interpolate(vertices.map(|i| self.tangents[i]));
+            let tangent = tangent - normal.dot(&tangent) * normal.into_inner();
+            if let Some(tangent) = UnitVec3::from_vec3(tangent) {
+                let uv = vertices.map(|i| self.texcoords[i]);
+                let det = (uv[1][0] - uv[0][0]) * (uv[2][1] - uv[0][1])
+                    - (uv[2][0] - uv[0][0]) * (uv[1][1] - uv[0][1]);
+                let bitangent = Vec3::cross(&normal, &tangent) * det.signum();
+
+                let color =
+                    normal_texture.value(u, v, &intersection) * 2.0 - Vec3::new(1.0, 1.0, 1.0);
+                let mapped = tangent.into_inner() * color[0]
+                    + bitangent * color[1]
+                    + normal.into_inner() * color[2];
+                normal = UnitVec3::from_vec3(mapped).unwrap_or(normal);
+            }
+        }
+
+        Some(rec.with_shading_normal(normal))
+    }
+
+    // 第 index 个三角形对方向的立体角 pdf，以几何法线换算
+    fn pdf_value(&self, index: u32, origin: &Point3, direction: &Vec3, _time: f64) -> f64 {
+        let [p0, p1, p2] = self.points(index);
+        let r = Ray::new(*origin, *direction);
+        let Some((t, _)) = intersect([p0, p1, p2], &r, &Interval::new(1e-8, f64::INFINITY)) else {
+            return 0.0;
+        };
+
+        let normal = Vec3::cross(&(p1 - p0), &(p2 - p0));
+        let distance_squared = t * t * direction.length_squared();
+        let cosine = (direction.dot(&normal) / (direction.length() * normal.length())).abs();
+
+        distance_squared / (cosine * self.area(index))
+    }
+
+    // 在第 index 个三角形上均匀取点，返回指向该点的方向
+    fn random(&self, index: u32, origin: &Point3, _time: f64) -> UnitVec3 {
+        let [p0, p1, p2] = self.points(index);
+        let mut u = Random::f64();
+        let mut v = Random::f64();
+
+        if u + v > 1.0 {
+            (u, v) = (1.0 - v, 1.0 - u);
+        }
+
+        let p = p0 + u * (p1 - p0) + v * (p2 - p0);
+        UnitVec3::from_vec3(p - origin).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bvh::{BVH, SplitMethod, linear::LinearBVH},
+        hit::Hittable,
+        material::Lambertian,
+        shapes::triangle::Triangle,
+        texture::SolidColor,
+        utils::color::Color,
+    };
+
+    fn quad() -> TriangleMesh {
+        let mat = Arc::new(Lambertian::new(Arc::new(SolidColor::new(Color::WHITE))));
+        let positions = vec![
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(2.0, 0.0, 0.0),
+            Point3::new(2.0, 2.0, 0.0),
+            Point3::new(0.0, 2.0, 0.0),
+            // 与第一个顶点重合，构成退化的三角形
+            Point3::new(0.0, 0.0, 0.0),
+        ];
+        let normals = vec![
+            Vec3::new(-1.0, 0.0, 1.0),
+            Vec3::new(1.0, 0.0, 1.0),
+            Vec3::new(1.0, 0.0, 1.0),
+            Vec3::new(-1.0, 0.0, 1.0),
+            Vec3::new(0.0, 0.0, 1.0),
+        ];
+        let texcoords = vec![[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0], [0.0, 0.0]];
+        TriangleMesh::new(
+            positions,
+            normals,
+            texcoords,
+            vec![[0, 1, 2], [0, 2, 3], [0, 4, 1]],
+            mat,
+        )
+    }
+
+    #[test]
+    fn test_interpolated_attributes() {
+        let mesh = quad();
+        assert_eq!(mesh.len(), 2);
+
+        let r = Ray::new(Point3::new(1.5, 0.5, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let interval = Interval::from_range(0.001..f64::INFINITY);
+        let rec = (0..2).find_map(|i| mesh.hit(i, &r, &interval)).unwrap();
+        assert!((rec.t - 1.0).abs() < 1e-12);
+        assert!((rec.u - 0.75).abs() < 1e-12 && (rec.v - 0.25).abs() < 1e-12);
+        // x 方向的法线由 -1 线性插值到 1
+        assert!(rec.normal.x() > 0.0 && rec.front_face);
+        assert_eq!(rec.geometric_normal.e(), [0.0, 0.0, 1.0]);
+        // 纹理坐标在 x、y 方向上各覆盖 2 个单位
+        assert!((rec.dpdu - Vec3::new(2.0, 0.0, 0.0)).length() < 1e-12);
+        assert!((rec.dpdv - Vec3::new(0.0, 2.0, 0.0)).length() < 1e-12);
+
+        // 从背面击中时法线朝向光线
+        let r = Ray::new(Point3::new(1.5, 0.5, -1.0), Vec3::new(0.0, 0.0, 1.0));
+        let rec = (0..2).find_map(|i| mesh.hit(i, &r, &interval)).unwrap();
+        assert!(rec.normal.z() < 0.0 && !rec.front_face);
+        assert_eq!(rec.geometric_normal.e(), [0.0, 0.0, -1.0]);
+
+        let mat = Arc::new(Lambertian::new(Arc::new(SolidColor::new(Color::WHITE))));
+        let reference = Triangle::new(
+            Point3::ZERO,
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(2.0, 2.0, 0.0),
+            mat,
+        )
+        .unwrap();
+        for _ in 0..200 {
+            let r = Ray::new(
+                Point3::random_range(-1.0..3.0),
+                Vec3::random_range(-1.0..1.0),
+            );
+            let expected = reference.hit(&r, &interval).map(|rec| rec.t);
+            let t = mesh.hit(0, &r, &interval).map(|rec| rec.t);
+            assert_eq!(t.is_some(), expected.is_some());
+            if let (Some(t), Some(expected)) = (t, expected) {
+                assert!((t - expected).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn test_pdf_uses_geometric_normal() {
+        let mesh = quad();
+        let mat = Arc::new(Lambertian::new(Arc::new(SolidColor::new(Color::WHITE))));
+        let [p0, p1, p2] = [0, 1, 2].map(|i| mesh.positions[i]);
+        let [n0, n1, n2] = [0, 1, 2].map(|i| mesh.normals[i]);
+        let triangle = Triangle::from_vertices([p0, p1, p2], mat)
+            .unwrap()
+            .with_normals([n0, n1, n2]);
+
+        let origin = Point3::new(1.0, 0.0, 1.0);
+        let direction = Vec3::new(0.5, 0.5, -1.0);
+        // 几何法线为 z 轴，距离的平方为 1.5，余弦为 1/sqrt(1.5)，面积为 2
+        let expected = 1.5 * 1.5f64.sqrt() / 2.0;
+        assert!((triangle.pdf_value(&origin, &direction, 0.0) - expected).abs() < 1e-12);
+        assert!((mesh.pdf_value(0, &origin, &direction, 0.0) - expected).abs() < 1e-12);
+    }
+
+    // 经纬划分的闭合球面，顶点在相邻三角形之间共享
+    fn closed_sphere(center: Point3, radius: f64) -> (Vec<Point3>, Vec<[u32; 3]>) {
+        const SLICES: u32 = 24;
+        const STACKS: u32 = 12;
+        let mut positions = vec![center + Vec3::new(0.0, radius, 0.0)];
+        for i in 1..STACKS {
+            let theta = std::f64::consts::PI * i as f64 / STACKS as f64;
+            for j in 0..SLICES {
+                let phi = 2.0 * std::f64::consts::PI * j as f64 / SLICES as f64;
+                let dir = Vec3::new(
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                );
+                positions.push(center + radius * dir);
+            }
+        }
+        positions.push(center - Vec3::new(0.0, radius, 0.0));
+
+        let ring = |i: u32, j: u32| 1 + (i - 1) * SLICES + j % SLICES;
+        let bottom = positions.len() as u32 - 1;
+        let mut indices = Vec::new();
+        for j in 0..SLICES {
+            indices.push([0, ring(1, j), ring(1, j + 1)]);
+            indices.push([bottom, ring(STACKS - 1, j + 1), ring(STACKS - 1, j)]);
+            for i in 1..STACKS - 1 {
+                indices.push([ring(i, j), ring(i + 1, j), ring(i + 1, j + 1)]);
+                indices.push([ring(i, j), ring(i + 1, j + 1), ring(i, j + 1)]);
+            }
+        }
+        (positions, indices)
+    }
+
+    #[test]
+    fn test_shading_normal_cannot_go_below_surface() {
+        use crate::material::{Material, ScatterRecord, disney::Disney};
+
+        let mesh = quad();
+        let r = Ray::new(Point3::new(1.5, 0.5, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let interval = Interval::from_range(0.001..f64::INFINITY);
+        let rec = (0..2).find_map(|i| mesh.hit(i, &r, &interval)).unwrap();
+
+        // 该方向在着色法线的上半球，却在几何表面之下
+        let below = Vec3::new(1.0, 0.0, -0.05);
+        assert!(below.dot(rec.normal.as_inner()) > 0.0);
+        assert!(below.dot(rec.geometric_normal.as_inner()) < 0.0);
+
+        for mat in [rec.mat, &Disney::new() as &dyn Material] {
+            let Some(ScatterRecord::PDF(pdf)) = mat.scatter(&r, &rec) else {
+                panic!("Expected a pdf");
+            };
+            assert_eq!(pdf.value(&below).1, 0.0);
+            for _ in 0..200 {
+                if let Some(direction) = pdf.generate() {
+                    assert!(direction.dot(&rec.geometric_normal) > 0.0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_watertight() {
+        let mat = Arc::new(Lambertian::new(Arc::new(SolidColor::new(Color::WHITE))));
+        let center = Point3::new(0.3, -0.2, 0.9);
+        let (positions, indices) = closed_sphere(center, 1.7);
+
+        let mesh = TriangleMesh::new(
+            positions.clone(),
+            Vec::new(),
+            Vec::new(),
+            indices.clone(),
+            mat.clone(),
+        );
+        let triangles: Vec<Box<dyn Hittable>> = indices
+            .iter()
+            .filter_map(|index| {
+                Triangle::from_vertices(index.map(|i| positions[i as usize]), mat.clone())
+                    .map(|t| Box::new(t) as Box<dyn Hittable>)
+            })
+            .collect();
+        let worlds: [Box<dyn Hittable>; 2] = [
+            Box::new(LinearBVH::with_method(mesh, SplitMethod::default())),
+            Box::new(BVH::from_vec(triangles)),
+        ];
+
+        // 对准顶点、边上的点与面内随机点的光线最容易从缝隙漏过
+        let mut targets = positions.clone();
+        for index in &indices {
+            let [p0, p1, p2] = index.map(|i| positions[i as usize]);
+            targets.push(p0 + 0.5 * (p1 - p0));
+            targets.push(p1 + 0.3 * (p2 - p1));
+            targets.push(p2 + 0.7 * (p0 - p2));
+            let (a, b) = (Random::f64(), Random::f64());
+            let (a, b) = if a + b > 1.0 {
+                (1.0 - a, 1.0 - b)
+            } else {
+                (a, b)
+            };
+            targets.push(p0 + a * (p1 - p0) + b * (p2 - p0));
+        }
+
+        let interval = Interval::from_range(0.0..f64::INFINITY);
+        for world in &worlds {
+            for &target in &targets {
+                for _ in 0..8 {
+                    // 从内部与外部射向目标点
+                    let inside = center + Vec3::random_range(-0.5..0.5);
+                    let outside = target + 3.0 * (target - center) + Vec3::random_range(-0.5..0.5);
+                    for origin in [inside, outside] {
+                        let r = Ray::new(origin, target - origin);
+                        assert!(
+                            world.hit(&r, &interval).is_some(),
+                            "Ray from {:?} to {:?} leaked through the mesh",
+                            origin.e(),
+                            target.e()
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,212 +1,264 @@
+mod cache;
+
 use std::{
     env::{self, current_dir},
-    path::PathBuf,
-    sync::Arc,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Instant,
 };
 
+use rayon::prelude::*;
+
 use crate::{
-    bvh::BVH,
-    hit::{HitRecord, Hittable},
+    bvh::{SplitMethod, linear::LinearBVH},
+    hit::{HitRecord, Hittable, next_object_id},
     hits::Hittables,
     material::{
         Dielectric, DiffuseLight, EmptyMaterial, Material, Metal, Mix, Transparent,
         disney::{Disney, DisneyParameters},
     },
-    shapes::triangle::Triangle,
+    shapes::{
+        mesh::TriangleMesh,
+        obj::cache::{CachedObject, MeshCache},
+    },
     texture::{ImageTexture, SolidColor, Texture},
     utils::vec3::{Point3, UnitVec3, Vec3},
 };
 
-struct RemappedMaterial {
-    pub material: Arc<dyn Material>,
-    pub tex_ori: Point3,
-    pub tex_u: Vec3,
-    pub tex_v: Vec3,
-    pub u_vec: Option<UnitVec3>,
-    pub v_vec: Option<UnitVec3>,
-    pub normal: [Vec3; 3],
-    pub normal_tex: Option<Arc<ImageTexture>>,
-}
-
-impl RemappedMaterial {
-    fn remap_record<'a>(&self, rec: &'a HitRecord) -> HitRecord<'a> {
-        let tex_coord = self.tex_ori + rec.u * self.tex_u + rec.v * self.tex_v;
-        let normal = UnitVec3::from_vec3(
-            (1.0 - rec.u - rec.v) * self.normal[0]
-                + rec.u * self.normal[1]
-                + rec.v * self.normal[2],
-        )
-        .unwrap();
-
-        let normal = if let Some(normal_tex) = &self.normal_tex {
-            let normal_color = normal_tex.value(tex_coord.x(), tex_coord.y(), &rec.p);
-            let normal_color = normal_color * 2.0 - Vec3::new(1.0, 1.0, 1.0);
-
-            let normal_raw = self.u_vec.unwrap().as_inner() * normal_color[0]
-                + self.v_vec.unwrap().as_inner() * normal_color[1]
-                + normal.as_inner() * normal_color[2];
-            UnitVec3::from_vec3(normal_raw).expect("The mapped normal can't normalized!")
-        } else {
-            normal
-        };
-
-        HitRecord {
-            p: rec.p,
-            normal,
-            mat: rec.mat,
-            t: rec.t,
-            u: tex_coord.x(),
-            v: tex_coord.y(),
-            front_face: rec.front_face,
-        }
-    }
-}
-
-impl Material for RemappedMaterial {
-    fn scatter(
-        &self,
-        r_in: &crate::utils::ray::Ray,
-        rec: &crate::hit::HitRecord,
-    ) -> Option<crate::material::ScatterRecord> {
-        self.material.scatter(r_in, &self.remap_record(rec))
-    }
-
-    fn emitted(
-        &self,
-        r_in: &crate::utils::ray::Ray,
-        rec: &HitRecord,
-    ) -> crate::utils::color::Color {
-        self.material.emitted(r_in, &self.remap_record(rec))
-    }
-}
-
 pub struct Wavefont {
     objects: Hittables,
+    id: u32,
 }
 
 impl Wavefont {
-    fn load(file_name: &str) -> tobj::LoadResult {
+    // 文件位于环境变量 RTW_OBJS 指定的目录，或当前目录下的 assets 中
+    pub(super) fn path(file_name: &str) -> Option<PathBuf> {
         if let Ok(specified_dir) = env::var("RTW_OBJS") {
-            let mut path = PathBuf::new();
-            path.push(specified_dir);
-            path.push(file_name);
-            return tobj::load_obj(path, &tobj::GPU_LOAD_OPTIONS);
+            return Some(PathBuf::from(specified_dir).join(file_name));
         };
 
-        let Ok(pathbuf) = current_dir() else {
-            return Err(tobj::LoadError::OpenFileFailed);
-        };
-
-        let mut path = pathbuf;
-        path.push("assets");
-        path.push(file_name);
-
-        tobj::load_obj(path, &tobj::GPU_LOAD_OPTIONS)
-    }
-
-    fn get_three_values(v: &[f64], index: usize) -> Vec3 {
-        let index = index * 3;
-        Vec3::from([v[index], v[index + 1], v[index + 2]])
-    }
-
-    fn get_two_values(v: &[f64], index: usize) -> Vec3 {
-        let index = index * 2;
-        Vec3::from([v[index], v[index + 1], 0.0])
+        Some(current_dir().ok()?.join("assets").join(file_name))
     }
 
     pub fn new(file_name: &str, prefix: &str, vanilla_material: bool) -> Option<Wavefont> {
         let file_path = prefix.to_owned() + "/" + file_name;
-        let (objects, materials) = Self::load(&file_path).ok()?;
+        Wavefont::from_path(&Self::path(&file_path)?, prefix, vanilla_material)
+    }
+
+    // 三角化的网格与构建好的 BVH 缓存在磁盘上，源文件与加载选项不变时直接读取
+    // 材质每次都从 mtllib 重新加载
+    pub fn from_path(path: &Path, prefix: &str, vanilla_material: bool) -> Option<Wavefont> {
+        let start = Instant::now();
+        let source = fs::read(path).ok()?;
+        let key = cache::key(&source);
+        let cache_path = cache::path(path);
+        let dir = path.parent().unwrap_or(Path::new("."));
 
         let mut mats: Vec<Arc<dyn Material>> = vec![];
         let mut normals: Vec<Option<Arc<ImageTexture>>> = vec![];
+
+        if let Some(mesh) = MeshCache::load(&cache_path, key) {
+            let materials: Result<Vec<_>, _> = mesh
+                .mtllibs
+                .iter()
+                .map(|lib| tobj::load_mtl(dir.join(lib)).map(|(materials, _)| materials))
+                .collect();
+            if let Ok(materials) = materials {
+                load_materials(
+                    &mut mats,
+                    &mut normals,
+                    materials.concat(),
+                    prefix,
+                    vanilla_material,
+                );
+            }
+
+            let loaded: io::Result<Vec<_>> = mesh
+                .objects
+                .into_par_iter()
+                .zip(normals.par_iter())
+                .map(|(object, normal)| load_cached(&mats, object, normal))
+                .collect();
+            match loaded {
+                Ok(loaded) => {
+                    let loaded: Vec<_> = loaded.into_iter().flatten().collect();
+                    let triangles: usize = loaded.iter().map(|(_, count)| count).sum();
+                    println!(
+                        "Loaded {} from cache: {} objects, {triangles} triangles ({:.2}s)",
+                        path.display(),
+                        loaded.len(),
+                        start.elapsed().as_secs_f64()
+                    );
+                    return Some(Wavefont::from_objects(loaded));
+                }
+                Err(e) => println!("Ignore the mesh cache {}: {e}", cache_path.display()),
+            }
+
+            mats.clear();
+            normals.clear();
+        }
+
+        // 记录 mtllib 的文件名，读取缓存时据此加载材质
+        let mtllibs = Mutex::new(Vec::new());
+        let (objects, materials) =
+            tobj::load_obj_buf(&mut &source[..], &tobj::GPU_LOAD_OPTIONS, |lib| {
+                mtllibs
+                    .lock()
+                    .unwrap()
+                    .push(lib.to_string_lossy().into_owned());
+                tobj::load_mtl(dir.join(lib))
+            })
+            .ok()?;
+
         if let Ok(materials) = materials {
             load_materials(&mut mats, &mut normals, materials, prefix, vanilla_material);
         }
 
-        let mut obs = Hittables::default();
+        let parse_time = start.elapsed();
+
+        // 各物体的三角形与 BVH 并行构建
+        let start = Instant::now();
+        let (loaded, cached): (Vec<_>, Vec<_>) = objects
+            .par_iter()
+            .zip(normals.par_iter())
+            .map(|(object, normal)| load_object(&mats, object, normal))
+            .unzip();
+        let build_time = start.elapsed();
 
-        for (object, normal) in objects.iter().zip(normals.iter()) {
-            load_object(file_name, &mats, &mut obs, object, normal);
+        let mesh = MeshCache {
+            mtllibs: mtllibs.into_inner().unwrap(),
+            objects: cached,
+        };
+        if let Err(e) = mesh.save(&cache_path, key) {
+            println!("Cannot write the mesh cache {}: {e}", cache_path.display());
+        }
+
+        let loaded: Vec<_> = loaded.into_iter().flatten().collect();
+        let triangles: usize = loaded.iter().map(|(_, count)| count).sum();
+        println!(
+            "Loaded {}: {} objects, {triangles} triangles (parse {:.2}s, build {:.2}s)",
+            path.display(),
+            loaded.len(),
+            parse_time.as_secs_f64(),
+            build_time.as_secs_f64()
+        );
+
+        Some(Wavefont::from_objects(loaded))
+    }
+
+    fn from_objects(loaded: Vec<LoadedObject>) -> Wavefont {
+        let mut obs = Hittables::default();
+        for (object, _) in loaded {
+            obs.add(object);
         }
 
-        Some(Wavefont { objects: obs })
+        Wavefont {
+            objects: obs,
+            id: next_object_id(),
+        }
     }
 }
 
+// 物体的 BVH 与其中三角形的数量
+type LoadedObject = (Box<dyn Hittable>, usize);
+
+// 返回加载的物体，以及按 BVH 叶节点顺序保存的缓存
 fn load_object(
-    file_name: &str,
     mats: &[Arc<dyn Material>],
-    obs: &mut Hittables,
     object: &tobj::Model,
     normal_texture: &Option<Arc<ImageTexture>>,
-) {
-    let empty_material = Arc::new(EmptyMaterial);
-
-    let mut v: Vec<Box<dyn Hittable>> = Vec::new();
-    for indices in object.mesh.indices.chunks(3) {
-        let p1 = Wavefont::get_three_values(&object.mesh.positions, indices[0] as usize);
-        let p2 = Wavefont::get_three_values(&object.mesh.positions, indices[1] as usize);
-        let p3 = Wavefont::get_three_values(&object.mesh.positions, indices[2] as usize);
-
-        let tex_p1 = Wavefont::get_two_values(&object.mesh.texcoords, indices[0] as usize);
-        let tex_p2 = Wavefont::get_two_values(&object.mesh.texcoords, indices[1] as usize);
-        let tex_p3 = Wavefont::get_two_values(&object.mesh.texcoords, indices[2] as usize);
-
-        let n_p1 = Wavefont::get_three_values(&object.mesh.normals, indices[0] as usize);
-        let n_p2 = Wavefont::get_three_values(&object.mesh.normals, indices[1] as usize);
-        let n_p3 = Wavefont::get_three_values(&object.mesh.normals, indices[2] as usize);
-
-        let mat = if let Some(id) = object.mesh.material_id {
-            mats[id].clone()
-        } else {
-            empty_material.clone()
-        };
-
-        let tex_u = tex_p2 - tex_p1;
-        let tex_v = tex_p3 - tex_p1;
-
-        let world_u = p2 - p1;
-        let world_v = p3 - p1;
-
-        let (u_vec, v_vec) = uv_local_to_world(tex_u, tex_v, world_u, world_v);
+) -> (Option<LoadedObject>, CachedObject) {
+    let mesh = &object.mesh;
+    let vec3s = |v: &[f64]| -> Vec<Vec3> {
+        v.chunks_exact(3)
+            .map(|v| Vec3::new(v[0], v[1], v[2]))
+            .collect()
+    };
+    let triangle_mesh = build_mesh(
+        mats,
+        mesh.material_id,
+        vec3s(&mesh.positions),
+        vec3s(&mesh.normals),
+        mesh.texcoords
+            .chunks_exact(2)
+            .map(|t| [t[0], t[1]])
+            .collect(),
+        mesh.indices
+            .chunks_exact(3)
+            .map(|i| [i[0], i[1], i[2]])
+            .collect(),
+        normal_texture,
+    );
+
+    let mut cached = CachedObject {
+        material_id: mesh.material_id,
+        positions: triangle_mesh.positions().to_vec(),
+        normals: triangle_mesh.normals().to_vec(),
+        texcoords: triangle_mesh.texcoords().to_vec(),
+        indices: Vec::new(),
+        nodes: Vec::new(),
+    };
+    if triangle_mesh.is_empty() {
+        println!("The object {} is empty!", object.name);
+        return (None, cached);
+    }
 
-        let mat = Arc::new(RemappedMaterial {
-            material: mat,
-            tex_ori: tex_p1,
-            tex_u,
-            tex_v,
-            u_vec,
-            v_vec,
-            normal: [n_p1, n_p2, n_p3],
-            normal_tex: normal_texture.clone(),
-        });
+    let count = triangle_mesh.len();
+    let bvh = LinearBVH::with_method(triangle_mesh, SplitMethod::default());
+    cached.indices = bvh
+        .primitive_indices()
+        .iter()
+        .map(|&index| bvh.primitives().indices()[index as usize])
+        .collect();
+    bvh.write_nodes(&mut cached.nodes)
+        .expect("Writing to a Vec never fails");
+
+    (Some((Box::new(bvh), count)), cached)
+}
 
-        if let Some(triangle) = Triangle::new(p1, world_u, world_v, mat) {
-            v.push(Box::new(triangle));
-        }
-    }
-    if !v.is_empty() {
-        obs.add(Box::new(BVH::from_vec(v)));
-    } else {
-        println!("The object {} from {} is empty!", object.name, file_name);
+// 由缓存的网格与节点恢复物体的 BVH，不再重新构建
+fn load_cached(
+    mats: &[Arc<dyn Material>],
+    object: CachedObject,
+    normal_texture: &Option<Arc<ImageTexture>>,
+) -> io::Result<Option<LoadedObject>> {
+    let triangle_mesh = build_mesh(
+        mats,
+        object.material_id,
+        object.positions,
+        object.normals,
+        object.texcoords,
+        object.indices,
+        normal_texture,
+    );
+    if triangle_mesh.is_empty() {
+        return Ok(None);
     }
+
+    let count = triangle_mesh.len();
+    let bvh = LinearBVH::read_nodes(&mut &object.nodes[..], triangle_mesh)?;
+    Ok(Some((Box::new(bvh), count)))
 }
 
-fn uv_local_to_world(
-    tex_u: Vec3,
-    tex_v: Vec3,
-    world_u: Vec3,
-    world_v: Vec3,
-) -> (Option<UnitVec3>, Option<UnitVec3>) {
-    let ua = tex_v.y() / (-tex_u.y() * tex_v.x() + tex_u.x() * tex_v.y());
-    let ub = tex_u.y() / (tex_u.y() * tex_v.x() - tex_u.x() * tex_v.y());
-    let va = tex_v.x() / (tex_u.y() * tex_v.x() - tex_u.x() * tex_v.y());
-    let vb = tex_u.x() / (-tex_u.y() * tex_v.x() + tex_u.x() * tex_v.y());
-
-    let u_vec = UnitVec3::from_vec3(world_u * ua + world_v * ub);
-    let v_vec = UnitVec3::from_vec3(world_u * va + world_v * vb);
-    (u_vec, v_vec)
+fn build_mesh(
+    mats: &[Arc<dyn Material>],
+    material_id: Option<usize>,
+    positions: Vec<Point3>,
+    normals: Vec<Vec3>,
+    texcoords: Vec<[f64; 2]>,
+    indices: Vec<[u32; 3]>,
+    normal_texture: &Option<Arc<ImageTexture>>,
+) -> TriangleMesh {
+    let material = match material_id.and_then(|id| mats.get(id)) {
+        Some(mat) => mat.clone(),
+        None => Arc::new(EmptyMaterial),
+    };
+    let mesh = TriangleMesh::new(positions, normals, texcoords, indices, material);
+    match normal_texture {
+        Some(normal_texture) => mesh.with_normal_texture(normal_texture.clone()),
+        None => mesh,
+    }
 }
 
 fn load_materials(
@@ -257,6 +309,11 @@ fn load_materials(
             .get("Pcr")
             .and_then(|s| s.parse::<f64>().ok())
             .unwrap_or(0.0);
+        let subsurface = material
+            .unknown_param
+            .get("Pss")
+            .and_then(|s| s.parse::<f64>().ok())
+            .unwrap_or(0.0);
         let ior = material.optical_density.unwrap_or(1.45);
         let spec_trans: f64 = if let Some(tf) = material.unknown_param.get("Tf") {
             let spec_trans_vals: Vec<f64> = tf
@@ -277,8 +334,8 @@ fn load_materials(
             Arc::new(Dielectric::new(base_color, ior))
         } else {
             Arc::new(Disney {
-                param_fn: Box::new(move |u, v, p| DisneyParameters {
-                    base_color: base_color.value(u, v, p),
+                param_fn: Box::new(move |rec| DisneyParameters {
+                    base_color: base_color.value_at(rec),
                     roughness,
                     anisotropic,
                     sheen,
@@ -287,6 +344,7 @@ fn load_materials(
                     metallic,
                     ior,
                     spec_trans,
+                    subsurface,
                     ..Default::default()
                 }),
             })
@@ -350,18 +408,101 @@ impl Hittable for Wavefont {
         r: &crate::utils::ray::Ray,
         interval: &crate::utils::interval::Interval,
     ) -> Option<crate::hit::HitRecord> {
-        self.objects.hit(r, interval)
+        self.objects.hit(r, interval).map(|rec| HitRecord {
+            object_id: self.id,
+            ..rec
+        })
     }
 
     fn bounding_box(&self) -> &crate::aabb::AABB {
         self.objects.bounding_box()
     }
 
-    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
-        self.objects.pdf_value(origin, direction)
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3, time: f64) -> f64 {
+        self.objects.pdf_value(origin, direction, time)
+    }
+
+    fn random(&self, origin: &Point3, time: f64) -> UnitVec3 {
+        self.objects.random(origin, time)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Write;
+
+    use super::*;
+    use crate::utils::{interval::Interval, ray::Ray};
+
+    // 两个物体，各为一片 8x8 的网格
+    fn grid_obj() -> String {
+        let mut obj = String::from("mtllib grid.mtl\n");
+        for (k, z) in [0.0, 5.0].into_iter().enumerate() {
+            writeln!(obj, "o grid{k}").unwrap();
+            writeln!(obj, "usemtl mat{k}").unwrap();
+            for j in 0..9 {
+                for i in 0..9 {
+                    writeln!(obj, "v {i} {j} {z}").unwrap();
+                    writeln!(obj, "vt {} {}", i as f64 / 8.0, j as f64 / 8.0).unwrap();
+                    writeln!(obj, "vn 0 0 1").unwrap();
+                }
+            }
+            let base = k * 81 + 1;
+            for j in 0..8 {
+                for i in 0..8 {
+                    let a = base + j * 9 + i;
+                    let [b, c, d] = [a + 1, a + 10, a + 9];
+                    writeln!(obj, "f {a}/{a}/{a} {b}/{b}/{b} {c}/{c}/{c} {d}/{d}/{d}").unwrap();
+                }
+            }
+        }
+        obj
     }
 
-    fn random(&self, origin: &Point3) -> UnitVec3 {
-        self.objects.random(origin)
+    #[test]
+    fn test_mesh_cache() {
+        let dir = env::temp_dir().join(format!("rtw_obj_cache_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("grid.obj");
+        fs::write(&path, grid_obj()).unwrap();
+        fs::write(
+            dir.join("grid.mtl"),
+            "newmtl mat0\nKd 1 1 1\nnewmtl mat1\nKd 0.5 0.5 0.5\n",
+        )
+        .unwrap();
+        let cache_path = cache::path(&path);
+        let _ = fs::remove_file(&cache_path);
+
+        let built = Wavefont::from_path(&path, "", false).unwrap();
+        assert!(cache_path.exists());
+        let cached = Wavefont::from_path(&path, "", false).unwrap();
+
+        // 损坏的缓存被忽略并重新构建
+        let bytes = fs::read(&cache_path).unwrap();
+        fs::write(&cache_path, &bytes[..bytes.len() / 2]).unwrap();
+        let rebuilt = Wavefont::from_path(&path, "", false).unwrap();
+        assert_eq!(fs::read(&cache_path).unwrap(), bytes);
+
+        let interval = Interval::from_range(0.001..f64::INFINITY);
+        let mut hits = 0;
+        for _ in 0..500 {
+            let r = Ray::new(
+                Point3::random_range(-2.0..10.0),
+                Vec3::random_range(-1.0..1.0),
+            );
+            let expected = built.hit(&r, &interval).map(|rec| (rec.t, rec.u, rec.v));
+            hits += expected.is_some() as usize;
+            assert_eq!(
+                cached.hit(&r, &interval).map(|rec| (rec.t, rec.u, rec.v)),
+                expected
+            );
+            assert_eq!(
+                rebuilt.hit(&r, &interval).map(|rec| (rec.t, rec.u, rec.v)),
+                expected
+            );
+        }
+        assert!(hits > 0);
+
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -0,0 +1,270 @@
+use std::{
+    env,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    bvh::SplitMethod,
+    utils::vec3::{Point3, Vec3},
+};
+
+const MAGIC: &[u8; 8] = b"RTWMESH\0";
+// 缓存格式的版本，格式或 LinearBVH 的节点布局改变时递增
+const VERSION: u32 = 3;
+// 影响缓存内容的加载选项，改变时缓存自动失效，BVH 的划分参数在 key 中另外加入
+const LOAD_OPTIONS: &str = "triangulate,single_index,bvh=linear";
+
+// 一个物体的三角形网格，indices 按 BVH 叶节点顺序存放，nodes 为 LinearBVH::write_nodes 写入的节点
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct CachedObject {
+    pub material_id: Option<usize>,
+    pub positions: Vec<Point3>,
+    pub normals: Vec<Vec3>,
+    pub texcoords: Vec<[f64; 2]>,
+    pub indices: Vec<[u32; 3]>,
+    pub nodes: Vec<u8>,
+}
+
+// 一个 OBJ 文件三角化并构建 BVH 后的结果，材质仍在每次加载时从 mtllib 读取
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(super) struct MeshCache {
+    pub mtllibs: Vec<String>,
+    pub objects: Vec<CachedObject>,
+}
+
+impl MeshCache {
+    // 缓存不存在、已损坏或源文件已改变时返回 None
+    pub fn load(path: &Path, key: u64) -> Option<MeshCache> {
+        let mut r = BufReader::new(File::open(path).ok()?);
+        MeshCache::read(&mut r, key).ok()
+    }
+
+    // 先写入临时文件再重命名，避免中断时留下不完整的缓存
+    pub fn save(&self, path: &Path, key: u64) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp_path = path.with_extension("tmp");
+        let mut w = BufWriter::new(File::create(&tmp_path)?);
+        self.write(&mut w, key)?;
+        w.into_inner()?.sync_all()?;
+        fs::rename(&tmp_path, path)
+    }
+
+    fn write(&self, w: &mut impl Write, key: u64) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        write_u32(w, VERSION)?;
+        w.write_all(&key.to_le_bytes())?;
+
+        write_u32(w, self.mtllibs.len() as u32)?;
+        for name in &self.mtllibs {
+            write_u32(w, name.len() as u32)?;
+            w.write_all(name.as_bytes())?;
+        }
+
+        write_u32(w, self.objects.len() as u32)?;
+        for object in &self.objects {
+            write_u32(w, object.material_id.map_or(u32::MAX, |id| id as u32))?;
+            for buffer in [&object.positions, &object.normals] {
+                write_u32(w, buffer.len() as u32)?;
+                for x in buffer.iter().flat_map(|v| v.e()) {
+                    w.write_all(&x.to_le_bytes())?;
+                }
+            }
+            write_u32(w, object.texcoords.len() as u32)?;
+            for x in object.texcoords.iter().flatten() {
+                w.write_all(&x.to_le_bytes())?;
+            }
+            write_u32(w, object.indices.len() as u32)?;
+            for i in object.indices.iter().flatten() {
+                write_u32(w, *i)?;
+            }
+            write_u32(w, object.nodes.len() as u32)?;
+            w.write_all(&object.nodes)?;
+        }
+        Ok(())
+    }
+
+    fn read(r: &mut impl Read, key: u64) -> io::Result<MeshCache> {
+        let mut magic = [0u8; 8];
+        r.read_exact(&mut magic)?;
+        let mut stored_key = [0u8; 8];
+        let version = read_u32(r)?;
+        r.read_exact(&mut stored_key)?;
+        if &magic != MAGIC || version != VERSION || u64::from_le_bytes(stored_key) != key {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Stale mesh cache",
+            ));
+        }
+
+        let mut mtllibs = Vec::new();
+        for _ in 0..read_u32(r)? {
+            let name = read_bytes(r)?;
+            mtllibs.push(String::from_utf8(name).map_err(io::Error::other)?);
+        }
+
+        let mut objects = Vec::new();
+        for _ in 0..read_u32(r)? {
+            let material_id = match read_u32(r)? {
+                u32::MAX => None,
+                id => Some(id as usize),
+            };
+            let mut read_vec3s = || -> io::Result<Vec<Vec3>> {
+                (0..read_u32(r)?)
+                    .map(|_| Ok(Vec3::new(read_f64(r)?, read_f64(r)?, read_f64(r)?)))
+                    .collect()
+            };
+            let positions = read_vec3s()?;
+            let normals = read_vec3s()?;
+            let texcoords: Vec<[f64; 2]> = (0..read_u32(r)?)
+                .map(|_| Ok([read_f64(r)?, read_f64(r)?]))
+                .collect::<io::Result<_>>()?;
+            // 法线与纹理坐标要么为空，要么与顶点一一对应，否则 TriangleMesh::new 会 panic
+            if [normals.len(), texcoords.len()]
+                .iter()
+                .any(|&len| len != 0 && len != positions.len())
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Mismatched vertex attributes",
+                ));
+            }
+            let indices: Vec<[u32; 3]> = (0..read_u32(r)?)
+                .map(|_| Ok([read_u32(r)?, read_u32(r)?, read_u32(r)?]))
+                .collect::<io::Result<_>>()?;
+            if indices
+                .iter()
+                .flatten()
+                .any(|&i| i as usize >= positions.len())
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Invalid vertex index",
+                ));
+            }
+            let nodes = read_bytes(r)?;
+            objects.push(CachedObject {
+                material_id,
+                positions,
+                normals,
+                texcoords,
+                indices,
+                nodes,
+            });
+        }
+
+        Ok(MeshCache { mtllibs, objects })
+    }
+}
+
+// 以源文件内容、加载选项与构建 BVH 使用的划分参数计算缓存的键
+pub(super) fn key(source: &[u8]) -> u64 {
+    let method = format!("{:?}", SplitMethod::default());
+    fnv1a(
+        source
+            .iter()
+            .chain(LOAD_OPTIONS.as_bytes())
+            .chain(method.as_bytes()),
+    )
+}
+
+fn fnv1a<'a>(bytes: impl Iterator<Item = &'a u8>) -> u64 {
+    bytes.fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+// 缓存目录由环境变量 RTW_CACHE 指定，默认为 OBJ 文件所在目录下的 .cache
+pub(super) fn path(source: &Path) -> PathBuf {
+    let dir = match env::var("RTW_CACHE") {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) => source.parent().unwrap_or(Path::new(".")).join(".cache"),
+    };
+    // 共用缓存目录时，不同目录下的同名文件以路径的哈希区分
+    let canonical = fs::canonicalize(source).unwrap_or_else(|_| source.to_path_buf());
+    let hash = fnv1a(canonical.as_os_str().as_encoded_bytes().iter());
+    let file_name = source.file_name().unwrap_or_default().to_string_lossy();
+    dir.join(format!("{file_name}.{hash:016x}.mesh"))
+}
+
+fn write_u32(w: &mut impl Write, x: u32) -> io::Result<()> {
+    w.write_all(&x.to_le_bytes())
+}
+
+fn read_u32(r: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+// 读取以长度开头的字节串，长度损坏时不会预先分配过大的内存
+fn read_bytes(r: &mut impl Read) -> io::Result<Vec<u8>> {
+    let len = read_u32(r)? as u64;
+    let mut bytes = Vec::new();
+    r.take(len).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(bytes)
+}
+
+fn read_f64(r: &mut impl Read) -> io::Result<f64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(f64::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let cache = MeshCache {
+            mtllibs: vec!["文字.mtl".to_owned()],
+            objects: vec![CachedObject {
+                material_id: Some(2),
+                positions: vec![
+                    Vec3::ZERO,
+                    Vec3::new(1.0, 0.0, 0.0),
+                    Vec3::new(0.0, 1.0, 0.0),
+                ],
+                normals: vec![Vec3::new(0.0, 0.0, 1.0); 3],
+                texcoords: Vec::new(),
+                indices: vec![[0, 1, 2], [2, 1, 0]],
+                nodes: vec![1, 2, 3],
+            }],
+        };
+
+        let key = key(b"v 0 0 0");
+        let mut buf = Vec::new();
+        cache.write(&mut buf, key).unwrap();
+        assert_eq!(MeshCache::read(&mut &buf[..], key).unwrap(), cache);
+        assert!(MeshCache::read(&mut &buf[..], key ^ 1).is_err());
+        assert!(MeshCache::read(&mut &buf[..buf.len() - 1], key).is_err());
+
+        let mut mismatched = cache.clone();
+        mismatched.objects[0].normals.pop();
+        let mut buf = Vec::new();
+        mismatched.write(&mut buf, key).unwrap();
+        let err = MeshCache::read(&mut &buf[..], key).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_ne!(key, super::key(b"v 0 0 1"));
+    }
+
+    #[test]
+    fn test_path_distinguishes_directories() {
+        let a = path(Path::new("a/mesh.obj"));
+        let b = path(Path::new("b/mesh.obj"));
+        assert_ne!(a.file_name(), b.file_name());
+        assert!(
+            a.file_name()
+                .unwrap()
+                .to_string_lossy()
+                .starts_with("mesh.obj.")
+        );
+    }
+}
@@ -0,0 +1,600 @@
+use std::{
+    fs, io,
+    path::Path,
+    str::{self, SplitAsciiWhitespace},
+    sync::Arc,
+    time::Instant,
+};
+
+use palette::Srgb;
+
+use crate::{
+    aabb::AABB,
+    bvh::{SplitMethod, linear::LinearBVH},
+    hit::{HitRecord, Hittable, next_object_id},
+    material::Material,
+    shapes::{mesh::TriangleMesh, obj::Wavefont},
+    utils::{
+        color::Color,
+        interval::Interval,
+        ray::Ray,
+        vec3::{Point3, UnitVec3, Vec3},
+    },
+};
+
+// 斯坦福 PLY 格式的网格，支持 ASCII 与大小端二进制
+// 顶点颜色插值后记录在 HitRecord::color 中，配合 VertexColor 纹理使用
+pub struct Ply {
+    bvh: LinearBVH<TriangleMesh>,
+    id: u32,
+}
+
+impl Ply {
+    // 与 Wavefont 相同，文件位于 RTW_OBJS 或当前目录下的 assets 中
+    pub fn new(file_name: &str, prefix: &str, material: Arc<dyn Material>) -> io::Result<Ply> {
+        let file_path = prefix.to_owned() + "/" + file_name;
+        let path = Wavefont::path(&file_path)
+            .ok_or_else(|| io::Error::other("Cannot find the assets directory"))?;
+        Ply::from_path(&path, material)
+    }
+
+    pub fn from_path(path: &Path, material: Arc<dyn Material>) -> io::Result<Ply> {
+        let start = Instant::now();
+        let data = PlyData::parse(&fs::read(path)?)?;
+        let vertices = data.positions.len();
+
+        let mesh = TriangleMesh::new(
+            data.positions,
+            data.normals,
+            data.texcoords,
+            data.indices,
+            material,
+        )
+        .with_colors(data.colors);
+        if mesh.is_empty() {
+            return Err(invalid_data("The mesh has no triangles"));
+        }
+
+        let triangles = mesh.len();
+        let bvh = LinearBVH::with_method(mesh, SplitMethod::default());
+        println!(
+            "Loaded {}: {vertices} vertices, {triangles} triangles ({:.2}s)",
+            path.display(),
+            start.elapsed().as_secs_f64()
+        );
+
+        Ok(Ply {
+            bvh,
+            id: next_object_id(),
+        })
+    }
+}
+
+impl Hittable for Ply {
+    fn hit(&self, r: &Ray, interval: &Interval) -> Option<HitRecord> {
+        self.bvh.hit(r, interval).map(|rec| HitRecord {
+            object_id: self.id,
+            ..rec
+        })
+    }
+
+    fn bounding_box(&self) -> &AABB {
+        self.bvh.bounding_box()
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3, time: f64) -> f64 {
+        self.bvh.pdf_value(origin, direction, time)
+    }
+
+    fn random(&self, origin: &Point3, time: f64) -> UnitVec3 {
+        self.bvh.random(origin, time)
+    }
+}
+
+// 解析得到的顶点属性为空或与 positions 一一对应，多边形已按扇形三角化
+#[derive(Debug, Default, PartialEq)]
+struct PlyData {
+    positions: Vec<Point3>,
+    normals: Vec<Vec3>,
+    texcoords: Vec<[f64; 2]>,
+    colors: Vec<Color>,
+    indices: Vec<[u32; 3]>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> io::Result<Scalar> {
+        Ok(match name {
+            "char" | "int8" => Scalar::I8,
+            "uchar" | "uint8" => Scalar::U8,
+            "short" | "int16" => Scalar::I16,
+            "ushort" | "uint16" => Scalar::U16,
+            "int" | "int32" => Scalar::I32,
+            "uint" | "uint32" => Scalar::U32,
+            "float" | "float32" => Scalar::F32,
+            "double" | "float64" => Scalar::F64,
+            _ => return Err(invalid_data(format!("Unknown property type {name}"))),
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+
+    // 整数类型的颜色按最大值归一化
+    fn color_scale(self) -> f64 {
+        match self {
+            Scalar::U8 => 1.0 / u8::MAX as f64,
+            Scalar::U16 => 1.0 / u16::MAX as f64,
+            _ => 1.0,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Property {
+    Scalar(Scalar, String),
+    // 元素个数的类型与元素的类型
+    List(Scalar, Scalar, String),
+}
+
+impl Property {
+    fn name(&self) -> &str {
+        match self {
+            Property::Scalar(_, name) | Property::List(_, _, name) => name,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+impl Element {
+    fn find(&self, names: &[&str]) -> Option<(usize, Scalar)> {
+        self.properties
+            .iter()
+            .enumerate()
+            .find_map(|(i, property)| match property {
+                Property::Scalar(ty, name) if names.contains(&name.as_str()) => Some((i, *ty)),
+                _ => None,
+            })
+    }
+}
+
+// 文件头之后的数据，所有数值都读为 f64
+enum Body<'a> {
+    Ascii(SplitAsciiWhitespace<'a>),
+    Binary { bytes: &'a [u8], big_endian: bool },
+}
+
+impl Body<'_> {
+    fn read(&mut self, ty: Scalar) -> io::Result<f64> {
+        match self {
+            Body::Ascii(tokens) => {
+                let token = tokens.next().ok_or(io::ErrorKind::UnexpectedEof)?;
+                token
+                    .parse()
+                    .map_err(|_| invalid_data(format!("Invalid number {token}")))
+            }
+            Body::Binary { bytes, big_endian } => {
+                let size = ty.size();
+                if bytes.len() < size {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                let (head, rest) = bytes.split_at(size);
+                *bytes = rest;
+
+                let mut buf = [0u8; 8];
+                buf[..size].copy_from_slice(head);
+                if *big_endian {
+                    buf[..size].reverse();
+                }
+                let [b0, b1, b2, b3, ..] = buf;
+                Ok(match ty {
+                    Scalar::I8 => i8::from_le_bytes([b0]) as f64,
+                    Scalar::U8 => b0 as f64,
+                    Scalar::I16 => i16::from_le_bytes([b0, b1]) as f64,
+                    Scalar::U16 => u16::from_le_bytes([b0, b1]) as f64,
+                    Scalar::I32 => i32::from_le_bytes([b0, b1, b2, b3]) as f64,
+                    Scalar::U32 => u32::from_le_bytes([b0, b1, b2, b3]) as f64,
+                    Scalar::F32 => f32::from_le_bytes([b0, b1, b2, b3]) as f64,
+                    Scalar::F64 => f64::from_le_bytes(buf),
+                })
+            }
+        }
+    }
+
+    // 读取一个元素的全部属性，列表属性读入 lists
+    fn read_element(
+        &mut self,
+        element: &Element,
+        scalars: &mut Vec<f64>,
+        lists: &mut Vec<Vec<f64>>,
+    ) -> io::Result<()> {
+        scalars.clear();
+        lists.clear();
+        for property in &element.properties {
+            match property {
+                Property::Scalar(ty, _) => scalars.push(self.read(*ty)?),
+                Property::List(count_ty, ty, _) => {
+                    let count = self.read(*count_ty)? as usize;
+                    // 逐个读取，个数损坏时不会预先分配过大的内存
+                    let mut list = Vec::new();
+                    for _ in 0..count {
+                        list.push(self.read(*ty)?);
+                    }
+                    lists.push(list);
+                    scalars.push(f64::NAN);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl PlyData {
+    fn parse(source: &[u8]) -> io::Result<PlyData> {
+        let (format, elements, body) = parse_header(source)?;
+        let mut body = match format {
+            Format::Ascii => Body::Ascii(
+                str::from_utf8(body)
+                    .map_err(|_| invalid_data("The ASCII body is not valid UTF-8"))?
+                    .split_ascii_whitespace(),
+            ),
+            Format::BinaryLittleEndian | Format::BinaryBigEndian => Body::Binary {
+                bytes: body,
+                big_endian: format == Format::BinaryBigEndian,
+            },
+        };
+
+        let mut data = PlyData::default();
+        let mut scalars = Vec::new();
+        let mut lists = Vec::new();
+        for element in &elements {
+            match element.name.as_str() {
+                "vertex" => {
+                    let position = ["x", "y", "z"].map(|name| element.find(&[name]));
+                    let normal = ["nx", "ny", "nz"].map(|name| element.find(&[name]));
+                    let texcoord = [
+                        &["u", "s", "texture_u", "texture_s"][..],
+                        &["v", "t", "texture_v", "texture_t"][..],
+                    ]
+                    .map(|names| element.find(names));
+                    let color = ["red", "green", "blue"].map(|name| element.find(&[name]));
+
+                    let [Some(x), Some(y), Some(z)] = position else {
+                        return Err(invalid_data("Vertices have no positions"));
+                    };
+                    for _ in 0..element.count {
+                        body.read_element(element, &mut scalars, &mut lists)?;
+                        data.positions
+                            .push(Point3::new(scalars[x.0], scalars[y.0], scalars[z.0]));
+                        if let [Some(x), Some(y), Some(z)] = normal {
+                            data.normals
+                                .push(Vec3::new(scalars[x.0], scalars[y.0], scalars[z.0]));
+                        }
+                        if let [Some(u), Some(v)] = texcoord {
+                            data.texcoords.push([scalars[u.0], scalars[v.0]]);
+                        }
+                        if let [Some(r), Some(g), Some(b)] = color {
+                            // 颜色以 sRGB 存储，与图像纹理一样转换到线性空间
+                            let srgb = Srgb::new(
+                                scalars[r.0] * r.1.color_scale(),
+                                scalars[g.0] * g.1.color_scale(),
+                                scalars[b.0] * b.1.color_scale(),
+                            );
+                            let (r, g, b) = srgb.into_linear().into_components();
+                            data.colors.push(Color::new(r, g, b));
+                        }
+                    }
+                }
+                "face" => {
+                    let list = element
+                        .properties
+                        .iter()
+                        .filter(|property| matches!(property, Property::List(..)))
+                        .position(|property| {
+                            matches!(property.name(), "vertex_indices" | "vertex_index")
+                        })
+                        .ok_or_else(|| invalid_data("Faces have no vertex indices"))?;
+                    for _ in 0..element.count {
+                        body.read_element(element, &mut scalars, &mut lists)?;
+                        let face = &lists[list];
+                        // 负的索引转换后超出范围，在最后统一检查
+                        for k in 1..face.len().saturating_sub(1) {
+                            data.indices
+                                .push([face[0], face[k], face[k + 1]].map(|i| i as i64 as u32));
+                        }
+                    }
+                }
+                _ => {
+                    for _ in 0..element.count {
+                        body.read_element(element, &mut scalars, &mut lists)?;
+                    }
+                }
+            }
+        }
+
+        if data
+            .indices
+            .iter()
+            .flatten()
+            .any(|&i| i as usize >= data.positions.len())
+        {
+            return Err(invalid_data("Vertex index out of range"));
+        }
+        Ok(data)
+    }
+}
+
+// 返回格式、元素的定义与文件头之后的数据
+fn parse_header(source: &[u8]) -> io::Result<(Format, Vec<Element>, &[u8])> {
+    let mut rest = source;
+    let mut next_line = || -> io::Result<&str> {
+        let end = rest
+            .iter()
+            .position(|&b| b == b'\n')
+            .ok_or_else(|| invalid_data("Unterminated PLY header"))?;
+        let line = str::from_utf8(&rest[..end]).map_err(|_| invalid_data("Invalid PLY header"))?;
+        rest = &rest[end + 1..];
+        Ok(line.trim_end_matches('\r'))
+    };
+
+    if next_line()? != "ply" {
+        return Err(invalid_data("Not a PLY file"));
+    }
+
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    loop {
+        let line = next_line()?;
+        let words: Vec<&str> = line.split_ascii_whitespace().collect();
+        match words[..] {
+            ["end_header"] => break,
+            ["comment", ..] | ["obj_info", ..] | [] => {}
+            ["format", name, "1.0"] => {
+                format = Some(match name {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::BinaryLittleEndian,
+                    "binary_big_endian" => Format::BinaryBigEndian,
+                    _ => return Err(invalid_data(format!("Unknown format {name}"))),
+                });
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_owned(),
+                count: count
+                    .parse()
+                    .map_err(|_| invalid_data(format!("Invalid element count {count}")))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count_ty, ty, name] => elements
+                .last_mut()
+                .ok_or_else(|| invalid_data("Property outside of an element"))?
+                .properties
+                .push(Property::List(
+                    Scalar::parse(count_ty)?,
+                    Scalar::parse(ty)?,
+                    name.to_owned(),
+                )),
+            ["property", ty, name] => elements
+                .last_mut()
+                .ok_or_else(|| invalid_data("Property outside of an element"))?
+                .properties
+                .push(Property::Scalar(Scalar::parse(ty)?, name.to_owned())),
+            _ => return Err(invalid_data(format!("Invalid header line {line}"))),
+        }
+    }
+
+    let format = format.ok_or_else(|| invalid_data("Missing PLY format"))?;
+    Ok((format, elements, rest))
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+    use crate::{
+        material::{Lambertian, Material, ScatterRecord, disney::Disney},
+        texture::{CheckerTexture, Texture, VertexColor},
+    };
+
+    const HEADER: &str = "element vertex 5
+property float x
+property float y
+property float z
+property float nx
+property float ny
+property float nz
+property float s
+property float t
+property uchar red
+property uchar green
+property uchar blue
+element face 2
+property uchar flags
+property list uchar int vertex_indices
+element edge 1
+property int vertex1
+property int vertex2
+end_header
+";
+
+    // 一个四边形与一个三角形，四边形的两个三角形共享第一个顶点
+    const VERTICES: [[f64; 11]; 5] = [
+        [0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 255.0, 0.0, 0.0],
+        [1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 255.0, 0.0],
+        [1.0, 1.0, 0.0, 0.0, 0.0, 1.0, 1.0, 1.0, 0.0, 0.0, 255.0],
+        [0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 1.0, 255.0, 255.0, 255.0],
+        [0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.5, 0.5, 0.0, 0.0, 0.0],
+    ];
+    const FACES: [&[i32]; 2] = [&[0, 1, 2, 3], &[0, 4, 1]];
+
+    fn ascii() -> Vec<u8> {
+        let mut ply = format!("ply\r\nformat ascii 1.0\r\ncomment 测试\r\n{HEADER}");
+        for v in VERTICES {
+            let line: Vec<String> = v.iter().map(f64::to_string).collect();
+            ply += &(line.join(" ") + "\n");
+        }
+        for face in FACES {
+            let line: Vec<String> = face.iter().map(i32::to_string).collect();
+            ply += &format!("0 {} {}\n", face.len(), line.join(" "));
+        }
+        ply += "0 1\n";
+        ply.into_bytes()
+    }
+
+    fn binary(big_endian: bool) -> Vec<u8> {
+        let format = if big_endian { "big" } else { "little" };
+        let mut ply = format!("ply\nformat binary_{format}_endian 1.0\n{HEADER}").into_bytes();
+        let mut push = |bytes: &[u8]| {
+            let mut bytes = bytes.to_vec();
+            if big_endian {
+                bytes.reverse();
+            }
+            ply.extend(bytes);
+        };
+        for v in VERTICES {
+            for x in &v[..8] {
+                push(&(*x as f32).to_le_bytes());
+            }
+            for x in &v[8..] {
+                push(&[*x as u8]);
+            }
+        }
+        for face in FACES {
+            push(&[0]);
+            push(&[face.len() as u8]);
+            for i in face {
+                push(&i.to_le_bytes());
+            }
+        }
+        push(&0i32.to_le_bytes());
+        push(&1i32.to_le_bytes());
+        ply
+    }
+
+    #[test]
+    fn test_formats() {
+        let data = PlyData::parse(&ascii()).unwrap();
+        assert_eq!(data.positions.len(), 5);
+        assert_eq!(data.normals.len(), 5);
+        assert_eq!(data.texcoords[4], [0.5, 0.5]);
+        assert_eq!(data.indices, vec![[0, 1, 2], [0, 2, 3], [0, 4, 1]]);
+        assert_eq!(data.colors[0].e(), [1.0, 0.0, 0.0]);
+        assert_eq!(data.colors[3].e(), [1.0, 1.0, 1.0]);
+
+        assert_eq!(PlyData::parse(&binary(false)).unwrap(), data);
+        assert_eq!(PlyData::parse(&binary(true)).unwrap(), data);
+
+        let truncated = binary(false);
+        assert!(PlyData::parse(&truncated[..truncated.len() - 1]).is_err());
+        let out_of_range = String::from_utf8(ascii())
+            .unwrap()
+            .replace("0 4 1", "0 5 1");
+        assert!(PlyData::parse(out_of_range.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_vertex_color() {
+        let path = env::temp_dir().join(format!("rtw_ply_{}.ply", std::process::id()));
+        fs::write(&path, binary(true)).unwrap();
+        let texture = Arc::new(VertexColor::default());
+        let ply = Ply::from_path(&path, Arc::new(Lambertian::new(texture.clone()))).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        // 重心坐标为 (0.5, 0.25, 0.25)，顶点依次为红、绿、蓝
+        let r = Ray::new(Point3::new(0.5, 0.25, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let rec = ply
+            .hit(&r, &Interval::from_range(0.001..f64::INFINITY))
+            .unwrap();
+        let color = texture.value_at(&rec);
+        assert!((color - Color::new(0.5, 0.25, 0.25)).length() < 1e-6);
+        assert_eq!(rec.color, Some(color));
+
+        // 没有顶点颜色的物体使用 fallback
+        let rec = HitRecord { color: None, ..rec };
+        assert_eq!(texture.value_at(&rec).e(), [1.0, 1.0, 1.0]);
+
+        // 作为光源采样时，采样的方向都指向网格
+        let origin = Point3::new(0.5, 0.25, 1.0);
+        for _ in 0..20 {
+            let direction = ply.random(&origin, 0.0);
+            assert!(ply.pdf_value(&origin, direction.as_inner(), 0.0) > 0.0);
+        }
+        assert_eq!(ply.pdf_value(&origin, &Vec3::new(0.0, 0.0, 1.0), 0.0), 0.0);
+    }
+
+    #[test]
+    fn test_vertex_color_in_wrappers() {
+        let path = env::temp_dir().join(format!("rtw_ply_wrap_{}.ply", std::process::id()));
+        fs::write(&path, binary(false)).unwrap();
+        let ply = Ply::from_path(
+            &path,
+            Arc::new(Lambertian::new(Arc::new(VertexColor::default()))),
+        )
+        .unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let r = Ray::new(Point3::new(0.5, 0.25, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let rec = ply
+            .hit(&r, &Interval::from_range(0.001..f64::INFINITY))
+            .unwrap();
+        let color = rec.color.unwrap();
+
+        // 棋盘格纹理将击中记录转发给内部的纹理
+        let checker = CheckerTexture::new(
+            1.0,
+            Arc::new(VertexColor::default()),
+            Arc::new(VertexColor::default()),
+        );
+        assert_eq!(checker.value_at(&rec), color);
+
+        // Disney 的基础颜色纹理读取顶点颜色，与直接设置该颜色的结果相同
+        let textured = Disney::builder()
+            .base_color_texture(Arc::new(VertexColor::default()))
+            .build();
+        let plain = Disney::builder().base_color(color).build();
+        let direction = Vec3::new(0.3, 0.2, 1.0);
+        let value = |mat: &dyn Material| match mat.scatter(&r, &rec) {
+            Some(ScatterRecord::PDF(pdf)) => pdf.value(&direction),
+            _ => panic!("Expected a pdf"),
+        };
+        let (expected, expected_pdf) = value(&plain);
+        let (attenuation, pdf) = value(&textured);
+        assert!((attenuation - expected).length() < 1e-12);
+        assert_eq!(pdf, expected_pdf);
+        assert!(attenuation.x() > attenuation.y());
+    }
+}
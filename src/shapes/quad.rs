@@ -2,10 +2,11 @@ use std::sync::Arc;
 
 use crate::{
     aabb::AABB,
-    hit::{HitRecord, Hittable},
+    hit::{HitRecord, Hittable, next_object_id},
     hits::Hittables,
     material::Material,
     shapes::Planar,
+    stats::{self, Counter},
     utils::{
         interval::Interval,
         random::Random,
@@ -25,6 +26,8 @@ pub struct Quad {
     normal: UnitVec3,
     parm_d: f64,
     area: f64,
+    // 物体编号，用于描边检测物体边界，盒子的六个面共用一个编号
+    id: u32,
 }
 
 impl Quad {
@@ -44,6 +47,7 @@ impl Quad {
             normal,
             parm_d,
             area,
+            id: next_object_id(),
         }
     }
 }
@@ -73,6 +77,7 @@ impl Hittable for Quad {
         r: &crate::utils::ray::Ray,
         interval: &crate::utils::interval::Interval,
     ) -> Option<crate::hit::HitRecord> {
+        stats::count(Counter::PrimitiveTests);
         let denom = self.normal.dot(r.direction());
         if denom.abs() < 1e-8 {
             return None;
@@ -90,22 +95,18 @@ impl Hittable for Quad {
 
         let (u, v) = Quad::is_interior(alpha, beta)?;
 
-        Some(HitRecord::new(
-            intersection,
-            self.normal,
-            self.mat.as_ref(),
-            t,
-            u,
-            v,
-            r,
-        ))
+        Some(
+            HitRecord::new(intersection, self.normal, self.mat.as_ref(), t, u, v, r)
+                .with_tangents(self.u, self.v)
+                .with_object_id(self.id),
+        )
     }
 
     fn bounding_box(&self) -> &AABB {
         &self.bbox
     }
 
-    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3, _time: f64) -> f64 {
         let Some(rec) = self.hit(
             &Ray::new(*origin, *direction),
             &Interval::new(1e-8, f64::INFINITY),
@@ -119,7 +120,7 @@ impl Hittable for Quad {
         distance_squared / (cosine * self.area)
     }
 
-    fn random(&self, origin: &Point3) -> UnitVec3 {
+    fn random(&self, origin: &Point3, _time: f64) -> UnitVec3 {
         let p = self.anchor + (Random::f64() * self.u) + (Random::f64() * self.v);
         UnitVec3::from_vec3(p - origin).unwrap()
     }
@@ -127,6 +128,7 @@ impl Hittable for Quad {
 
 pub fn build_box(a: Point3, b: Point3, mat: Arc<dyn Material>) -> Hittables {
     let mut sides = Hittables::default();
+    let id = next_object_id();
 
     let min = Point3::from(
         Iterator::zip(a.e().iter(), b.e().iter())
@@ -148,42 +150,67 @@ pub fn build_box(a: Point3, b: Point3, mat: Arc<dyn Material>) -> Hittables {
     let dy = Vec3::new(0.0, max.y() - min.y(), 0.0);
     let dz = Vec3::new(0.0, 0.0, max.z() - min.z());
 
-    sides.add(Box::new(Quad::new(
-        Point3::new(min.x(), min.y(), max.z()),
-        dx,
-        dy,
-        mat.clone(),
-    )));
-    sides.add(Box::new(Quad::new(
-        Point3::new(max.x(), min.y(), max.z()),
-        -dz,
-        dy,
-        mat.clone(),
-    )));
-    sides.add(Box::new(Quad::new(
-        Point3::new(max.x(), min.y(), min.z()),
-        -dx,
-        dy,
-        mat.clone(),
-    )));
-    sides.add(Box::new(Quad::new(
-        Point3::new(min.x(), min.y(), min.z()),
-        dz,
-        dy,
-        mat.clone(),
-    )));
-    sides.add(Box::new(Quad::new(
-        Point3::new(min.x(), max.y(), max.z()),
-        dx,
-        -dz,
-        mat.clone(),
-    )));
-    sides.add(Box::new(Quad::new(
-        Point3::new(min.x(), min.y(), min.z()),
-        dx,
-        dz,
-        mat,
-    )));
+    sides.add(Box::new(Quad {
+        id,
+        ..Quad::new(Point3::new(min.x(), min.y(), max.z()), dx, dy, mat.clone())
+    }));
+    sides.add(Box::new(Quad {
+        id,
+        ..Quad::new(Point3::new(max.x(), min.y(), max.z()), -dz, dy, mat.clone())
+    }));
+    sides.add(Box::new(Quad {
+        id,
+        ..Quad::new(Point3::new(max.x(), min.y(), min.z()), -dx, dy, mat.clone())
+    }));
+    sides.add(Box::new(Quad {
+        id,
+        ..Quad::new(Point3::new(min.x(), min.y(), min.z()), dz, dy, mat.clone())
+    }));
+    sides.add(Box::new(Quad {
+        id,
+        ..Quad::new(Point3::new(min.x(), max.y(), max.z()), dx, -dz, mat.clone())
+    }));
+    sides.add(Box::new(Quad {
+        id,
+        ..Quad::new(Point3::new(min.x(), min.y(), min.z()), dx, dz, mat)
+    }));
 
     sides
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{material::EmptyMaterial, shapes::sphere::Sphere};
+
+    #[test]
+    fn test_object_ids() {
+        let mat: Arc<dyn Material> = Arc::new(EmptyMaterial);
+        let interval = Interval::new(1e-8, f64::INFINITY);
+        let hit_id = |object: &dyn Hittable, origin: Point3| {
+            let r = Ray::new(origin, Point3::new(0.5, 0.5, 0.5) - origin);
+            object.hit(&r, &interval).unwrap().object_id
+        };
+
+        // 盒子的各个面属于同一个物体
+        let cube = build_box(Point3::ZERO, Point3::new(1.0, 1.0, 1.0), mat.clone());
+        let front = hit_id(&cube, Point3::new(0.5, 0.5, 5.0));
+        let top = hit_id(&cube, Point3::new(0.5, 5.0, 0.5));
+        assert_ne!(front, 0);
+        assert_eq!(front, top);
+
+        let quad = Quad::new(
+            Point3::new(0.0, 0.0, 2.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            mat.clone(),
+        );
+        let sphere = Sphere::new(Point3::new(0.5, 0.5, 0.5), 0.5, mat);
+        let ids = [
+            front,
+            hit_id(&quad, Point3::new(0.5, 0.5, 5.0)),
+            hit_id(&sphere, Point3::new(0.5, 0.5, 5.0)),
+        ];
+        assert!(ids[0] != ids[1] && ids[1] != ids[2] && ids[0] != ids[2]);
+    }
+}
@@ -2,8 +2,9 @@ use std::{f64::consts::PI, sync::Arc};
 
 use crate::{
     aabb::AABB,
-    hit::{HitRecord, Hittable},
+    hit::{HitRecord, Hittable, next_object_id},
     material::Material,
+    stats::{self, Counter},
     utils::{
         interval::Interval,
         onb::OrthonormalBasis,
@@ -19,6 +20,8 @@ pub struct Sphere {
     radius: f64,
     mat: Arc<dyn Material>,
     bbox: AABB,
+    // 物体编号，用于描边检测物体边界
+    id: u32,
 }
 
 impl Sphere {
@@ -29,6 +32,7 @@ impl Sphere {
             radius: f64::max(0.0, radius),
             mat,
             bbox: AABB::from_points(static_center - rvec, static_center + rvec),
+            id: next_object_id(),
         }
     }
 
@@ -47,6 +51,7 @@ impl Sphere {
             radius: f64::max(0.0, radius),
             mat,
             bbox: AABB::union(box1, box2),
+            id: next_object_id(),
         }
     }
 
@@ -60,6 +65,16 @@ impl Sphere {
         (u, v)
     }
 
+    // 对 get_sphere_uv 的参数化求偏导，phi 取自 u 以免在两极除以零
+    fn get_sphere_tangents(radius: f64, p: UnitVec3, u: f64) -> (Vec3, Vec3) {
+        let phi = 2.0 * PI * u;
+        let sin_theta = (1.0 - p.y() * p.y()).max(0.0).sqrt();
+
+        let dpdu = 2.0 * PI * radius * Vec3::new(p.z(), 0.0, -p.x());
+        let dpdv = PI * radius * Vec3::new(p.y() * phi.cos(), sin_theta, -p.y() * phi.sin());
+        (dpdu, dpdv)
+    }
+
     fn random_to_sphere(radius: f64, distance_squared: f64) -> Vec3 {
         let r1 = Random::f64();
         let r2 = Random::f64();
@@ -79,6 +94,7 @@ impl Hittable for Sphere {
         r: &crate::utils::ray::Ray,
         interval: &Interval,
     ) -> Option<crate::hit::HitRecord> {
+        stats::count(Counter::PrimitiveTests);
         let current_center = self.center.at(*r.time());
         let oc = current_center - r.origin();
         let a = r.direction().length_squared();
@@ -103,7 +119,10 @@ impl Hittable for Sphere {
         let p = r.at(root);
         let outward_normal = UnitVec3::from_vec3_raw((p - current_center) / self.radius);
         let (u, v) = Sphere::get_sphere_uv(outward_normal);
-        let hr = HitRecord::new(p, outward_normal, self.mat.as_ref(), root, u, v, r);
+        let (dpdu, dpdv) = Sphere::get_sphere_tangents(self.radius, outward_normal, u);
+        let hr = HitRecord::new(p, outward_normal, self.mat.as_ref(), root, u, v, r)
+            .with_tangents(dpdu, dpdv)
+            .with_object_id(self.id);
         Some(hr)
     }
 
@@ -111,17 +130,16 @@ impl Hittable for Sphere {
         &self.bbox
     }
 
-    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
-        // 只适用于静态球
-
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3, time: f64) -> f64 {
+        // 运动的球使用 time 时刻的球心
         let Some(_) = self.hit(
-            &Ray::new(*origin, *direction),
+            &Ray::new_with_time(*origin, *direction, time),
             &Interval::new(1e-8, f64::INFINITY),
         ) else {
             return 0.0;
         };
 
-        let dist_squared = (self.center.at(0.0) - origin).length_squared();
+        let dist_squared = (self.center.at(time) - origin).length_squared();
         let cos_theta_max = (1.0 - self.radius * self.radius / dist_squared).sqrt();
         if cos_theta_max.is_nan() {
             // 此时是从内部入射到球面上
@@ -132,8 +150,8 @@ impl Hittable for Sphere {
         1.0 / solid_angle
     }
 
-    fn random(&self, origin: &Point3) -> UnitVec3 {
-        let direction = self.center.at(0.0) - origin;
+    fn random(&self, origin: &Point3, time: f64) -> UnitVec3 {
+        let direction = self.center.at(time) - origin;
         let distance_squared = direction.length_squared();
         let uvw = OrthonormalBasis::new(
             &UnitVec3::from_vec3(direction).expect("The direction should be normalizable!"),
@@ -168,4 +186,33 @@ mod tests {
         let (u, v) = Sphere::get_sphere_uv(UnitVec3::from_vec3_raw(Vec3::new(0.0, 0.0, -1.0)));
         assert_eq!((u, v), (0.75, 0.5));
     }
+
+    #[test]
+    fn test_sphere_tangents() {
+        let radius = 2.0;
+        let point = |u: f64, v: f64| {
+            let (phi, theta) = (2.0 * PI * u, PI * v);
+            radius
+                * Vec3::new(
+                    -theta.sin() * phi.cos(),
+                    -theta.cos(),
+                    theta.sin() * phi.sin(),
+                )
+        };
+
+        for (u, v) in [(0.1, 0.3), (0.6, 0.8), (0.9, 0.45)] {
+            let p = UnitVec3::from_vec3(point(u, v)).unwrap();
+            assert!((Sphere::get_sphere_uv(p).0 - u).abs() < 1e-9);
+            assert!((Sphere::get_sphere_uv(p).1 - v).abs() < 1e-9);
+
+            // 与有限差分比较，并且都位于切平面内
+            let (dpdu, dpdv) = Sphere::get_sphere_tangents(radius, p, u);
+            let h = 1e-6;
+            let du = (point(u + h, v) - point(u - h, v)) / (2.0 * h);
+            let dv = (point(u, v + h) - point(u, v - h)) / (2.0 * h);
+            assert!((dpdu - du).length() < 1e-5);
+            assert!((dpdv - dv).length() < 1e-5);
+            assert!(dpdu.dot(&p).abs() < 1e-9 && dpdv.dot(&p).abs() < 1e-9);
+        }
+    }
 }
@@ -4,7 +4,7 @@ use crate::{
     aabb::AABB,
     hit::{HitRecord, Hittable},
     material::Material,
-    shapes::Planar,
+    stats::{self, Counter},
     utils::{
         interval::Interval,
         random::Random,
@@ -14,94 +14,150 @@ use crate::{
 };
 
 pub struct Triangle {
-    anchor: Point3,
-    u: Vec3,
-    v: Vec3,
-    w: Vec3,
+    // 求交使用的三个顶点，相邻三角形共享完全相同的顶点坐标时求交无缝
+    vertices: [Point3; 3],
+    // 可选的顶点纹理坐标与法线，缺省时以重心坐标为纹理坐标、以几何法线着色
+    texcoords: Option<[[f64; 2]; 3]>,
+    normals: Option<[Vec3; 3]>,
     mat: Arc<dyn Material>,
     bbox: AABB,
     normal: UnitVec3,
-    parm_d: f64,
     area: f64,
 }
 
 impl Triangle {
     pub fn new(anchor: Point3, u: Vec3, v: Vec3, mat: Arc<dyn Material>) -> Option<Triangle> {
+        Triangle::from_vertices([anchor, anchor + u, anchor + v], mat)
+    }
+
+    // 由顶点创建，anchor + u 的舍入误差不会使顶点偏离与相邻三角形共享的坐标
+    pub fn from_vertices(vertices: [Point3; 3], mat: Arc<dyn Material>) -> Option<Triangle> {
+        let (u, v) = edges(vertices);
         let n = Vec3::cross(&u, &v);
         let normal = UnitVec3::from_vec3(n)?;
-        let parm_d = normal.dot(&anchor);
-        let w = n / n.length_squared();
         let area = n.length() / 2.0;
         Some(Triangle {
-            anchor,
-            u,
-            v,
-            w,
+            vertices,
+            texcoords: None,
+            normals: None,
             mat,
-            bbox: Triangle::cal_bounding_box(&anchor, &u, &v),
+            bbox: AABB::union(
+                AABB::from_points(vertices[0], vertices[1]),
+                AABB::from_points(vertices[0], vertices[2]),
+            ),
             normal,
-            parm_d,
             area,
         })
     }
+
+    pub fn with_texcoords(mut self, texcoords: [[f64; 2]; 3]) -> Triangle {
+        self.texcoords = Some(texcoords);
+        self
+    }
+
+    pub fn with_normals(mut self, normals: [Vec3; 3]) -> Triangle {
+        self.normals = Some(normals);
+        self
+    }
 }
 
-impl Planar for Triangle {
-    fn cal_bounding_box(anchor: &Point3, u: &Vec3, v: &Vec3) -> AABB {
-        let bbox1 = AABB::from_points(*anchor, anchor + u);
-        let bbox2 = AABB::from_points(*anchor, anchor + v);
+// 以第一个顶点为起点的两条边
+fn edges([p0, p1, p2]: [Point3; 3]) -> (Vec3, Vec3) {
+    (p1 - p0, p2 - p0)
+}
+
+// 由顶点位置与纹理坐标求 dp/du 与 dp/dv，纹理坐标退化时返回 None
+pub fn tangents(p: [Point3; 3], uv: [[f64; 2]; 3]) -> Option<(Vec3, Vec3)> {
+    let duv02 = [uv[0][0] - uv[2][0], uv[0][1] - uv[2][1]];
+    let duv12 = [uv[1][0] - uv[2][0], uv[1][1] - uv[2][1]];
+    let dp02 = p[0] - p[2];
+    let dp12 = p[1] - p[2];
 
-        AABB::union(bbox1, bbox2)
+    let det = duv02[0] * duv12[1] - duv02[1] * duv12[0];
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let dpdu = (duv12[1] * dp02 - duv02[1] * dp12) / det;
+    let dpdv = (duv02[0] * dp12 - duv12[0] * dp02) / det;
+    if Vec3::cross(&dpdu, &dpdv).length_squared() == 0.0 {
+        return None;
     }
+    Some((dpdu, dpdv))
+}
 
-    fn is_interior(a: f64, b: f64) -> Option<(f64, f64)> {
-        const UNIT_INTERVAL: Interval = Interval::new(0.0, 1.0);
+// Woop 等人的无缝求交算法：平移、错切到以光线为 z 轴的空间后，用三条边函数的符号判断是否击中
+// 边函数只取决于边的两个端点，相邻三角形在共享边与顶点上的判断一致，光线不会从缝隙漏过
+// 返回距离与三个顶点的重心坐标
+pub fn intersect(p: [Point3; 3], r: &Ray, interval: &Interval) -> Option<(f64, [f64; 3])> {
+    let d = r.direction();
+
+    // 以方向分量绝对值最大的轴为 z 轴，交换 x 与 y 以保持三角形的绕向
+    let kz = (0..3)
+        .max_by(|&i, &j| f64::total_cmp(&d[i].abs(), &d[j].abs()))
+        .unwrap();
+    let (mut kx, mut ky) = ((kz + 1) % 3, (kz + 2) % 3);
+    if d[kz] < 0.0 {
+        (kx, ky) = (ky, kx);
+    }
+    let sx = d[kx] / d[kz];
+    let sy = d[ky] / d[kz];
+    let sz = 1.0 / d[kz];
+
+    let [a, b, c] = p.map(|p| p - r.origin());
+    let (ax, ay) = (a[kx] - sx * a[kz], a[ky] - sy * a[kz]);
+    let (bx, by) = (b[kx] - sx * b[kz], b[ky] - sy * b[kz]);
+    let (cx, cy) = (c[kx] - sx * c[kz], c[ky] - sy * c[kz]);
+
+    let e0 = cx * by - cy * bx;
+    let e1 = ax * cy - ay * cx;
+    let e2 = bx * ay - by * ax;
+    if (e0 < 0.0 || e1 < 0.0 || e2 < 0.0) && (e0 > 0.0 || e1 > 0.0 || e2 > 0.0) {
+        return None;
+    }
+    let det = e0 + e1 + e2;
+    if det == 0.0 {
+        return None;
+    }
 
-        if UNIT_INTERVAL.contains(a) && UNIT_INTERVAL.contains(b) && UNIT_INTERVAL.contains(a + b) {
-            Some((a, b))
-        } else {
-            None
-        }
+    let t = (e0 * a[kz] + e1 * b[kz] + e2 * c[kz]) * sz / det;
+    if !interval.contains(t) {
+        return None;
     }
+    Some((t, [e0 / det, e1 / det, e2 / det]))
 }
 
 impl Hittable for Triangle {
     fn hit(&self, r: &Ray, interval: &Interval) -> Option<HitRecord> {
-        // 从 Quad 的 Hit 复制而来
-
-        let denom = self.normal.dot(r.direction());
-        if denom.abs() < 1e-8 {
-            return None;
-        }
-
-        let t = (self.parm_d - self.normal.dot(r.origin())) / denom;
-        if !interval.contains(t) {
-            return None;
-        }
+        stats::count(Counter::PrimitiveTests);
 
+        let (t, b) = intersect(self.vertices, r, interval)?;
         let intersection = r.at(t);
-        let hit_vector_from_anchor = intersection - self.anchor;
-        let alpha = Vec3::dot(&self.w, &Vec3::cross(&hit_vector_from_anchor, &self.v));
-        let beta = Vec3::dot(&self.w, &Vec3::cross(&self.u, &hit_vector_from_anchor));
-
-        let (u, v) = Triangle::is_interior(alpha, beta)?;
-
-        Some(HitRecord::new(
-            intersection,
-            self.normal,
-            self.mat.as_ref(),
-            t,
-            u,
-            v,
-            r,
-        ))
+
+        let (u, v, (dpdu, dpdv)) = match self.texcoords {
+            Some(uv) => (
+                b[0] * uv[0][0] + b[1] * uv[1][0] + b[2] * uv[2][0],
+                b[0] * uv[0][1] + b[1] * uv[1][1] + b[2] * uv[2][1],
+                tangents(self.vertices, uv).unwrap_or(edges(self.vertices)),
+            ),
+            None => (b[1], b[2], edges(self.vertices)),
+        };
+        let rec = HitRecord::new(intersection, self.normal, self.mat.as_ref(), t, u, v, r)
+            .with_tangents(dpdu, dpdv);
+
+        let shading_normal = self
+            .normals
+            .and_then(|n| UnitVec3::from_vec3(b[0] * n[0] + b[1] * n[1] + b[2] * n[2]));
+        Some(match shading_normal {
+            Some(normal) => rec.with_shading_normal(normal),
+            None => rec,
+        })
     }
 
     fn bounding_box(&self) -> &AABB {
         &self.bbox
     }
 
-    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3, _time: f64) -> f64 {
         let Some(rec) = self.hit(
             &Ray::new(*origin, *direction),
             &Interval::new(1e-8, f64::INFINITY),
@@ -110,12 +166,13 @@ impl Hittable for Triangle {
         };
 
         let distance_squared = rec.t * rec.t * direction.length_squared();
-        let cosine = (direction.dot(&rec.normal) / direction.length()).abs();
+        // 立体角换算取决于几何面，不受插值的着色法线影响
+        let cosine = (direction.dot(self.normal.as_inner()) / direction.length()).abs();
 
         distance_squared / (cosine * self.area)
     }
 
-    fn random(&self, origin: &Point3) -> UnitVec3 {
+    fn random(&self, origin: &Point3, _time: f64) -> UnitVec3 {
         let mut u_l = Random::f64();
         let mut v_l = Random::f64();
 
@@ -123,7 +180,8 @@ impl Hittable for Triangle {
             (u_l, v_l) = (1.0 - v_l, 1.0 - u_l);
         }
 
-        let p = self.anchor + (u_l * self.u) + (v_l * self.v);
+        let (u, v) = edges(self.vertices);
+        let p = self.vertices[0] + (u_l * u) + (v_l * v);
         UnitVec3::from_vec3(p - origin).unwrap()
     }
 }
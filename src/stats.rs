@@ -0,0 +1,336 @@
+use std::{
+    cell::RefCell,
+    fmt,
+    fs::File,
+    io::BufWriter,
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use image::{Rgb, RgbImage};
+use serde::Serialize;
+
+use crate::material::Material;
+
+// 渲染过程中统计的事件
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Counter {
+    CameraRays,
+    BounceRays,
+    // 对光源的重要性采样求 pdf 的次数，此时只与光源求交而不追踪阴影光线
+    LightPdfEvaluations,
+    BvhNodes,
+    PrimitiveTests,
+}
+
+impl Counter {
+    const COUNT: usize = 5;
+}
+
+// 每个线程先在本地累加，由调用者在每个像素结束时取出，避免在热点路径上使用原子操作
+#[derive(Debug, Clone, Default)]
+pub struct LocalStats {
+    counts: [u64; Counter::COUNT],
+    // 以 Material::id() 区分材质，场景中的材质通常很少，线性查找即可
+    scatters: Vec<(usize, &'static str, u64)>,
+}
+
+thread_local! {
+    static LOCAL: RefCell<LocalStats> = RefCell::new(LocalStats::default());
+}
+
+// 正在收集统计的渲染数量，为 0 时计数直接返回
+static COLLECTING: AtomicUsize = AtomicUsize::new(0);
+
+// 统计默认关闭，持有该值期间才会计数
+pub struct Collecting(());
+
+pub fn collect() -> Collecting {
+    COLLECTING.fetch_add(1, Ordering::Relaxed);
+    Collecting(())
+}
+
+impl Drop for Collecting {
+    fn drop(&mut self) {
+        COLLECTING.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[inline]
+pub fn enabled() -> bool {
+    COLLECTING.load(Ordering::Relaxed) > 0
+}
+
+pub fn count(counter: Counter) {
+    if enabled() {
+        LOCAL.with_borrow_mut(|local| local.counts[counter as usize] += 1);
+    }
+}
+
+// 记录一次材质的散射
+pub fn count_scatter(material: &dyn Material) {
+    if !enabled() {
+        return;
+    }
+    let id = material.id();
+    LOCAL.with_borrow_mut(|local| {
+        match local.scatters.iter_mut().find(|(other, _, _)| *other == id) {
+            Some((_, _, n)) => *n += 1,
+            None => local.scatters.push((id, material.name(), 1)),
+        }
+    });
+}
+
+// 取出并清空当前线程的计数
+pub fn take_local() -> LocalStats {
+    LOCAL.with_borrow_mut(std::mem::take)
+}
+
+impl LocalStats {
+    pub fn get(&self, counter: Counter) -> u64 {
+        self.counts[counter as usize]
+    }
+
+    // 一个像素的求交开销
+    pub fn cost(&self) -> u64 {
+        self.get(Counter::BvhNodes) + self.get(Counter::PrimitiveTests)
+    }
+}
+
+// 一次渲染的统计结果，由 rayon 的 fold/reduce 合并各线程的局部结果
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RenderStats {
+    pub camera_rays: u64,
+    pub bounce_rays: u64,
+    pub light_pdf_evaluations: u64,
+    pub bvh_nodes_visited: u64,
+    pub primitive_tests: u64,
+    // 每个材质实例一项，同一类型的不同材质分别统计
+    pub scatters_by_material: Vec<MaterialScatters>,
+    pub seconds: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MaterialScatters {
+    pub id: usize,
+    pub material: &'static str,
+    pub scatters: u64,
+}
+
+impl RenderStats {
+    pub fn add(&mut self, local: LocalStats) {
+        self.camera_rays += local.get(Counter::CameraRays);
+        self.bounce_rays += local.get(Counter::BounceRays);
+        self.light_pdf_evaluations += local.get(Counter::LightPdfEvaluations);
+        self.bvh_nodes_visited += local.get(Counter::BvhNodes);
+        self.primitive_tests += local.get(Counter::PrimitiveTests);
+        for (id, material, n) in local.scatters {
+            self.count_scatters(id, material, n);
+        }
+    }
+
+    fn count_scatters(&mut self, id: usize, material: &'static str, n: u64) {
+        match self.scatters_by_material.iter_mut().find(|s| s.id == id) {
+            Some(entry) => entry.scatters += n,
+            None => self.scatters_by_material.push(MaterialScatters {
+                id,
+                material,
+                scatters: n,
+            }),
+        }
+    }
+
+    pub fn merge(mut self, other: RenderStats) -> RenderStats {
+        self.camera_rays += other.camera_rays;
+        self.bounce_rays += other.bounce_rays;
+        self.light_pdf_evaluations += other.light_pdf_evaluations;
+        self.bvh_nodes_visited += other.bvh_nodes_visited;
+        self.primitive_tests += other.primitive_tests;
+        for entry in other.scatters_by_material {
+            self.count_scatters(entry.id, entry.material, entry.scatters);
+        }
+        self
+    }
+
+    // 每条相机光线的平均路径段数
+    pub fn average_path_length(&self) -> f64 {
+        if self.camera_rays == 0 {
+            0.0
+        } else {
+            (self.camera_rays + self.bounce_rays) as f64 / self.camera_rays as f64
+        }
+    }
+
+    pub fn save_json(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        #[derive(Serialize)]
+        struct Report<'a> {
+            #[serde(flatten)]
+            stats: &'a RenderStats,
+            average_path_length: f64,
+        }
+
+        let report = Report {
+            stats: self,
+            average_path_length: self.average_path_length(),
+        };
+        serde_json::to_writer_pretty(BufWriter::new(File::create(path)?), &report)?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for RenderStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let per_second = |n: u64| n as f64 / self.seconds.max(1e-9) / 1e6;
+        writeln!(
+            f,
+            "Rendered in {:.2}s: {} camera rays, {} bounce rays ({:.2} Mrays/s)",
+            self.seconds,
+            self.camera_rays,
+            self.bounce_rays,
+            per_second(self.camera_rays + self.bounce_rays)
+        )?;
+        write!(
+            f,
+            "  {} light pdf evaluations, {} BVH nodes visited, {} primitive tests, average path length {:.2}",
+            self.light_pdf_evaluations,
+            self.bvh_nodes_visited,
+            self.primitive_tests,
+            self.average_path_length()
+        )?;
+
+        let mut scatters: Vec<_> = self.scatters_by_material.iter().collect();
+        scatters.sort_by(|a, b| {
+            b.scatters
+                .cmp(&a.scatters)
+                .then(a.material.cmp(b.material))
+                .then(a.id.cmp(&b.id))
+        });
+        for entry in scatters {
+            write!(
+                f,
+                "\n  {} scatters on {} ({:#x})",
+                entry.scatters, entry.material, entry.id
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeatmapKind {
+    Time,
+    Cost,
+}
+
+// 每个像素的渲染时间与求交开销
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PixelCost {
+    pub time: Duration,
+    pub cost: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Heatmap {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<PixelCost>,
+}
+
+impl Heatmap {
+    // 以最大值归一化，从黑色经红、黄到白色
+    pub fn to_image(&self, kind: HeatmapKind) -> RgbImage {
+        let values: Vec<f64> = self
+            .pixels
+            .iter()
+            .map(|p| match kind {
+                HeatmapKind::Time => p.time.as_secs_f64(),
+                HeatmapKind::Cost => p.cost as f64,
+            })
+            .collect();
+        let max = values.iter().copied().fold(0.0, f64::max);
+
+        RgbImage::from_fn(self.width, self.height, |x, y| {
+            let v = values[(y * self.width + x) as usize];
+            let t = if max > 0.0 { v / max } else { 0.0 };
+            let channel = |start: f64| (((t - start) * 3.0).clamp(0.0, 1.0) * 255.0) as u8;
+            Rgb([channel(0.0), channel(1.0 / 3.0), channel(2.0 / 3.0)])
+        })
+    }
+
+    pub fn save(&self, path: &Path, kind: HeatmapKind) -> Result<(), Box<dyn std::error::Error>> {
+        self.to_image(kind).save(path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{material::Lambertian, texture::SolidColor, utils::color::Color};
+
+    #[test]
+    fn test_local_counters() {
+        let lambertian = Lambertian::new(Arc::new(SolidColor::new(Color::WHITE)));
+        let black = Lambertian::new(Arc::new(SolidColor::new(Color::BLACK)));
+        let collecting = collect();
+        take_local();
+        count(Counter::CameraRays);
+        count(Counter::BounceRays);
+        count(Counter::BounceRays);
+        count(Counter::BvhNodes);
+        count_scatter(&lambertian);
+        count_scatter(&lambertian);
+        // 同类型的另一个材质单独计数
+        count_scatter(&black);
+        drop(collecting);
+
+        let local = take_local();
+        assert_eq!(local.get(Counter::BounceRays), 2);
+        assert_eq!(local.cost(), 1);
+        assert_eq!(take_local().get(Counter::CameraRays), 0);
+
+        let mut stats = RenderStats::default();
+        stats.add(local.clone());
+        let stats = stats.merge({
+            let mut other = RenderStats::default();
+            other.add(local);
+            other
+        });
+        assert_eq!(stats.camera_rays, 2);
+        let scatters = |material: &Lambertian| {
+            (stats.scatters_by_material.iter())
+                .find(|s| s.id == material.id())
+                .map(|s| s.scatters)
+        };
+        assert_eq!(stats.scatters_by_material.len(), 2);
+        assert_eq!(scatters(&lambertian), Some(4));
+        assert_eq!(scatters(&black), Some(2));
+        assert_eq!(stats.average_path_length(), 3.0);
+    }
+
+    #[test]
+    fn test_heatmap() {
+        let heatmap = Heatmap {
+            width: 2,
+            height: 1,
+            pixels: vec![
+                PixelCost {
+                    time: Duration::ZERO,
+                    cost: 10,
+                },
+                PixelCost {
+                    time: Duration::from_millis(1),
+                    cost: 5,
+                },
+            ],
+        };
+        let img = heatmap.to_image(HeatmapKind::Time);
+        assert_eq!(img.get_pixel(0, 0).0, [0, 0, 0]);
+        assert_eq!(img.get_pixel(1, 0).0, [255, 255, 255]);
+        let img = heatmap.to_image(HeatmapKind::Cost);
+        assert_eq!(img.get_pixel(0, 0).0, [255, 255, 255]);
+    }
+}
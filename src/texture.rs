@@ -1,9 +1,17 @@
 use std::{fmt::Debug, sync::Arc};
 
-use crate::utils::{color::Color, image::Image, perlin::Perlin, vec3::Point3};
+use crate::{
+    hit::HitRecord,
+    utils::{color::Color, image::Image, perlin::Perlin, vec3::Point3},
+};
 
 pub trait Texture: Send + Sync + Debug {
     fn value(&self, u: f64, v: f64, p: &Point3) -> Color;
+
+    // 材质在击中点取值，需要击中记录中其他信息的纹理重写此方法
+    fn value_at(&self, rec: &HitRecord) -> Color {
+        self.value(rec.u, rec.v, &rec.p)
+    }
 }
 
 #[derive(Debug)]
@@ -56,19 +64,26 @@ impl CheckerTexture {
     }
 }
 
-impl Texture for CheckerTexture {
-    fn value(&self, u: f64, v: f64, p: &Point3) -> Color {
+impl CheckerTexture {
+    fn pick(&self, p: &Point3) -> &Arc<dyn Texture> {
         let x_int = f64::floor(self.inv_scale * p.x()) as i32;
         let y_int = f64::floor(self.inv_scale * p.y()) as i32;
         let z_int = f64::floor(self.inv_scale * p.z()) as i32;
 
         let is_even = (x_int + y_int + z_int) % 2 == 0;
 
-        if is_even {
-            self.even.value(u, v, p)
-        } else {
-            self.odd.value(u, v, p)
-        }
+        if is_even { &self.even } else { &self.odd }
+    }
+}
+
+impl Texture for CheckerTexture {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color {
+        self.pick(p).value(u, v, p)
+    }
+
+    // 转发击中记录，使内部的 VertexColor 等纹理可以读取其他信息
+    fn value_at(&self, rec: &HitRecord) -> Color {
+        self.pick(&rec.p).value_at(rec)
     }
 }
 
@@ -194,3 +209,34 @@ impl Texture for NoiseTexture {
             * (1.0 + f64::sin(self.scale * p.z() + 10.0 * self.noise.turb(p, 7)))
     }
 }
+
+// 网格的顶点颜色，击中没有顶点颜色的物体时使用 fallback
+#[derive(Debug)]
+pub struct VertexColor {
+    fallback: Arc<dyn Texture>,
+}
+
+impl VertexColor {
+    pub fn new(fallback: Arc<dyn Texture>) -> VertexColor {
+        VertexColor { fallback }
+    }
+}
+
+impl Default for VertexColor {
+    fn default() -> Self {
+        VertexColor::new(Arc::new(SolidColor::new(Color::WHITE)))
+    }
+}
+
+impl Texture for VertexColor {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color {
+        self.fallback.value(u, v, p)
+    }
+
+    fn value_at(&self, rec: &HitRecord) -> Color {
+        match rec.color {
+            Some(color) => color,
+            None => self.fallback.value_at(rec),
+        }
+    }
+}
@@ -1,16 +1,121 @@
 use palette::{LinSrgb, Srgb};
 
-use crate::utils::vec3::Vec3;
+use crate::utils::{lerp, vec3::Vec3};
 
 pub type Color = Vec3;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum ToonMap {
     None,
     ACES,
+    // white 为映射到纯白的亮度
+    Reinhard { white: f64 },
+    Hable,
+    AgX,
+    KhronosPBRNeutral,
+}
+
+// 曝光控制，得到的缩放系数在色调映射之前乘到线性颜色上
+#[derive(Debug, Clone, Copy)]
+pub enum Exposure {
+    // 以 EV 表示的曝光补偿，每增加 1 亮度翻倍
+    Manual {
+        ev: f64,
+    },
+    // 以相机参数计算的曝光，快门单位为秒
+    Physical {
+        iso: f64,
+        shutter_in_seconds: f64,
+        f_stop: f64,
+    },
+    // 根据图像的对数平均亮度自动曝光，key 为中灰对应的亮度
+    Auto {
+        key: f64,
+    },
+}
+
+impl Default for Exposure {
+    fn default() -> Self {
+        Exposure::Manual { ev: 0.0 }
+    }
+}
+
+impl Exposure {
+    pub fn scale(&self, pixels: &[Color]) -> f64 {
+        match *self {
+            Exposure::Manual { ev } => 2.0_f64.powf(ev),
+            Exposure::Physical {
+                iso,
+                shutter_in_seconds,
+                f_stop,
+            } => {
+                // 参考 Lagarde & de Rousiers, "Moving Frostbite to Physically Based Rendering"
+                let ev100 = (f_stop * f_stop / shutter_in_seconds * 100.0 / iso).log2();
+                1.0 / (1.2 * 2.0_f64.powf(ev100))
+            }
+            Exposure::Auto { key } => {
+                let average = Color::log_average_luminance(pixels);
+                if average > 0.0 { key / average } else { 1.0 }
+            }
+        }
+    }
 }
 
 impl Color {
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.x() + 0.7152 * self.y() + 0.0722 * self.z()
+    }
+
+    pub fn log_average_luminance(pixels: &[Color]) -> f64 {
+        const DELTA: f64 = 1e-4;
+
+        let valid = pixels.iter().filter(|c| c.luminance().is_finite());
+        let (sum, count) = valid.fold((0.0, 0usize), |(sum, count), c| {
+            (sum + (DELTA + c.luminance().max(0.0)).ln(), count + 1)
+        });
+
+        if count == 0 {
+            0.0
+        } else {
+            (sum / count as f64).exp()
+        }
+    }
+
+    // 白平衡的各通道增益，使色温为 kelvin 的光源呈现为白色（D65 保持不变）
+    pub fn white_balance_gains(kelvin: f64) -> Color {
+        let source = Color::blackbody_rgb(kelvin);
+        let target = Color::blackbody_rgb(6504.0);
+        let gains = target / source;
+        gains / gains.luminance()
+    }
+
+    // 普朗克轨迹上色温对应的线性 sRGB 颜色，使用 Kim et al. 的三次样条近似
+    fn blackbody_rgb(kelvin: f64) -> Color {
+        let t = kelvin.clamp(1667.0, 25000.0);
+        let (t2, t3) = (t * t, t * t * t);
+
+        let x = if t <= 4000.0 {
+            -0.2661239e9 / t3 - 0.2343589e6 / t2 + 0.8776956e3 / t + 0.179910
+        } else {
+            -3.0258469e9 / t3 + 2.1070379e6 / t2 + 0.2226347e3 / t + 0.240390
+        };
+        let (x2, x3) = (x * x, x * x * x);
+        let y = if t <= 2222.0 {
+            -1.1063814 * x3 - 1.34811020 * x2 + 2.18555832 * x - 0.20219683
+        } else if t <= 4000.0 {
+            -0.9549476 * x3 - 1.37418593 * x2 + 2.09137015 * x - 0.16748867
+        } else {
+            3.0817580 * x3 - 5.87338670 * x2 + 3.75112997 * x - 0.37001483
+        };
+
+        let xyz = Vec3::new(x / y, 1.0, (1.0 - x - y) / y);
+        Color::new(
+            3.2404542 * xyz.x() - 1.5371385 * xyz.y() - 0.4985314 * xyz.z(),
+            -0.9692660 * xyz.x() + 1.8760108 * xyz.y() + 0.0415560 * xyz.z(),
+            0.0556434 * xyz.x() - 0.2040259 * xyz.y() + 1.0572252 * xyz.z(),
+        )
+    }
+
     fn aces_tonemap(&self) -> Color {
         const A: f64 = 2.51;
         const B: Vec3 = Vec3::new(0.03, 0.03, 0.03);
@@ -24,13 +129,104 @@ impl Color {
         )
     }
 
-    pub fn to_rgb(&self, toon_map: &ToonMap) -> [u8; 3] {
-        assert!(!self.e().iter().any(|&x| x.is_nan()));
+    fn reinhard_tonemap(&self, white: f64) -> Color {
+        let l = self.luminance();
+        if l <= 0.0 {
+            return Color::BLACK;
+        }
+        let mapped = l * (1.0 + l / (white * white)) / (1.0 + l);
+        Vec3::clamp(self * (mapped / l), 0.0, 1.0)
+    }
+
+    fn hable_tonemap(&self) -> Color {
+        const EXPOSURE_BIAS: f64 = 2.0;
+        const WHITE: f64 = 11.2;
+
+        fn partial(x: f64) -> f64 {
+            const A: f64 = 0.15;
+            const B: f64 = 0.50;
+            const C: f64 = 0.10;
+            const D: f64 = 0.20;
+            const E: f64 = 0.02;
+            const F: f64 = 0.30;
+            ((x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F)) - E / F
+        }
+
+        let white_scale = 1.0 / partial(WHITE);
+        Color::from(self.e().map(|c| partial(c * EXPOSURE_BIAS) * white_scale)).clamp(0.0, 1.0)
+    }
+
+    // 参考 Benjamin Wrensch 的 AgX 最小实现
+    fn agx_tonemap(&self) -> Color {
+        const MIN_EV: f64 = -12.47393;
+        const MAX_EV: f64 = 4.026069;
 
-        let mapped_color = match toon_map {
+        let inset = Color::new(
+            0.842479062253094 * self.x()
+                + 0.0784335999999992 * self.y()
+                + 0.0792237451477643 * self.z(),
+            0.0423282422610123 * self.x()
+                + 0.878468636469772 * self.y()
+                + 0.0791661274605434 * self.z(),
+            0.0423756549057051 * self.x() + 0.0784336 * self.y() + 0.879142973793104 * self.z(),
+        );
+
+        let contrast = inset.e().map(|c| {
+            let x = ((c.max(1e-10).log2()).clamp(MIN_EV, MAX_EV) - MIN_EV) / (MAX_EV - MIN_EV);
+            let x2 = x * x;
+            let x4 = x2 * x2;
+            15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+                - 0.00232
+        });
+        let c = Color::from(contrast);
+
+        let outset = Color::new(
+            1.19687900512017 * c.x() - 0.0980208811401368 * c.y() - 0.0990297440797205 * c.z(),
+            -0.0528968517574562 * c.x() + 1.15190312990417 * c.y() - 0.0989611768448433 * c.z(),
+            -0.0529716355144438 * c.x() - 0.0980434501171241 * c.y() + 1.15107367264116 * c.z(),
+        );
+
+        Color::from(outset.e().map(|c| c.max(0.0).powf(2.2))).clamp(0.0, 1.0)
+    }
+
+    fn khronos_pbr_neutral_tonemap(&self) -> Color {
+        const START_COMPRESSION: f64 = 0.8 - 0.04;
+        const DESATURATION: f64 = 0.15;
+
+        let x = self.x().min(self.y()).min(self.z());
+        let offset = if x < 0.08 { x - 6.25 * x * x } else { 0.04 };
+        let color = self - Vec3::new(offset, offset, offset);
+
+        let peak = color.x().max(color.y()).max(color.z());
+        if peak < START_COMPRESSION {
+            return color.clamp(0.0, 1.0);
+        }
+
+        let d = 1.0 - START_COMPRESSION;
+        let new_peak = 1.0 - d * d / (peak + d - START_COMPRESSION);
+        let color = color * (new_peak / peak);
+
+        let g = 1.0 - 1.0 / (DESATURATION * (peak - new_peak) + 1.0);
+        lerp(color, Vec3::new(new_peak, new_peak, new_peak), g).clamp(0.0, 1.0)
+    }
+
+    pub fn tone_map(&self, toon_map: &ToonMap) -> Color {
+        match toon_map {
             ToonMap::None => *self,
             ToonMap::ACES => self.aces_tonemap(),
-        };
+            ToonMap::Reinhard { white } => self.reinhard_tonemap(*white),
+            ToonMap::Hable => self.hable_tonemap(),
+            ToonMap::AgX => self.agx_tonemap(),
+            ToonMap::KhronosPBRNeutral => self.khronos_pbr_neutral_tonemap(),
+        }
+    }
+
+    pub fn to_rgb(&self, toon_map: &ToonMap) -> [u8; 3] {
+        let mapped_color = self.tone_map(toon_map);
+        // NaN 按黑色输出，不让单个异常像素中断整张图像的保存
+        if mapped_color.e().iter().any(|x| x.is_nan()) {
+            return [0, 0, 0];
+        }
 
         Srgb::from_linear(LinSrgb::from(mapped_color.e())).into()
     }
@@ -40,3 +236,70 @@ impl Color {
     pub const BLUE: Color = Color::new(0.0, 0.0, 1.0);
     pub const RED: Color = Color::new(1.0, 0.0, 0.0);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tone_maps_stay_in_range() {
+        let maps = [
+            ToonMap::ACES,
+            ToonMap::Reinhard { white: 4.0 },
+            ToonMap::Hable,
+            ToonMap::AgX,
+            ToonMap::KhronosPBRNeutral,
+        ];
+        let colors = [
+            Color::BLACK,
+            Color::new(0.18, 0.18, 0.18),
+            Color::new(1.0, 0.5, 0.2),
+            Color::new(100.0, 20.0, 3.0),
+        ];
+
+        for map in &maps {
+            let mut prev = -1.0;
+            for color in &colors {
+                let mapped = color.tone_map(map);
+                assert!(mapped.e().iter().all(|c| (0.0..=1.0).contains(c)));
+                let l = mapped.luminance();
+                assert!(l >= prev - 1e-6, "{map:?} is not monotonic");
+                prev = l;
+            }
+        }
+    }
+
+    #[test]
+    fn test_nan_is_black() {
+        let color = Color::new(f64::NAN, 0.5, 0.5);
+        assert_eq!(color.to_rgb(&ToonMap::None), [0, 0, 0]);
+        assert_eq!(color.to_rgb(&ToonMap::ACES), [0, 0, 0]);
+    }
+
+    #[test]
+    fn test_reinhard_white_point() {
+        let mapped = Color::new(4.0, 4.0, 4.0).tone_map(&ToonMap::Reinhard { white: 4.0 });
+        assert!((mapped.x() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_exposure_scale() {
+        assert_eq!(Exposure::Manual { ev: 1.0 }.scale(&[]), 2.0);
+
+        let pixels = vec![Color::new(0.5, 0.5, 0.5); 16];
+        let scale = Exposure::Auto { key: 0.18 }.scale(&pixels);
+        assert!((scale * 0.5 - 0.18).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_white_balance_d65_is_neutral() {
+        let gains = Color::white_balance_gains(6504.0);
+        assert!((gains.x() - 1.0).abs() < 1e-9);
+        assert!((gains.y() - 1.0).abs() < 1e-9);
+        assert!((gains.z() - 1.0).abs() < 1e-9);
+
+        // 暖色光源需要压低红色
+        let gains = Color::white_balance_gains(3200.0);
+        assert!(gains.x() < gains.z());
+    }
+}
@@ -20,6 +20,18 @@ impl OrthonormalBasis {
         }
     }
 
+    // 以 tangent 在法线切平面上的投影为 u 轴，切线退化时与 new 相同
+    pub fn from_tangent(normal: &UnitVec3, tangent: &Vec3) -> OrthonormalBasis {
+        let Some(u) = UnitVec3::from_vec3(tangent - normal.dot(tangent) * normal.as_inner()) else {
+            return OrthonormalBasis::new(normal);
+        };
+        let w = UnitVec3::from_vec3_raw(Vec3::cross(&u, normal));
+
+        OrthonormalBasis {
+            axis: [u, *normal, w],
+        }
+    }
+
     pub fn u(&self) -> &UnitVec3 {
         &self.axis[0]
     }
@@ -81,6 +81,56 @@ impl Quaternion {
         Vec3::new(result.x, result.y, result.z)
     }
 
+    pub fn dot(self, rhs: Quaternion) -> f64 {
+        self.w * rhs.w + self.x * rhs.x + self.y * rhs.y + self.z * rhs.z
+    }
+
+    pub fn normalize(self) -> Quaternion {
+        let len = self.dot(self).sqrt();
+        Quaternion {
+            w: self.w / len,
+            x: self.x / len,
+            y: self.y / len,
+            z: self.z / len,
+        }
+    }
+
+    // 球面线性插值，总是沿较短的弧插值
+    pub fn slerp(self, rhs: Quaternion, t: f64) -> Quaternion {
+        let mut cos_theta = self.dot(rhs);
+        let rhs = if cos_theta < 0.0 {
+            cos_theta = -cos_theta;
+            Quaternion {
+                w: -rhs.w,
+                x: -rhs.x,
+                y: -rhs.y,
+                z: -rhs.z,
+            }
+        } else {
+            rhs
+        };
+
+        let (a, b) = if cos_theta > 0.9995 {
+            // 夹角过小时退化为线性插值
+            (1.0 - t, t)
+        } else {
+            let theta = cos_theta.acos();
+            let sin_theta = theta.sin();
+            (
+                ((1.0 - t) * theta).sin() / sin_theta,
+                (t * theta).sin() / sin_theta,
+            )
+        };
+
+        Quaternion {
+            w: a * self.w + b * rhs.w,
+            x: a * self.x + b * rhs.x,
+            y: a * self.y + b * rhs.y,
+            z: a * self.z + b * rhs.z,
+        }
+        .normalize()
+    }
+
     pub fn conjugate(self) -> Quaternion {
         Quaternion {
             w: self.w,
@@ -172,6 +222,20 @@ mod tests {
         assert_eq!(qc.z, -4.0);
     }
 
+    #[test]
+    fn test_slerp() {
+        let q0 = Quaternion::identity();
+        let q1 = Quaternion::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), 90.0);
+        let q = q0.slerp(q1, 0.5);
+        let expected = Quaternion::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), 45.0);
+        assert!(approx_eq(q.w, expected.w, 1e-10));
+        assert!(approx_eq(q.y, expected.y, 1e-10));
+
+        let q = q0.slerp(q1, 1.0);
+        assert!(approx_eq(q.w, q1.w, 1e-10));
+        assert!(approx_eq(q.y, q1.y, 1e-10));
+    }
+
     #[test]
     fn test_rotate_vector_identity() {
         let q = Quaternion::identity();
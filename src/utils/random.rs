@@ -1,27 +1,58 @@
-use std::ops::{Range, RangeInclusive};
+use std::{
+    cell::RefCell,
+    ops::{Range, RangeInclusive},
+    sync::atomic::{AtomicU64, Ordering},
+};
 
-use rand::rngs::ThreadRng;
+use rand::{Rng, SeedableRng, rngs::StdRng};
 
 pub struct Random;
 
+// 全局种子，每次调用 Random::seed 后各线程在下一次取随机数时重新播种
+static SEED: AtomicU64 = AtomicU64::new(0);
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+static STREAM: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    static RNG: RefCell<(u64, StdRng)> = RefCell::new((0, StdRng::from_os_rng()));
+}
+
 impl Random {
-    pub fn rng() -> ThreadRng {
-        rand::rng()
+    // 设置种子后，各线程使用由种子派生的互不相同的随机数序列
+    // 多线程调度不确定，因此只保证不同种子得到相互独立的样本，不保证结果可复现
+    pub fn seed(seed: u64) {
+        SEED.store(seed, Ordering::SeqCst);
+        STREAM.store(0, Ordering::SeqCst);
+        GENERATION.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn with_rng<T>(f: impl FnOnce(&mut StdRng) -> T) -> T {
+        RNG.with_borrow_mut(|(generation, rng)| {
+            let current = GENERATION.load(Ordering::Relaxed);
+            if *generation != current {
+                let stream = STREAM.fetch_add(1, Ordering::Relaxed);
+                let seed =
+                    SEED.load(Ordering::Relaxed) ^ stream.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+                *rng = StdRng::seed_from_u64(seed);
+                *generation = current;
+            }
+            f(rng)
+        })
     }
 
     pub fn f64() -> f64 {
-        rand::random()
+        Random::with_rng(|rng| rng.random())
     }
 
     pub fn random_range(interval: Range<f64>) -> f64 {
-        rand::random_range(interval)
+        Random::with_rng(|rng| rng.random_range(interval))
     }
 
     pub fn i32(interval: Range<i32>) -> i32 {
-        rand::random_range(interval)
+        Random::with_rng(|rng| rng.random_range(interval))
     }
 
     pub fn usize(interval: RangeInclusive<usize>) -> usize {
-        rand::random_range(interval)
+        Random::with_rng(|rng| rng.random_range(interval))
     }
 }
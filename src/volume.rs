@@ -4,9 +4,10 @@ use palette::num::ClampAssign;
 
 use crate::{
     hit::{HitRecord, Hittable},
-    material::Isotropic,
+    material::{Isotropic, subsurface::RandomWalk},
     texture::Texture,
     utils::{
+        color::Color,
         interval::Interval,
         random::Random,
         vec3::{UnitVec3, Vec3},
@@ -76,3 +77,71 @@ impl Hittable for ConstantMedium {
         self.boundary.bounding_box()
     }
 }
+
+// 封闭网格内部的随机游走次表面散射介质
+// 光线从外部击中时交由表面材质（如 subsurface > 0 的 Disney）决定是否进入，
+// 在内部时按最大消光系数采样碰撞距离，碰撞点使用 RandomWalk 材质
+pub struct SubsurfaceMedium {
+    boundary: Box<dyn Hittable>,
+    phase_function: RandomWalk,
+}
+
+impl SubsurfaceMedium {
+    pub fn new(
+        boundary: Box<dyn Hittable>,
+        albedo: Color,
+        mean_free_path: Color,
+    ) -> SubsurfaceMedium {
+        SubsurfaceMedium {
+            boundary,
+            phase_function: RandomWalk::new(albedo, mean_free_path),
+        }
+    }
+}
+
+impl Hittable for SubsurfaceMedium {
+    fn hit(
+        &self,
+        r: &crate::utils::ray::Ray,
+        interval: &crate::utils::interval::Interval,
+    ) -> Option<crate::hit::HitRecord> {
+        let rec = self.boundary.hit(r, interval)?;
+
+        if rec.front_face {
+            return Some(rec);
+        }
+
+        let ray_length = r.direction().length();
+        let hit_distance = -Random::f64().ln() / self.phase_function.majorant();
+        let t = interval.min().max(0.0) + hit_distance / ray_length;
+
+        if t >= rec.t {
+            return Some(rec);
+        }
+
+        let p = r.at(t);
+        let normal = UnitVec3::from_vec3_raw(Vec3::new(1.0, 0.0, 0.0));
+
+        Some(HitRecord::new(
+            p,
+            normal,
+            &self.phase_function,
+            t,
+            0.0,
+            0.0,
+            r,
+        ))
+    }
+
+    fn bounding_box(&self) -> &crate::aabb::AABB {
+        self.boundary.bounding_box()
+    }
+
+    fn pdf_value(&self, origin: &crate::utils::vec3::Point3, direction: &Vec3, time: f64) -> f64 {
+        self.boundary.pdf_value(origin, direction, time)
+    }
+
+    fn random(&self, origin: &crate::utils::vec3::Point3, time: f64) -> UnitVec3 {
+        self.boundary.random(origin, time)
+    }
+}